@@ -0,0 +1,504 @@
+//! Progress bars for the CLI, drawn from the library's progress events.
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use snapsync::{ProgressEvent, ProgressObserver};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Layout of the merge, extract and stream bars.
+const TEMPLATE: &str =
+    "{spinner:.cyan} [{bar:40.cyan/blue}] {pos}/{len} {msg} | {elapsed_precise} elapsed, ETA {eta_precise}";
+
+/// Layout of the overall download bar.
+const DOWNLOAD_TEMPLATE: &str =
+    "{spinner:.cyan} [{bar:40.cyan/blue}] {binary_bytes}/{binary_total_bytes} ({binary_bytes_per_sec}) {msg} | {elapsed_precise} elapsed, ETA {eta_precise}";
+
+/// Layout of the per-shard download bars.
+const SHARD_DOWNLOAD_TEMPLATE: &str =
+    "  {prefix:>9} [{bar:40.cyan/blue}] {binary_bytes}/{binary_total_bytes} ({binary_bytes_per_sec}) {msg}";
+
+/// Renders progress events as indicatif bars: a byte-based download bar with
+/// one sub-bar per shard, and one bar per shard for merging, extracting or
+/// streaming.
+#[derive(Debug)]
+pub struct IndicatifProgress {
+    multi: MultiProgress,
+    bars: Mutex<Bars>,
+}
+
+#[derive(Debug, Default)]
+struct Bars {
+    download: Option<DownloadBars>,
+    /// Number of chunks per shard, from the fetched metadata.
+    chunk_counts: BTreeMap<u32, usize>,
+    shards: HashMap<u32, ShardBars>,
+}
+
+#[derive(Debug, Default)]
+struct ShardBars {
+    merge: Option<ProgressBar>,
+    extract: Option<ProgressBar>,
+    stream: Option<ProgressBar>,
+    written: u64,
+    skipped: u64,
+}
+
+/// Byte accounting for the download stage.
+///
+/// The bars only count bytes transferred in this run: verified chunks and
+/// bytes resumed from earlier runs are left out, so throughput and ETA reflect
+/// the work that remains. Chunks whose size is not known yet are estimated at
+/// the average size of the known ones.
+#[derive(Debug)]
+struct DownloadBars {
+    total: ProgressBar,
+    shards: BTreeMap<u32, ShardDownload>,
+    total_chunks: usize,
+    completed_chunks: usize,
+    verified_chunks: usize,
+    activity: String,
+}
+
+#[derive(Debug)]
+struct ShardDownload {
+    /// Only drawn when more than one shard is downloaded.
+    bar: Option<ProgressBar>,
+    chunks: usize,
+    completed: usize,
+    sizes: HashMap<String, ChunkBytes>,
+}
+
+#[derive(Debug, Default)]
+struct ChunkBytes {
+    size: Option<u64>,
+    /// Bytes that were already on disk when the first transfer of this run began.
+    resumed: Option<u64>,
+    /// Bytes currently on disk.
+    on_disk: u64,
+    verified: bool,
+}
+
+impl ChunkBytes {
+    /// Bytes of this chunk counted as transferred in this run.
+    fn transferred(&self) -> u64 {
+        self.on_disk.saturating_sub(self.resumed.unwrap_or(0))
+    }
+}
+
+impl ShardDownload {
+    /// Expected number of bytes to transfer for this shard.
+    fn expected_bytes(&self) -> u64 {
+        let known: Vec<&ChunkBytes> = self.sizes.values().filter(|c| c.size.is_some()).collect();
+        let known_total: u64 = known.iter().filter_map(|c| c.size).sum();
+        let average = known_total.checked_div(known.len() as u64).unwrap_or(0);
+        let unknown = self.chunks.saturating_sub(known.len()) as u64;
+
+        known
+            .iter()
+            .filter(|c| !c.verified)
+            .map(|c| c.size.unwrap_or(0).saturating_sub(c.resumed.unwrap_or(0)))
+            .sum::<u64>()
+            + unknown * average
+    }
+}
+
+impl DownloadBars {
+    fn chunk(
+        &mut self,
+        shard_id: u32,
+        chunk: &str,
+    ) -> Option<(&mut ChunkBytes, Option<&ProgressBar>)> {
+        let shard = self.shards.get_mut(&shard_id)?;
+        let bar = shard.bar.as_ref();
+        Some((shard.sizes.entry(chunk.to_string()).or_default(), bar))
+    }
+
+    /// Recomputes the bar lengths after a chunk size changed.
+    fn resize(&self) {
+        let mut total = 0;
+        for shard in self.shards.values() {
+            let expected = shard.expected_bytes();
+            if let Some(ref bar) = shard.bar {
+                bar.set_length(expected);
+            }
+            total += expected;
+        }
+        self.total.set_length(total);
+    }
+
+    /// Moves a chunk's transferred bytes from `old` to `new`; less when a transfer restarts.
+    fn advance(&self, shard_bar: Option<&ProgressBar>, old: u64, new: u64) {
+        for bar in shard_bar.into_iter().chain([&self.total]) {
+            if new >= old {
+                bar.inc(new - old);
+            } else {
+                bar.set_position(bar.position().saturating_sub(old - new));
+            }
+        }
+    }
+
+    fn complete_chunk(&mut self, shard_id: u32, verified: bool) {
+        self.completed_chunks += 1;
+        if verified {
+            self.verified_chunks += 1;
+        }
+        if let Some(shard) = self.shards.get_mut(&shard_id) {
+            shard.completed += 1;
+            if shard.completed == shard.chunks {
+                if let Some(ref bar) = shard.bar {
+                    bar.finish_with_message("✅");
+                }
+            }
+        }
+        self.refresh_message();
+    }
+
+    fn set_activity(&mut self, activity: String) {
+        self.activity = activity;
+        self.refresh_message();
+    }
+
+    fn refresh_message(&self) {
+        self.total.set_message(format!(
+            "| {}/{} chunks ({} verified) | {}",
+            self.completed_chunks, self.total_chunks, self.verified_chunks, self.activity
+        ));
+    }
+}
+
+impl Bars {
+    /// Whether chunk events for a shard belong to its streaming bar.
+    fn is_streaming(&self, shard_id: u32) -> bool {
+        self.shards
+            .get(&shard_id)
+            .is_some_and(|shard| shard.stream.is_some())
+    }
+
+    fn stream_bar(&self, shard_id: u32) -> Option<&ProgressBar> {
+        self.shards.get(&shard_id).and_then(|s| s.stream.as_ref())
+    }
+}
+
+impl IndicatifProgress {
+    pub fn new() -> Self {
+        Self {
+            multi: MultiProgress::new(),
+            bars: Mutex::new(Bars::default()),
+        }
+    }
+
+    fn add_bar(&self, len: u64, template: &str) -> ProgressBar {
+        let bar = self.multi.add(ProgressBar::new(len));
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template(template)
+                .unwrap()
+                .progress_chars("█▓▒░ "),
+        );
+        bar
+    }
+
+    fn start_download(&self, bars: &mut Bars, total_chunks: usize) {
+        let total = self.add_bar(0, DOWNLOAD_TEMPLATE);
+        let per_shard_bars = bars.chunk_counts.len() > 1;
+        let shards = bars
+            .chunk_counts
+            .iter()
+            .map(|(&shard_id, &chunks)| {
+                let bar = per_shard_bars.then(|| {
+                    let bar = self.add_bar(0, SHARD_DOWNLOAD_TEMPLATE);
+                    bar.set_prefix(format!("shard {}", shard_id));
+                    bar
+                });
+                let shard = ShardDownload {
+                    bar,
+                    chunks,
+                    completed: 0,
+                    sizes: HashMap::new(),
+                };
+                (shard_id, shard)
+            })
+            .collect();
+
+        let download = DownloadBars {
+            total,
+            shards,
+            total_chunks,
+            completed_chunks: 0,
+            verified_chunks: 0,
+            activity: format!("📦 Downloading from {} shard(s)", bars.chunk_counts.len()),
+        };
+        download.refresh_message();
+        bars.download = Some(download);
+    }
+
+    /// Handles chunk events of the download stage.
+    fn on_download_event(&self, download: &mut DownloadBars, event: &ProgressEvent) {
+        match event {
+            ProgressEvent::ChunkVerified {
+                shard_id,
+                chunk,
+                size,
+            } => {
+                if let Some((bytes, _)) = download.chunk(*shard_id, chunk) {
+                    bytes.size = Some(*size);
+                    bytes.verified = true;
+                }
+                download.resize();
+                download.complete_chunk(*shard_id, true);
+            }
+            ProgressEvent::ChunkSized {
+                shard_id,
+                chunk,
+                size,
+            } => {
+                if let Some((bytes, _)) = download.chunk(*shard_id, chunk) {
+                    bytes.size = Some(*size);
+                }
+                download.resize();
+            }
+            ProgressEvent::ChunkStarted { chunk, .. } => {
+                download.set_activity(format!("⬇️  Downloading: {}", chunk));
+            }
+            ProgressEvent::ChunkTransferStarted {
+                shard_id,
+                chunk,
+                offset,
+            } => {
+                let Some((bytes, shard_bar)) = download.chunk(*shard_id, chunk) else {
+                    return;
+                };
+                let shard_bar = shard_bar.cloned();
+                let first_transfer = bytes.resumed.is_none();
+                let old = bytes.transferred();
+                bytes.resumed.get_or_insert(*offset);
+                bytes.on_disk = *offset;
+                let new = bytes.transferred();
+                download.advance(shard_bar.as_ref(), old, new);
+                if first_transfer {
+                    download.resize();
+                }
+            }
+            ProgressEvent::ChunkBytes {
+                shard_id,
+                chunk,
+                bytes: received,
+            } => {
+                let Some((bytes, shard_bar)) = download.chunk(*shard_id, chunk) else {
+                    return;
+                };
+                bytes.on_disk += received;
+                if let Some(bar) = shard_bar {
+                    bar.inc(*received);
+                }
+                download.total.inc(*received);
+            }
+            ProgressEvent::ChunkFinished { shard_id, .. } => {
+                download.complete_chunk(*shard_id, false);
+            }
+            ProgressEvent::ChunkFailed { shard_id, .. } => {
+                if let Some(bar) = download.shards.get(shard_id).and_then(|s| s.bar.as_ref()) {
+                    bar.abandon_with_message("❌");
+                }
+                download.total.finish_with_message("❌ Download failed!");
+            }
+            _ => {}
+        }
+    }
+}
+
+impl ProgressObserver for IndicatifProgress {
+    fn on_event(&self, event: &ProgressEvent) {
+        let mut bars = self.bars.lock().unwrap();
+        match event {
+            ProgressEvent::MetadataFetched {
+                shard_id, chunks, ..
+            } => {
+                bars.chunk_counts.insert(*shard_id, *chunks);
+            }
+            ProgressEvent::DownloadStarted { total_chunks, .. } => {
+                self.start_download(&mut bars, *total_chunks);
+            }
+            ProgressEvent::ChunkVerified {
+                shard_id, chunk, ..
+            } if bars.is_streaming(*shard_id) => {
+                if let Some(bar) = bars.stream_bar(*shard_id) {
+                    bar.set_message(format!("| ✅ Verified: {}", chunk));
+                }
+            }
+            ProgressEvent::ChunkStarted { shard_id, chunk } if bars.is_streaming(*shard_id) => {
+                if let Some(bar) = bars.stream_bar(*shard_id) {
+                    bar.set_message(format!("| ⬇️  Downloading: {}", chunk));
+                }
+            }
+            ProgressEvent::ChunkVerified { .. }
+            | ProgressEvent::ChunkSized { .. }
+            | ProgressEvent::ChunkStarted { .. }
+            | ProgressEvent::ChunkTransferStarted { .. }
+            | ProgressEvent::ChunkBytes { .. }
+            | ProgressEvent::ChunkFinished { .. }
+            | ProgressEvent::ChunkFailed { .. } => {
+                if let Some(ref mut download) = bars.download {
+                    self.on_download_event(download, event);
+                }
+            }
+            ProgressEvent::MergeStarted {
+                shard_id,
+                total_chunks,
+                resumed_chunks,
+            } => {
+                let bar = self.add_bar(*total_chunks as u64, TEMPLATE);
+                bar.set_message(format!("🔄 Merging shard {} chunks", shard_id));
+                bar.set_position(*resumed_chunks as u64);
+                bars.shards.entry(*shard_id).or_default().merge = Some(bar);
+            }
+            ProgressEvent::MergeProgress {
+                shard_id,
+                chunk,
+                merged_chunks,
+                total_chunks,
+            } => {
+                if let Some(bar) = bars.shards.get(shard_id).and_then(|s| s.merge.as_ref()) {
+                    bar.set_position(*merged_chunks as u64);
+                    bar.set_message(format!(
+                        "| 🔄 Merged: {}/{} | {}",
+                        merged_chunks, total_chunks, chunk
+                    ));
+                }
+            }
+            ProgressEvent::MergeFinished {
+                shard_id,
+                total_chunks,
+            } => {
+                if let Some(bar) = bars.shards.get(shard_id).and_then(|s| s.merge.as_ref()) {
+                    bar.finish_with_message(format!(
+                        "✅ Merged {} chunks for shard {}",
+                        total_chunks, shard_id
+                    ));
+                }
+            }
+            ProgressEvent::ExtractStarted {
+                shard_id,
+                estimated_entries,
+            } => {
+                let bar = self.add_bar(*estimated_entries, TEMPLATE);
+                bar.set_message(format!("📂 Extracting shard {}", shard_id));
+                let shard = bars.shards.entry(*shard_id).or_default();
+                shard.extract = Some(bar);
+                shard.written = 0;
+                shard.skipped = 0;
+            }
+            ProgressEvent::ExtractEntryWritten { shard_id, path, .. }
+            | ProgressEvent::ExtractEntrySkipped { shard_id, path, .. } => {
+                let Some(shard) = bars.shards.get_mut(shard_id) else {
+                    return;
+                };
+                if matches!(event, ProgressEvent::ExtractEntryWritten { .. }) {
+                    shard.written += 1;
+                } else {
+                    shard.skipped += 1;
+                }
+                let count = shard.written + shard.skipped;
+
+                if let Some(ref bar) = shard.extract {
+                    bar.set_position(count);
+                    // Update the message often at the beginning, then every 100 entries
+                    if count <= 10 || count.is_multiple_of(100) {
+                        let name = Path::new(path)
+                            .file_name()
+                            .map_or_else(|| path.clone(), |n| n.to_string_lossy().to_string());
+                        bar.set_message(format!(
+                            "| 📂 {} new, {} skipped | {}",
+                            shard.written, shard.skipped, name
+                        ));
+                    }
+                } else if let Some(ref bar) = shard.stream {
+                    if count.is_multiple_of(100) {
+                        bar.set_message(format!("| 📂 {} entries written | {}", count, path));
+                    }
+                }
+            }
+            ProgressEvent::ExtractFinished {
+                shard_id,
+                written,
+                skipped,
+            } => {
+                if let Some(bar) = bars.shards.get(shard_id).and_then(|s| s.extract.as_ref()) {
+                    bar.finish_with_message(format!(
+                        "✅ Extracted shard {} ({} new, {} skipped)",
+                        shard_id, written, skipped
+                    ));
+                }
+            }
+            ProgressEvent::StreamStarted {
+                shard_id,
+                total_chunks,
+                resumed_chunks,
+            } => {
+                let bar = self.add_bar(*total_chunks as u64, TEMPLATE);
+                bar.set_message(format!("🌊 Streaming shard {}", shard_id));
+                bar.set_position(*resumed_chunks as u64);
+                let shard = bars.shards.entry(*shard_id).or_default();
+                shard.stream = Some(bar);
+                shard.written = 0;
+            }
+            ProgressEvent::StreamChunkUnpacked { shard_id, .. } => {
+                if let Some(bar) = bars.stream_bar(*shard_id) {
+                    bar.inc(1);
+                }
+            }
+            ProgressEvent::StreamFinished {
+                shard_id,
+                entries_written,
+            } => {
+                if let Some(bar) = bars.stream_bar(*shard_id) {
+                    bar.finish_with_message(format!(
+                        "✅ Streamed shard {} ({} entries written)",
+                        shard_id, entries_written
+                    ));
+                }
+            }
+            ProgressEvent::ShardFailed { shard_id, .. } => {
+                if let Some(shard) = bars.shards.get(shard_id) {
+                    for bar in [&shard.merge, &shard.extract, &shard.stream]
+                        .into_iter()
+                        .flatten()
+                        .filter(|bar| !bar.is_finished())
+                    {
+                        bar.finish_with_message(format!("❌ Shard {} failed", shard_id));
+                    }
+                }
+            }
+            ProgressEvent::Cancelled => {
+                let shard_bars = bars
+                    .shards
+                    .values()
+                    .flat_map(|shard| [&shard.merge, &shard.extract, &shard.stream]);
+                let download_bars = bars.download.iter().flat_map(|download| {
+                    std::iter::once(&download.total).chain(
+                        download
+                            .shards
+                            .values()
+                            .filter_map(|shard| shard.bar.as_ref()),
+                    )
+                });
+                for bar in shard_bars
+                    .flatten()
+                    .chain(download_bars)
+                    .filter(|bar| !bar.is_finished())
+                {
+                    bar.abandon_with_message("⏹️  Cancelled");
+                }
+            }
+            ProgressEvent::Finished => {
+                if let Some(ref download) = bars.download {
+                    download.total.finish_with_message(
+                        "✅ All snapshots downloaded and extracted successfully!",
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! Fixed or adaptive limit on the number of chunks downloaded at once.
+
+use crate::error::SnapshotError;
+use reqwest::StatusCode;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::info;
+
+/// How often the adaptive controller re-evaluates the concurrency level.
+const ADJUST_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Minimum relative throughput gain for an increase to count as useful.
+const MIN_THROUGHPUT_GAIN: f64 = 0.05;
+
+/// Bounds for adaptive download concurrency.
+///
+/// The number of in-flight chunk downloads starts at
+/// `max_concurrent_downloads` (clamped to the bounds), grows by one while
+/// aggregate throughput keeps improving, and is halved when the source
+/// throttles (HTTP 429/503) or downloads fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveConcurrency {
+    /// Lowest number of concurrent downloads.
+    pub min: usize,
+    /// Highest number of concurrent downloads.
+    pub max: usize,
+}
+
+impl Default for AdaptiveConcurrency {
+    fn default() -> Self {
+        Self { min: 1, max: 32 }
+    }
+}
+
+/// Measurements for the current adjustment window.
+#[derive(Debug)]
+struct Controller {
+    bounds: AdaptiveConcurrency,
+    level: usize,
+    /// Permits to drop instead of returning, after a decrease while they were in use.
+    excess: usize,
+    window_start: Instant,
+    window_bytes: u64,
+    window_failures: u32,
+    window_throttled: u32,
+    /// Throughput of the previous window and whether the level was raised after it.
+    previous: Option<(f64, bool)>,
+}
+
+/// Hands out download slots, optionally adapting their number AIMD-style.
+#[derive(Debug)]
+pub(crate) struct ConcurrencyController {
+    semaphore: Arc<Semaphore>,
+    controller: Option<Mutex<Controller>>,
+    max_level: usize,
+}
+
+/// A download slot, returned to the controller when dropped.
+pub(crate) struct DownloadPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    controller: Arc<ConcurrencyController>,
+}
+
+impl Drop for DownloadPermit {
+    fn drop(&mut self) {
+        let Some(permit) = self.permit.take() else {
+            return;
+        };
+        if let Some(ref controller) = self.controller.controller {
+            let mut controller = controller.lock().unwrap();
+            if controller.excess > 0 {
+                controller.excess -= 1;
+                permit.forget();
+            }
+        }
+    }
+}
+
+impl ConcurrencyController {
+    /// Creates a controller with `initial` slots, adapting within `adaptive` if set.
+    pub(crate) fn new(initial: usize, adaptive: Option<AdaptiveConcurrency>) -> Self {
+        match adaptive {
+            None => Self {
+                semaphore: Arc::new(Semaphore::new(initial.max(1))),
+                controller: None,
+                max_level: initial.max(1),
+            },
+            Some(bounds) => {
+                let min = bounds.min.max(1);
+                let bounds = AdaptiveConcurrency {
+                    min,
+                    max: bounds.max.max(min),
+                };
+                let level = initial.clamp(bounds.min, bounds.max);
+                info!(
+                    "⚙️  Adaptive download concurrency: starting at {} (bounds {}-{})",
+                    level, bounds.min, bounds.max
+                );
+                Self {
+                    semaphore: Arc::new(Semaphore::new(level)),
+                    controller: Some(Mutex::new(Controller {
+                        bounds,
+                        level,
+                        excess: 0,
+                        window_start: Instant::now(),
+                        window_bytes: 0,
+                        window_failures: 0,
+                        window_throttled: 0,
+                        previous: None,
+                    })),
+                    max_level: bounds.max,
+                }
+            }
+        }
+    }
+
+    /// Highest number of downloads that may ever run at once.
+    pub(crate) fn max_level(&self) -> usize {
+        self.max_level
+    }
+
+    /// Waits for a free download slot.
+    pub(crate) async fn acquire(self: &Arc<Self>) -> DownloadPermit {
+        let permit = Arc::clone(&self.semaphore)
+            .acquire_owned()
+            .await
+            .expect("download semaphore is never closed");
+        DownloadPermit {
+            permit: Some(permit),
+            controller: Arc::clone(self),
+        }
+    }
+
+    /// Records downloaded bytes.
+    pub(crate) fn record_bytes(&self, bytes: u64) {
+        if let Some(ref controller) = self.controller {
+            let mut controller = controller.lock().unwrap();
+            controller.window_bytes += bytes;
+            self.maybe_adjust(&mut controller);
+        }
+    }
+
+    /// Records a failed download attempt.
+    pub(crate) fn record_failure(&self, error: &SnapshotError) {
+        if let Some(ref controller) = self.controller {
+            let mut controller = controller.lock().unwrap();
+            if is_throttled(error) {
+                controller.window_throttled += 1;
+            } else {
+                controller.window_failures += 1;
+            }
+            self.maybe_adjust(&mut controller);
+        }
+    }
+
+    /// Re-evaluates the level once per [`ADJUST_INTERVAL`].
+    fn maybe_adjust(&self, controller: &mut Controller) {
+        let elapsed = controller.window_start.elapsed();
+        if elapsed < ADJUST_INTERVAL {
+            return;
+        }
+        let throughput = controller.window_bytes as f64 / elapsed.as_secs_f64();
+        let (throttled, failures) = (controller.window_throttled, controller.window_failures);
+        let old_level = controller.level;
+        let saturated = self.semaphore.available_permits() == 0;
+
+        let mut raised = false;
+        let new_level = if throttled > 0 || failures > 0 {
+            // Multiplicative decrease on congestion
+            (old_level / 2).max(controller.bounds.min)
+        } else {
+            match controller.previous {
+                // The last increase did not pay off, step back
+                Some((previous, true)) if throughput < previous * (1.0 + MIN_THROUGHPUT_GAIN) => {
+                    old_level.saturating_sub(1).max(controller.bounds.min)
+                }
+                // Additive increase, only if all slots are actually in use
+                _ if saturated && old_level < controller.bounds.max => {
+                    raised = true;
+                    old_level + 1
+                }
+                _ => old_level,
+            }
+        };
+
+        if new_level > old_level {
+            let mut grow = new_level - old_level;
+            let repaid = grow.min(controller.excess);
+            controller.excess -= repaid;
+            grow -= repaid;
+            self.semaphore.add_permits(grow);
+        } else if new_level < old_level {
+            let shrink = old_level - new_level;
+            let forgotten = self.semaphore.forget_permits(shrink);
+            controller.excess += shrink - forgotten;
+        }
+
+        if new_level != old_level {
+            info!(
+                "⚙️  Download concurrency {} → {} ({}/s, {} throttled, {} failed)",
+                old_level,
+                new_level,
+                indicatif::HumanBytes(throughput as u64),
+                throttled,
+                failures
+            );
+        }
+
+        controller.level = new_level;
+        controller.previous = Some((throughput, raised));
+        controller.window_start = Instant::now();
+        controller.window_bytes = 0;
+        controller.window_failures = 0;
+        controller.window_throttled = 0;
+    }
+}
+
+/// Returns true for responses asking us to slow down.
+fn is_throttled(error: &SnapshotError) -> bool {
+    match error {
+        SnapshotError::HttpStatus { status, .. } => {
+            *status == StatusCode::TOO_MANY_REQUESTS.as_u16()
+                || *status == StatusCode::SERVICE_UNAVAILABLE.as_u16()
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expire_window(controller: &ConcurrencyController) {
+        let mut state = controller.controller.as_ref().unwrap().lock().unwrap();
+        state.window_start -= ADJUST_INTERVAL;
+    }
+
+    fn level(controller: &ConcurrencyController) -> usize {
+        controller
+            .controller
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .level
+    }
+
+    #[tokio::test]
+    async fn test_aimd_adjustments() {
+        let controller = Arc::new(ConcurrencyController::new(
+            2,
+            Some(AdaptiveConcurrency { min: 1, max: 4 }),
+        ));
+
+        // All slots busy and throughput flowing: grow by one
+        let first = controller.acquire().await;
+        let second = controller.acquire().await;
+        expire_window(&controller);
+        controller.record_bytes(1_000_000);
+        assert_eq!(level(&controller), 3);
+        assert_eq!(controller.semaphore.available_permits(), 1);
+
+        // A failure halves the level; busy permits are dropped when released
+        let third = controller.acquire().await;
+        expire_window(&controller);
+        controller.record_failure(&SnapshotError::DownloadFailed("boom".to_string()));
+        assert_eq!(level(&controller), 1);
+        drop((first, second, third));
+        assert_eq!(controller.semaphore.available_permits(), 1);
+    }
+}
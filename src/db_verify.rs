@@ -0,0 +1,574 @@
+//! Consistency check of a restored RocksDB directory.
+//!
+//! `CURRENT` names the live MANIFEST, a log of `VersionEdit` records that add
+//! and remove table files. Replaying it yields the set of live SST files with
+//! their sizes, which is compared with the files on disk; every SST footer is
+//! checked for the RocksDB magic number.
+
+use crate::error::SnapshotError;
+use crate::sst_verify::verify_sst_magic_number;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Size of the blocks a RocksDB log file is written in.
+const LOG_BLOCK_SIZE: usize = 32 * 1024;
+/// Log record header: checksum (4), length (2), type (1).
+const LOG_HEADER_SIZE: usize = 7;
+/// Header of records in recycled log files, which adds the log number (4).
+const RECYCLABLE_LOG_HEADER_SIZE: usize = 11;
+/// Offset added to CRC32C checksums stored by RocksDB.
+const CRC_MASK_DELTA: u32 = 0xa282_ead8;
+
+/// `VersionEdit` field tags, as defined in RocksDB's `version_edit.h`.
+const TAG_COMPARATOR: u32 = 1;
+const TAG_LOG_NUMBER: u32 = 2;
+const TAG_NEXT_FILE_NUMBER: u32 = 3;
+const TAG_LAST_SEQUENCE: u32 = 4;
+const TAG_COMPACT_CURSOR: u32 = 5;
+const TAG_DELETED_FILE: u32 = 6;
+const TAG_NEW_FILE: u32 = 7;
+const TAG_PREV_LOG_NUMBER: u32 = 9;
+const TAG_MIN_LOG_NUMBER_TO_KEEP: u32 = 10;
+const TAG_NEW_FILE2: u32 = 100;
+const TAG_NEW_FILE3: u32 = 102;
+const TAG_NEW_FILE4: u32 = 103;
+const TAG_COLUMN_FAMILY: u32 = 200;
+const TAG_COLUMN_FAMILY_ADD: u32 = 201;
+const TAG_COLUMN_FAMILY_DROP: u32 = 202;
+const TAG_MAX_COLUMN_FAMILY: u32 = 203;
+const TAG_IN_ATOMIC_GROUP: u32 = 300;
+const TAG_BLOB_FILE_ADDITION: u32 = 400;
+const TAG_BLOB_FILE_GARBAGE: u32 = 401;
+/// Tags with this bit set are followed by a length-prefixed payload that may be ignored.
+const TAG_SAFE_IGNORE_MASK: u32 = 1 << 13;
+/// Ends the custom fields of a `kNewFile4` record.
+const NEW_FILE4_TERMINATE: u32 = 1;
+/// Ends the custom fields of blob file records.
+const BLOB_FIELDS_END: u32 = 0;
+
+/// A problem found in a RocksDB directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DbIssue {
+    /// A file referenced by `CURRENT` or the MANIFEST doesn't exist.
+    Missing {
+        /// Path of the missing file.
+        path: PathBuf,
+    },
+    /// An SST file that the MANIFEST doesn't reference.
+    Extra {
+        /// Path of the unreferenced file.
+        path: PathBuf,
+    },
+    /// A file that exists but is damaged.
+    Corrupt {
+        /// Path of the damaged file.
+        path: PathBuf,
+        /// What is wrong with it.
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for DbIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbIssue::Missing { path } => write!(f, "missing: {}", path.display()),
+            DbIssue::Extra { path } => write!(f, "extra: {}", path.display()),
+            DbIssue::Corrupt { path, reason } => {
+                write!(f, "corrupt: {} ({})", path.display(), reason)
+            }
+        }
+    }
+}
+
+/// Outcome of [`verify_db`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DbReport {
+    /// RocksDB directories that were checked.
+    pub databases: Vec<PathBuf>,
+    /// Number of SST files checked.
+    pub files_checked: usize,
+    /// Problems found, in the order they were found.
+    pub issues: Vec<DbIssue>,
+}
+
+impl DbReport {
+    /// Returns true if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Verifies a restored RocksDB directory.
+///
+/// `db_dir` is either a RocksDB directory or a directory of them, such as the
+/// `shard-N` directories of a restore. For each database:
+///
+/// - `CURRENT` must name an existing MANIFEST
+/// - the MANIFEST must be readable, with intact record checksums
+/// - every SST file it references must exist with the recorded size
+/// - every SST file must end with the RocksDB magic number
+/// - SST files it doesn't reference are reported as extra
+///
+/// # Arguments
+///
+/// * `db_dir` - Directory to verify
+///
+/// # Returns
+///
+/// A report of the problems found, or an error if a directory can't be read.
+pub fn verify_db(db_dir: impl AsRef<Path>) -> Result<DbReport, SnapshotError> {
+    let db_dir = db_dir.as_ref();
+    let mut report = DbReport::default();
+    let databases = if is_database(db_dir)? {
+        vec![db_dir.to_path_buf()]
+    } else {
+        let mut databases = Vec::new();
+        for entry in std::fs::read_dir(db_dir)? {
+            let path = entry?.path();
+            if path.is_dir() && is_database(&path)? {
+                databases.push(path);
+            }
+        }
+        databases.sort();
+        databases
+    };
+
+    if databases.is_empty() {
+        report.issues.push(DbIssue::Missing {
+            path: db_dir.join("CURRENT"),
+        });
+    }
+    for database in databases {
+        verify_database(&database, &mut report)?;
+        report.databases.push(database);
+    }
+    Ok(report)
+}
+
+/// Returns true if a directory holds RocksDB files.
+fn is_database(dir: &Path) -> Result<bool, SnapshotError> {
+    for entry in std::fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        if name == "CURRENT" || name.starts_with("MANIFEST-") || name.ends_with(".sst") {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Checks one RocksDB directory, adding its problems to the report.
+fn verify_database(dir: &Path, report: &mut DbReport) -> Result<(), SnapshotError> {
+    let mut sst_files: BTreeMap<u64, PathBuf> = BTreeMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let number = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".sst"))
+            .and_then(|number| number.parse().ok());
+        if let Some(number) = number {
+            sst_files.insert(number, path);
+        }
+    }
+
+    // Without a readable MANIFEST only the SST footers can be checked
+    let live = read_live_files(dir, report);
+    report.files_checked += sst_files.len();
+    if let Some(ref live) = live {
+        for (number, size) in live {
+            let path = dir.join(format!("{:06}.sst", number));
+            let actual = match std::fs::metadata(&path) {
+                Ok(metadata) => metadata.len(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    report.issues.push(DbIssue::Missing { path });
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if actual != *size {
+                report.issues.push(DbIssue::Corrupt {
+                    path,
+                    reason: format!("{} bytes, the MANIFEST records {}", actual, size),
+                });
+                sst_files.remove(number);
+            }
+        }
+    }
+
+    for (number, path) in sst_files {
+        if !verify_sst_magic_number(path.to_str().unwrap_or_default())? {
+            report.issues.push(DbIssue::Corrupt {
+                path,
+                reason: "invalid SST footer magic number".to_string(),
+            });
+        } else if live
+            .as_ref()
+            .is_some_and(|live| !live.contains_key(&number))
+        {
+            report.issues.push(DbIssue::Extra { path });
+        }
+    }
+    Ok(())
+}
+
+/// Reads the live SST files (number and size) from the MANIFEST named by `CURRENT`.
+///
+/// Problems with `CURRENT` or the MANIFEST are added to the report and yield `None`.
+fn read_live_files(dir: &Path, report: &mut DbReport) -> Option<BTreeMap<u64, u64>> {
+    let current = dir.join("CURRENT");
+    let content = match std::fs::read_to_string(&current) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            report.issues.push(DbIssue::Missing { path: current });
+            return None;
+        }
+        Err(e) => {
+            report.issues.push(DbIssue::Corrupt {
+                path: current,
+                reason: e.to_string(),
+            });
+            return None;
+        }
+    };
+    let manifest_name = content
+        .strip_suffix('\n')
+        .filter(|name| name.starts_with("MANIFEST-") && !name.contains('/'));
+    let Some(manifest_name) = manifest_name else {
+        report.issues.push(DbIssue::Corrupt {
+            path: current,
+            reason: format!("expected a MANIFEST file name, found {:?}", content),
+        });
+        return None;
+    };
+
+    let manifest = dir.join(manifest_name);
+    let data = match std::fs::read(&manifest) {
+        Ok(data) => data,
+        Err(e) => {
+            report
+                .issues
+                .push(if e.kind() == std::io::ErrorKind::NotFound {
+                    DbIssue::Missing { path: manifest }
+                } else {
+                    DbIssue::Corrupt {
+                        path: manifest,
+                        reason: e.to_string(),
+                    }
+                });
+            return None;
+        }
+    };
+    match replay_manifest(&data) {
+        Ok(live) => Some(live),
+        Err(reason) => {
+            report.issues.push(DbIssue::Corrupt {
+                path: manifest,
+                reason,
+            });
+            None
+        }
+    }
+}
+
+/// Replays the version edits of a MANIFEST, returning the live SST files and their sizes.
+fn replay_manifest(data: &[u8]) -> Result<BTreeMap<u64, u64>, String> {
+    // File number -> (column family, size)
+    let mut live: BTreeMap<u64, (u32, u64)> = BTreeMap::new();
+    for record in log_records(data)? {
+        let mut edit = Decoder(&record);
+        let mut column_family = 0;
+        let mut dropped = false;
+        let mut deleted = Vec::new();
+        let mut added = Vec::new();
+        while !edit.0.is_empty() {
+            match edit.varint32()? {
+                TAG_COMPARATOR | TAG_COLUMN_FAMILY_ADD => {
+                    edit.slice()?;
+                }
+                TAG_LOG_NUMBER
+                | TAG_NEXT_FILE_NUMBER
+                | TAG_LAST_SEQUENCE
+                | TAG_PREV_LOG_NUMBER
+                | TAG_MIN_LOG_NUMBER_TO_KEEP => {
+                    edit.varint64()?;
+                }
+                TAG_COMPACT_CURSOR => {
+                    edit.varint32()?;
+                    edit.slice()?;
+                }
+                TAG_DELETED_FILE => {
+                    edit.varint32()?;
+                    deleted.push(edit.varint64()?);
+                }
+                tag @ (TAG_NEW_FILE | TAG_NEW_FILE2 | TAG_NEW_FILE3 | TAG_NEW_FILE4) => {
+                    edit.varint32()?; // level
+                    let number = edit.varint64()?;
+                    if tag == TAG_NEW_FILE3 {
+                        edit.varint32()?; // path id
+                    }
+                    let size = edit.varint64()?;
+                    edit.slice()?; // smallest key
+                    edit.slice()?; // largest key
+                    if tag != TAG_NEW_FILE {
+                        edit.varint64()?; // smallest sequence number
+                        edit.varint64()?; // largest sequence number
+                    }
+                    if tag == TAG_NEW_FILE4 {
+                        edit.custom_fields(NEW_FILE4_TERMINATE)?;
+                    }
+                    added.push((number, size));
+                }
+                TAG_COLUMN_FAMILY => column_family = edit.varint32()?,
+                TAG_COLUMN_FAMILY_DROP => dropped = true,
+                TAG_MAX_COLUMN_FAMILY | TAG_IN_ATOMIC_GROUP => {
+                    edit.varint32()?;
+                }
+                TAG_BLOB_FILE_ADDITION => {
+                    edit.varint64()?; // blob file number
+                    edit.varint64()?; // blob count
+                    edit.varint64()?; // blob bytes
+                    edit.slice()?; // checksum method
+                    edit.slice()?; // checksum value
+                    edit.custom_fields(BLOB_FIELDS_END)?;
+                }
+                TAG_BLOB_FILE_GARBAGE => {
+                    edit.varint64()?; // blob file number
+                    edit.varint64()?; // garbage count
+                    edit.varint64()?; // garbage bytes
+                    edit.custom_fields(BLOB_FIELDS_END)?;
+                }
+                tag if tag & TAG_SAFE_IGNORE_MASK != 0 => {
+                    edit.slice()?;
+                }
+                tag => return Err(format!("unknown version edit tag {}", tag)),
+            }
+        }
+
+        if dropped {
+            live.retain(|_, (family, _)| *family != column_family);
+        }
+        for number in deleted {
+            live.remove(&number);
+        }
+        for (number, size) in added {
+            live.insert(number, (column_family, size));
+        }
+    }
+    Ok(live
+        .into_iter()
+        .map(|(number, (_, size))| (number, size))
+        .collect())
+}
+
+/// Splits a RocksDB log file into its records, checking their checksums.
+fn log_records(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut records = Vec::new();
+    let mut fragments: Option<Vec<u8>> = None;
+    let mut offset = 0;
+    while offset < data.len() {
+        let block_left = LOG_BLOCK_SIZE - offset % LOG_BLOCK_SIZE;
+        let header = &data[offset..];
+        // Block trailers too short for a header are zero padding, as are preallocated zeros
+        if block_left < LOG_HEADER_SIZE || header[..LOG_HEADER_SIZE.min(header.len())] == [0; 7] {
+            offset += block_left;
+            continue;
+        }
+        if header.len() < LOG_HEADER_SIZE {
+            return Err(format!("truncated record header at offset {}", offset));
+        }
+
+        let kind = header[6];
+        let header_size = if (5..=8).contains(&kind) {
+            RECYCLABLE_LOG_HEADER_SIZE
+        } else {
+            LOG_HEADER_SIZE
+        };
+        let length = u16::from_le_bytes([header[4], header[5]]) as usize;
+        if header_size + length > block_left || header_size + length > header.len() {
+            return Err(format!("truncated record at offset {}", offset));
+        }
+        let payload = &header[header_size..header_size + length];
+        let stored = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let stored = stored.wrapping_sub(CRC_MASK_DELTA).rotate_left(15);
+        let actual = crc32c::crc32c_append(crc32c::crc32c(&header[6..header_size]), payload);
+        if stored != actual {
+            return Err(format!("checksum mismatch in record at offset {}", offset));
+        }
+
+        match (kind, fragments.as_mut()) {
+            (1 | 5, None) => records.push(payload.to_vec()),
+            (2 | 6, None) => fragments = Some(payload.to_vec()),
+            (3 | 7, Some(record)) => record.extend_from_slice(payload),
+            (4 | 8, Some(record)) => {
+                record.extend_from_slice(payload);
+                records.extend(fragments.take());
+            }
+            _ => {
+                return Err(format!(
+                    "unexpected record type {} at offset {}",
+                    kind, offset
+                ))
+            }
+        }
+        offset += header_size + length;
+    }
+    if fragments.is_some() {
+        return Err("truncated record at end of file".to_string());
+    }
+    Ok(records)
+}
+
+/// Reads the varint and length-prefixed fields of a version edit.
+struct Decoder<'a>(&'a [u8]);
+
+impl Decoder<'_> {
+    fn varint64(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for (i, byte) in self.0.iter().enumerate().take(10) {
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.0 = &self.0[i + 1..];
+                return Ok(value);
+            }
+        }
+        Err("malformed varint in version edit".to_string())
+    }
+
+    fn varint32(&mut self) -> Result<u32, String> {
+        u32::try_from(self.varint64()?).map_err(|_| "varint32 out of range".to_string())
+    }
+
+    fn slice(&mut self) -> Result<&[u8], String> {
+        let length = self.varint32()? as usize;
+        if length > self.0.len() {
+            return Err("truncated field in version edit".to_string());
+        }
+        let (slice, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(slice)
+    }
+
+    /// Skips `tag, slice` custom fields up to the terminating tag.
+    fn custom_fields(&mut self, terminator: u32) -> Result<(), String> {
+        while self.varint32()? != terminator {
+            self.slice()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            buf.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+
+    fn put_slice(buf: &mut Vec<u8>, slice: &[u8]) {
+        put_varint(buf, slice.len() as u64);
+        buf.extend_from_slice(slice);
+    }
+
+    /// Encodes a version edit adding `(number, size)` files and deleting `deleted`.
+    fn edit(added: &[(u64, u64)], deleted: &[u64]) -> Vec<u8> {
+        let mut edit = Vec::new();
+        put_varint(&mut edit, TAG_COMPARATOR.into());
+        put_slice(&mut edit, b"leveldb.BytewiseComparator");
+        for &number in deleted {
+            put_varint(&mut edit, TAG_DELETED_FILE.into());
+            put_varint(&mut edit, 1);
+            put_varint(&mut edit, number);
+        }
+        for &(number, size) in added {
+            put_varint(&mut edit, TAG_NEW_FILE4.into());
+            put_varint(&mut edit, 1);
+            put_varint(&mut edit, number);
+            put_varint(&mut edit, size);
+            put_slice(&mut edit, b"a\x01\0\0\0\0\0\0\0");
+            put_slice(&mut edit, b"z\x01\0\0\0\0\0\0\0");
+            put_varint(&mut edit, 1);
+            put_varint(&mut edit, 9);
+            put_varint(&mut edit, 2); // need compaction
+            put_slice(&mut edit, b"\0");
+            put_varint(&mut edit, NEW_FILE4_TERMINATE.into());
+        }
+        edit
+    }
+
+    /// Frames version edits as full log records.
+    fn log(edits: &[Vec<u8>]) -> Vec<u8> {
+        let mut log = Vec::new();
+        for edit in edits {
+            let crc = crc32c::crc32c_append(crc32c::crc32c(&[1]), edit);
+            let masked = crc.rotate_right(15).wrapping_add(CRC_MASK_DELTA);
+            log.extend_from_slice(&masked.to_le_bytes());
+            log.extend_from_slice(&(edit.len() as u16).to_le_bytes());
+            log.push(1);
+            log.extend_from_slice(edit);
+        }
+        log
+    }
+
+    fn sst(size: usize) -> Vec<u8> {
+        let mut data = vec![0u8; size - 8];
+        data.extend_from_slice(&0x88e241b785f4cff7u64.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_manifest_compared_with_files_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("shard-0");
+        std::fs::create_dir_all(&db).unwrap();
+        std::fs::write(db.join("CURRENT"), "MANIFEST-000005\n").unwrap();
+        let manifest = log(&[
+            edit(&[(10, 64), (11, 64), (12, 64)], &[]),
+            edit(&[(13, 64), (14, 64)], &[11]),
+        ]);
+        std::fs::write(db.join("MANIFEST-000005"), &manifest).unwrap();
+        std::fs::write(db.join("000010.sst"), sst(64)).unwrap();
+        std::fs::write(db.join("000011.sst"), sst(64)).unwrap(); // deleted: extra
+        std::fs::write(db.join("000012.sst"), sst(32)).unwrap(); // wrong size
+        std::fs::write(db.join("000013.sst"), vec![0u8; 64]).unwrap(); // bad footer
+
+        // 000014.sst is missing
+        let report = verify_db(dir.path()).unwrap();
+        assert_eq!(report.databases, std::slice::from_ref(&db));
+        assert_eq!(report.files_checked, 4);
+        assert_eq!(
+            report.issues,
+            [
+                DbIssue::Corrupt {
+                    path: db.join("000012.sst"),
+                    reason: "32 bytes, the MANIFEST records 64".to_string()
+                },
+                DbIssue::Missing {
+                    path: db.join("000014.sst")
+                },
+                DbIssue::Extra {
+                    path: db.join("000011.sst")
+                },
+                DbIssue::Corrupt {
+                    path: db.join("000013.sst"),
+                    reason: "invalid SST footer magic number".to_string()
+                },
+            ]
+        );
+
+        // A flipped bit in the MANIFEST is caught by the record checksum
+        let mut damaged = manifest;
+        damaged[20] ^= 1;
+        std::fs::write(db.join("MANIFEST-000005"), damaged).unwrap();
+        let report = verify_db(&db).unwrap();
+        assert!(matches!(
+            &report.issues[0],
+            DbIssue::Corrupt { reason, .. } if reason.contains("checksum mismatch")
+        ));
+    }
+}
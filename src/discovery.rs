@@ -0,0 +1,285 @@
+//! Discovery of the networks and shards a snapshot source provides.
+//!
+//! Sources are inspected in this order:
+//!
+//! 1. An `index.json` at the source root of the form
+//!    `{"networks": {"FARCASTER_NETWORK_MAINNET": [0, 1, 2]}}`
+//! 2. A listing of the source, for sources that support it (local directories
+//!    and S3): every `{network}/{shard}/latest.json` is an available shard
+//! 3. Probing `{network}/{shard}/latest.json` of the well-known networks for
+//!    shard 0, 1, 2, … until one is missing
+
+use crate::error::SnapshotError;
+use crate::metadata::{download_metadata, metadata_path};
+use crate::source::SnapshotSource;
+use crate::types::DownloadConfig;
+use futures_util::{StreamExt, TryStreamExt};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use tracing::info;
+
+/// Path of the optional index of networks and shards at the source root.
+const SOURCE_INDEX_PATH: &str = "index.json";
+
+/// Networks probed when the source has no index and can't be listed.
+const KNOWN_NETWORKS: [&str; 3] = [
+    "FARCASTER_NETWORK_MAINNET",
+    "FARCASTER_NETWORK_TESTNET",
+    "FARCASTER_NETWORK_DEVNET",
+];
+
+/// Upper bound on shard IDs probed per network.
+const MAX_PROBED_SHARDS: u32 = 64;
+
+/// The latest snapshot of a shard available from the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardInfo {
+    /// Network name.
+    pub network: String,
+    /// Shard ID.
+    pub shard_id: u32,
+    /// Base path of the latest snapshot.
+    pub key_base: String,
+    /// Unix timestamp when the latest snapshot was created.
+    pub timestamp: i64,
+    /// Number of chunks in the latest snapshot.
+    pub chunks: usize,
+    /// Combined size of the chunks in bytes, if the source reports every chunk's size.
+    pub total_size: Option<u64>,
+}
+
+/// The root `index.json` document.
+#[derive(Deserialize)]
+struct SourceIndex {
+    networks: BTreeMap<String, Vec<u32>>,
+}
+
+/// Networks and shards found on a source.
+#[derive(Debug, Default)]
+struct SourceLayout {
+    /// Shard IDs per network, sorted.
+    shards: BTreeMap<String, Vec<u32>>,
+    /// Object sizes by key, when they came with a listing.
+    sizes: HashMap<String, u64>,
+}
+
+/// Lists the networks with at least one shard available from the snapshot source.
+///
+/// # Arguments
+///
+/// * `config` - Download configuration (source)
+///
+/// # Returns
+///
+/// The network names, sorted, or an error.
+pub async fn discover_networks(config: &DownloadConfig) -> Result<Vec<String>, SnapshotError> {
+    let source = config.snapshot_source()?;
+    let layout = discover_layout(source.as_ref(), None).await?;
+    Ok(layout.shards.into_keys().collect())
+}
+
+/// Lists the shard IDs of [`DownloadConfig::network`] available from the snapshot source.
+///
+/// This is what `--shards all` restores.
+///
+/// # Arguments
+///
+/// * `config` - Download configuration (source and network)
+///
+/// # Returns
+///
+/// The shard IDs, sorted, or an error.
+pub async fn available_shards(config: &DownloadConfig) -> Result<Vec<u32>, SnapshotError> {
+    let source = config.snapshot_source()?;
+    let mut layout = discover_layout(source.as_ref(), Some(&config.network)).await?;
+    Ok(layout.shards.remove(&config.network).unwrap_or_default())
+}
+
+/// Describes the latest snapshot of every shard of [`DownloadConfig::network`].
+///
+/// Reads the metadata of each shard (verifying its signature when
+/// [`DownloadConfig::trusted_keys`] is set). Chunk sizes come from the
+/// published checksums or the source listing, and are otherwise requested
+/// with up to [`DownloadConfig::max_concurrent_downloads`] HEAD requests.
+///
+/// # Arguments
+///
+/// * `config` - Download configuration (source, network and trusted keys)
+///
+/// # Returns
+///
+/// One entry per available shard, sorted by shard ID, or an error.
+pub async fn discover_shards(config: &DownloadConfig) -> Result<Vec<ShardInfo>, SnapshotError> {
+    let source = config.snapshot_source()?;
+    let source = source.as_ref();
+    let mut layout = discover_layout(source, Some(&config.network)).await?;
+    let shard_ids = layout.shards.remove(&config.network).unwrap_or_default();
+
+    let mut shards = Vec::with_capacity(shard_ids.len());
+    for shard_id in shard_ids {
+        let metadata =
+            download_metadata(source, &config.network, shard_id, &config.trusted_keys).await?;
+        let sizes: Vec<Option<u64>> = futures_util::stream::iter(&metadata.chunks)
+            .map(|chunk| {
+                let key = format!("{}/{}", metadata.key_base, chunk);
+                let known = metadata
+                    .checksum(chunk)
+                    .and_then(|checksum| checksum.size)
+                    .or_else(|| layout.sizes.get(&key).copied());
+                async move {
+                    match known {
+                        Some(size) => Ok(Some(size)),
+                        None => source.stat(&key).await.map(|info| info.size),
+                    }
+                }
+            })
+            .buffered(config.max_concurrent_downloads.max(1))
+            .try_collect()
+            .await?;
+
+        shards.push(ShardInfo {
+            network: config.network.clone(),
+            shard_id,
+            key_base: metadata.key_base,
+            timestamp: metadata.timestamp,
+            chunks: metadata.chunks.len(),
+            total_size: sizes.into_iter().sum(),
+        });
+    }
+    Ok(shards)
+}
+
+/// Finds the networks and shards of a source, optionally restricted to one network.
+async fn discover_layout(
+    source: &dyn SnapshotSource,
+    network: Option<&str>,
+) -> Result<SourceLayout, SnapshotError> {
+    match source.fetch(SOURCE_INDEX_PATH).await {
+        Ok(data) => {
+            let index: SourceIndex = serde_json::from_slice(&data).map_err(|e| {
+                SnapshotError::DownloadFailed(format!(
+                    "Invalid source index from {}: {}",
+                    source.describe(SOURCE_INDEX_PATH),
+                    e
+                ))
+            })?;
+            let shards = index
+                .networks
+                .into_iter()
+                .filter(|(name, _)| network.is_none_or(|network| network == name))
+                .map(|(name, mut shards)| {
+                    shards.sort_unstable();
+                    shards.dedup();
+                    (name, shards)
+                })
+                .collect();
+            return Ok(SourceLayout {
+                shards,
+                sizes: HashMap::new(),
+            });
+        }
+        Err(SnapshotError::NotFound(_)) => {}
+        Err(e) => return Err(e),
+    }
+
+    let prefix = network
+        .map(|network| format!("{}/", network))
+        .unwrap_or_default();
+    match source.list(&prefix).await {
+        Ok(objects) => {
+            let mut layout = SourceLayout::default();
+            for object in objects {
+                let mut parts = object.key.split('/');
+                if let (Some(name), Some(shard), Some("latest.json"), None) =
+                    (parts.next(), parts.next(), parts.next(), parts.next())
+                {
+                    if let Ok(shard_id) = shard.parse() {
+                        layout
+                            .shards
+                            .entry(name.to_string())
+                            .or_default()
+                            .push(shard_id);
+                    }
+                }
+                layout.sizes.insert(object.key, object.size);
+            }
+            for shards in layout.shards.values_mut() {
+                shards.sort_unstable();
+            }
+            return Ok(layout);
+        }
+        Err(SnapshotError::ListingNotSupported(_)) => {}
+        Err(e) => return Err(e),
+    }
+
+    info!(
+        "No index at {} and listing is not supported, probing shards",
+        source.describe(SOURCE_INDEX_PATH)
+    );
+    let networks = network.map_or_else(|| KNOWN_NETWORKS.to_vec(), |network| vec![network]);
+    let mut layout = SourceLayout::default();
+    for network in networks {
+        let mut shards = Vec::new();
+        for shard_id in 0..MAX_PROBED_SHARDS {
+            match source.stat(&metadata_path(network, shard_id)).await {
+                Ok(_) => shards.push(shard_id),
+                Err(SnapshotError::NotFound(_)) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        if !shards.is_empty() {
+            layout.shards.insert(network.to_string(), shards);
+        }
+    }
+    Ok(layout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shards_discovered_from_listing_and_index() {
+        let dir = tempfile::tempdir().unwrap();
+        for (network, shard) in [("net-a", 0), ("net-a", 2), ("net-b", 1)] {
+            let snapshot = dir.path().join(format!("{}/{}/snapshot-1", network, shard));
+            std::fs::create_dir_all(&snapshot).unwrap();
+            std::fs::write(snapshot.join("chunk_0000.bin"), b"0123456789").unwrap();
+            std::fs::write(snapshot.join("chunk_0001.bin"), b"01234").unwrap();
+            std::fs::write(
+                dir.path().join(format!("{}/{}/latest.json", network, shard)),
+                format!(
+                    r#"{{"key_base":"{}/{}/snapshot-1","chunks":["chunk_0000.bin","chunk_0001.bin"],"timestamp":7}}"#,
+                    network, shard
+                ),
+            )
+            .unwrap();
+        }
+        let config = DownloadConfig {
+            snapshot_download_url: dir.path().to_str().unwrap().to_string(),
+            network: "net-a".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            discover_networks(&config).await.unwrap(),
+            ["net-a", "net-b"]
+        );
+        assert_eq!(available_shards(&config).await.unwrap(), [0, 2]);
+        let shards = discover_shards(&config).await.unwrap();
+        assert_eq!(shards.len(), 2);
+        assert_eq!(shards[1].shard_id, 2);
+        assert_eq!(shards[1].timestamp, 7);
+        assert_eq!(shards[1].chunks, 2);
+        assert_eq!(shards[1].total_size, Some(15));
+
+        // A root index takes precedence over the listing
+        std::fs::write(
+            dir.path().join("index.json"),
+            br#"{"networks": {"net-a": [2]}}"#,
+        )
+        .unwrap();
+        assert_eq!(discover_networks(&config).await.unwrap(), ["net-a"]);
+        assert_eq!(available_shards(&config).await.unwrap(), [2]);
+    }
+}
@@ -1,63 +1,331 @@
 //! Chunk download functionality.
 
+use crate::bandwidth::BandwidthLimiter;
+use crate::concurrency::ConcurrencyController;
 use crate::error::SnapshotError;
+use crate::progress::{ProgressEvent, ProgressObserver};
+use crate::retry::RetryPolicy;
+use crate::source::{ByteRange, SnapshotSource};
+use crate::state::ChunkStateStore;
+use crate::types::ChunkChecksum;
+use crate::verify::{
+    checksum_hasher_for_file, md5_hasher_for_file, verify_local_file, verify_multipart_etag,
+    ChecksumHasher, MultipartCheck,
+};
 use futures_util::StreamExt;
-use std::io;
+use std::sync::Arc;
 use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
-/// Downloads a file from a URL with MD5 verification.
+/// State shared by all chunk downloads of a run.
+#[derive(Debug, Clone)]
+pub(crate) struct DownloadContext {
+    /// Source chunks are read from.
+    pub source: Arc<dyn SnapshotSource>,
+    /// Limits the combined throughput of all downloads.
+    pub limiter: BandwidthLimiter,
+    /// Limits, and possibly adapts, the number of downloads in flight.
+    pub concurrency: Arc<ConcurrencyController>,
+    /// How failed downloads are retried.
+    pub retry: RetryPolicy,
+    /// Receives progress events.
+    pub progress: Arc<dyn ProgressObserver>,
+    /// Stops downloads when cancelled.
+    pub cancel: CancellationToken,
+    /// Chunks verified by this or earlier runs.
+    pub state: Arc<ChunkStateStore>,
+    /// Part size of multipart uploads, inferred from the ETag when `None`.
+    pub multipart_part_size: Option<u64>,
+}
+
+impl DownloadContext {
+    /// Checks whether a local chunk is complete, reporting what is learned about its size.
+    ///
+    /// Chunks that are unchanged since they were last verified are trusted
+    /// without contacting the source; others are verified against the published
+    /// checksum or the source, and recorded for the next run if a hash matched.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the chunk doesn't need to be downloaded, or an error if the
+    /// local file can't be read or prepared for resuming.
+    pub(crate) async fn check_local_chunk(
+        &self,
+        shard_id: u32,
+        key: &str,
+        filename: &str,
+        checksum: Option<&ChunkChecksum>,
+        skip_verify: bool,
+    ) -> Result<bool, SnapshotError> {
+        let chunk = std::path::Path::new(filename)
+            .file_name()
+            .map_or_else(|| filename.to_string(), |n| n.to_string_lossy().to_string());
+
+        if !skip_verify {
+            if let Some(size) = self.state.verified_size(shard_id, key, filename) {
+                info!(
+                    "✅ File {} verified (unchanged since last verification)",
+                    chunk
+                );
+                self.progress.on_event(&ProgressEvent::ChunkVerified {
+                    shard_id,
+                    chunk,
+                    size,
+                });
+                return Ok(true);
+            }
+        }
+
+        let status = verify_local_file(
+            filename,
+            self.source.as_ref(),
+            key,
+            skip_verify,
+            self.multipart_part_size,
+            checksum,
+        )
+        .await?;
+        if status.integrity_confirmed() && !skip_verify {
+            self.state.record(
+                shard_id,
+                key,
+                filename,
+                status.etag,
+                status.md5,
+                status.digest,
+            );
+        }
+
+        match status.size {
+            Some(size) if status.verified => {
+                self.progress.on_event(&ProgressEvent::ChunkVerified {
+                    shard_id,
+                    chunk,
+                    size,
+                });
+            }
+            Some(size) => {
+                self.progress.on_event(&ProgressEvent::ChunkSized {
+                    shard_id,
+                    chunk,
+                    size,
+                });
+            }
+            None => {}
+        }
+        Ok(status.verified)
+    }
+}
+
+/// Returns the path of the partial file used while a chunk is being downloaded.
+pub(crate) fn partial_path(filename: &str) -> String {
+    format!("{}.part", filename)
+}
+
+/// Returns the path of the sidecar file recording the ETag a partial file belongs to.
+pub(crate) fn partial_etag_path(filename: &str) -> String {
+    format!("{}.part.etag", filename)
+}
+
+/// Removes a partial file and its ETag sidecar, ignoring missing files.
+pub(crate) async fn discard_partial(filename: &str) {
+    let _ = tokio::fs::remove_file(partial_path(filename)).await;
+    let _ = tokio::fs::remove_file(partial_etag_path(filename)).await;
+}
+
+/// Makes a completed rename durable by syncing the directory that holds `path`.
+///
+/// Directories can't be opened for syncing on Windows, where this is a no-op.
+pub(crate) async fn sync_parent_dir(path: &str) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            std::path::Path::new(".")
+        } else {
+            parent
+        };
+        tokio::fs::File::open(parent).await?.sync_all().await?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Returns the offset and ETag of a resumable partial download, if any.
+///
+/// A partial file is only resumable when we know which version of the remote
+/// object it was downloaded from, so that `If-Range` can protect the resume.
+async fn resumable_partial(filename: &str) -> Option<(u64, String)> {
+    let size = tokio::fs::metadata(partial_path(filename))
+        .await
+        .ok()?
+        .len();
+    let etag = tokio::fs::read_to_string(partial_etag_path(filename))
+        .await
+        .ok()?;
+    let etag = etag.trim();
+    if size == 0 || etag.is_empty() {
+        return None;
+    }
+    Some((size, etag.to_string()))
+}
+
+/// Downloads a file from a snapshot source with MD5 verification.
 ///
-/// Simplified version that just downloads and verifies the file.
+/// The data is written to `<filename>.part`, synced to disk and only renamed to
+/// `filename` once the size and MD5 checks pass; the rename itself is made durable
+/// by syncing the directory. A chunk file under its final name is therefore always
+/// complete, even after a crash. If a partial file from a previous attempt exists,
+/// the download resumes from its end with a range request guarded by the ETag, and
+/// the MD5 is computed over the existing prefix plus the newly received bytes.
+///
+/// When the metadata publishes SHA-256/BLAKE3 digests for the chunk, those are
+/// verified instead of the ETag, along with the published size.
 ///
 /// # Arguments
 ///
-/// * `url` - The URL to download from
+/// * `ctx` - Shared download state (source, bandwidth limiter, concurrency, progress)
+/// * `shard_id` - Shard the chunk belongs to, for progress events
+/// * `key` - The object key within the source
 /// * `filename` - The local filename to save to
-/// * `pb` - Progress bar for updating download progress
+/// * `checksum` - Size and digests published in the metadata, if any
 ///
 /// # Returns
 ///
 /// `Ok(())` on successful download and verification, or an error.
 pub(crate) async fn download_file_simple(
-    url: &str,
+    ctx: &DownloadContext,
+    shard_id: u32,
+    key: &str,
     filename: &str,
-    _pb: indicatif::ProgressBar,
+    checksum: Option<&ChunkChecksum>,
 ) -> Result<(), SnapshotError> {
     let file_display_name = std::path::Path::new(filename)
         .file_name()
         .and_then(|n| n.to_str())
         .unwrap_or(filename);
+    let part_filename = partial_path(filename);
 
     // Create parent directory if needed
     if let Some(parent) = std::path::Path::new(filename).parent() {
         tokio::fs::create_dir_all(parent).await?;
     }
 
-    let mut file = BufWriter::new(tokio::fs::File::create(filename).await?);
-    let download_response = reqwest::get(url).await?.error_for_status()?;
-    let content_length = download_response.content_length();
+    let resume = resumable_partial(filename).await;
+    let range = resume.as_ref().map(|(offset, etag)| ByteRange {
+        start: *offset,
+        if_etag: Some(etag.clone()),
+    });
+    let source = ctx.source.as_ref();
+    let object = tokio::select! {
+        biased;
+        _ = ctx.cancel.cancelled() => return Err(SnapshotError::Cancelled),
+        object = source.get(key, range) => object?,
+    };
+
+    // The source returns the whole object if the partial file can't be resumed
+    let offset = object.offset;
+    if offset > 0 {
+        info!("⏩ Resuming {} from byte {}", file_display_name, offset);
+    } else if resume.is_some() {
+        info!(
+            "Partial file for {} cannot be resumed, restarting download",
+            file_display_name
+        );
+    }
+    let content_length = object.content_length;
+    ctx.progress.on_event(&ProgressEvent::ChunkTransferStarted {
+        shard_id,
+        chunk: file_display_name.to_string(),
+        offset,
+    });
+    if let Some(content_length) = content_length {
+        ctx.progress.on_event(&ProgressEvent::ChunkSized {
+            shard_id,
+            chunk: file_display_name.to_string(),
+            size: offset + content_length,
+        });
+    }
 
-    // Get ETag from response headers (this is MD5 for simple S3/R2 uploads)
-    let etag = download_response
-        .headers()
-        .get("etag")
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.trim_matches('"').to_string());
+    // Get ETag from the object (this is MD5 for simple S3/R2 uploads)
+    let etag = object.info.etag;
 
-    // Stream download and compute MD5 simultaneously
-    let mut byte_stream = download_response.bytes_stream();
-    let mut hasher = if etag.is_some() {
-        use md5::Digest;
-        Some(md5::Md5::new())
+    let file = if offset > 0 {
+        tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&part_filename)
+            .await?
     } else {
-        None
+        let file = tokio::fs::File::create(&part_filename).await?;
+        // Remember which object version this partial file belongs to
+        match etag {
+            Some(ref etag) => tokio::fs::write(partial_etag_path(filename), etag).await?,
+            None => {
+                let _ = tokio::fs::remove_file(partial_etag_path(filename)).await;
+            }
+        }
+        file
     };
+    let mut file = BufWriter::new(file);
 
-    while let Some(piece) = byte_stream.next().await {
-        let chunk = piece?;
+    // Stream download and compute MD5 simultaneously, seeding the hash with the resumed prefix.
+    // Multipart ETags are checked against the finished file instead, and published
+    // digests replace the ETag altogether.
+    let mut byte_stream = object.stream;
+    let published = checksum.filter(|checksum| checksum.has_digest());
+    let mut checksum_hasher = match published {
+        Some(checksum) if offset > 0 => checksum_hasher_for_file(&part_filename, checksum).await?,
+        Some(checksum) => ChecksumHasher::new(checksum),
+        None => None,
+    };
+    let multipart = etag.as_deref().is_some_and(|etag| etag.contains('-'));
+    let mut hasher = match etag {
+        _ if published.is_some() => None,
+        Some(_) if multipart => None,
+        Some(_) if offset > 0 => Some(md5_hasher_for_file(&part_filename).await?),
+        Some(_) => {
+            use md5::Digest;
+            Some(md5::Md5::new())
+        }
+        None => None,
+    };
 
-        // Update MD5 hash
+    loop {
+        let next = async {
+            let piece = byte_stream.next().await;
+            if let Some(Ok(ref chunk)) = piece {
+                ctx.limiter.acquire(chunk.len()).await;
+            }
+            piece
+        };
+        // Keep what was received so far for the next attempt or run to resume from
+        let chunk = tokio::select! {
+            biased;
+            _ = ctx.cancel.cancelled() => {
+                file.flush().await?;
+                return Err(SnapshotError::Cancelled);
+            }
+            piece = next => match piece {
+                Some(Ok(chunk)) => chunk,
+                Some(Err(e)) => {
+                    file.flush().await?;
+                    return Err(e);
+                }
+                None => break,
+            },
+        };
+        ctx.concurrency.record_bytes(chunk.len() as u64);
+        ctx.progress.on_event(&ProgressEvent::ChunkBytes {
+            shard_id,
+            chunk: file_display_name.to_string(),
+            bytes: chunk.len() as u64,
+        });
+
+        // Update hashes
+        if let Some(ref mut h) = checksum_hasher {
+            h.update(&chunk);
+        }
         if let Some(ref mut h) = hasher {
             use md5::Digest;
             h.update(&chunk);
@@ -66,48 +334,380 @@ pub(crate) async fn download_file_simple(
         file.write_all(&chunk).await?;
     }
     file.flush().await?;
+    file.get_ref().sync_all().await?;
 
     // Verify file size
-    let file_size = tokio::fs::metadata(filename).await?.len();
+    let file_size = tokio::fs::metadata(&part_filename).await?.len();
     if let Some(content_length) = content_length {
-        if file_size != content_length {
-            return Err(SnapshotError::IoError(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!(
-                    "File size mismatch for {}: expected {} bytes, got {} bytes",
-                    filename, content_length, file_size
-                ),
-            )));
+        let expected_size = offset + content_length;
+        if file_size != expected_size {
+            // A short file can be resumed later, a longer one is corrupt
+            if file_size > expected_size {
+                discard_partial(filename).await;
+            }
+            return Err(SnapshotError::SizeMismatch {
+                chunk: filename.to_string(),
+                expected: expected_size,
+                actual: file_size,
+            });
         }
-    } else {
+    } else if checksum.and_then(|checksum| checksum.size).is_none() {
         warn!(
             "Content-Length header was not present for {}. Cannot verify file size.",
-            url
+            source.describe(key)
         );
     }
+    if let Some(published_size) = checksum
+        .and_then(|checksum| checksum.size)
+        .filter(|&size| size != file_size)
+    {
+        // A longer file or one from another object version can't be resumed
+        if file_size > published_size || content_length.is_some() {
+            discard_partial(filename).await;
+        }
+        return Err(SnapshotError::SizeMismatch {
+            chunk: filename.to_string(),
+            expected: published_size,
+            actual: file_size,
+        });
+    }
 
     // Verify MD5 checksum (Snapchain's ETag is always MD5 for simple uploads)
-    if let (Some(expected_etag), Some(hasher)) = (etag, hasher) {
-        // Skip multipart uploads (they have "-" in ETag)
-        if !expected_etag.contains('-') {
-            use md5::Digest;
-            info!("🔍 Verifying MD5 for {}", file_display_name);
-            let computed_md5 = format!("{:x}", hasher.finalize());
-
-            if computed_md5 != expected_etag {
-                // MD5 mismatch - delete corrupted file
-                let _ = tokio::fs::remove_file(filename).await;
-                return Err(SnapshotError::IoError(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!(
-                        "❌ MD5 mismatch for {}: expected {}, got {}",
-                        file_display_name, expected_etag, computed_md5
-                    ),
-                )));
+    let mut verified_md5 = None;
+    let mut verified_digest = None;
+    let mut integrity_confirmed = false;
+    if let (Some(published), Some(hasher)) = (published, checksum_hasher) {
+        info!("🔍 Verifying published checksum for {}", file_display_name);
+        if let Some((expected, actual)) = hasher.mismatch(published) {
+            discard_partial(filename).await;
+            warn!("❌ Checksum mismatch for {}", file_display_name);
+            return Err(SnapshotError::ChecksumMismatch {
+                chunk: filename.to_string(),
+                expected,
+                actual,
+            });
+        }
+        info!("✅ Published checksum verified for {}", file_display_name);
+        verified_digest = published.digest();
+        integrity_confirmed = true;
+    } else if let (Some(expected_etag), Some(hasher)) = (etag.clone(), hasher) {
+        use md5::Digest;
+        info!("🔍 Verifying MD5 for {}", file_display_name);
+        let computed_md5 = format!("{:x}", hasher.finalize());
+
+        if computed_md5 != expected_etag {
+            // MD5 mismatch - delete corrupted file
+            discard_partial(filename).await;
+            warn!("❌ MD5 mismatch for {}", file_display_name);
+            return Err(SnapshotError::ChecksumMismatch {
+                chunk: filename.to_string(),
+                expected: expected_etag,
+                actual: computed_md5,
+            });
+        }
+        info!("✅ MD5 verified for {}", file_display_name);
+        verified_md5 = Some(computed_md5);
+        integrity_confirmed = true;
+    } else if let Some(expected_etag) = etag.as_deref().filter(|_| multipart) {
+        info!("🔍 Verifying multipart ETag for {}", file_display_name);
+        match verify_multipart_etag(&part_filename, expected_etag, ctx.multipart_part_size).await? {
+            MultipartCheck::Verified { part_size } => {
+                info!(
+                    "✅ Multipart ETag verified for {} ({} byte parts)",
+                    file_display_name, part_size
+                );
+                integrity_confirmed = true;
+            }
+            MultipartCheck::Mismatch { actual } => {
+                discard_partial(filename).await;
+                warn!("❌ Multipart ETag mismatch for {}", file_display_name);
+                return Err(SnapshotError::ChecksumMismatch {
+                    chunk: filename.to_string(),
+                    expected: expected_etag.to_string(),
+                    actual,
+                });
+            }
+            MultipartCheck::UnknownPartSize => {
+                warn!(
+                    "⚠️  {} only size-checked (unknown multipart part size, set multipart_part_size to verify it)",
+                    file_display_name
+                );
             }
-            info!("✅ MD5 verified for {}", file_display_name);
         }
     }
 
+    // Move the completed file into place
+    tokio::fs::rename(&part_filename, filename).await?;
+    sync_parent_dir(filename).await?;
+    let _ = tokio::fs::remove_file(partial_etag_path(filename)).await;
+    // A size-checked chunk is verified again on the next run
+    if integrity_confirmed {
+        ctx.state
+            .record(shard_id, key, filename, etag, verified_md5, verified_digest);
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::NoProgress;
+    use crate::source::{ObjectInfo, ObjectStream};
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use std::sync::Mutex;
+
+    /// Serves one object with a fixed ETag, honoring ranges like an HTTP server with `If-Range`.
+    #[derive(Debug)]
+    struct FixedSource {
+        data: &'static [u8],
+        etag: String,
+        /// Fail the next transfer after this many bytes.
+        interrupt_after: Mutex<Option<usize>>,
+        /// Offsets of the transfers served so far.
+        offsets: Mutex<Vec<u64>>,
+    }
+
+    impl FixedSource {
+        fn new(etag: &str) -> Arc<Self> {
+            Arc::new(Self {
+                data: b"chunk data",
+                etag: etag.to_string(),
+                interrupt_after: Mutex::new(None),
+                offsets: Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl SnapshotSource for FixedSource {
+        fn describe(&self, key: &str) -> String {
+            key.to_string()
+        }
+
+        async fn stat(&self, _key: &str) -> Result<ObjectInfo, SnapshotError> {
+            Ok(ObjectInfo {
+                size: Some(self.data.len() as u64),
+                etag: Some(self.etag.clone()),
+            })
+        }
+
+        async fn get(
+            &self,
+            key: &str,
+            range: Option<ByteRange>,
+        ) -> Result<ObjectStream, SnapshotError> {
+            let offset = match range {
+                Some(range)
+                    if range
+                        .if_etag
+                        .as_deref()
+                        .is_none_or(|etag| etag == self.etag)
+                        && range.start <= self.data.len() as u64 =>
+                {
+                    range.start
+                }
+                _ => 0,
+            };
+            self.offsets.lock().unwrap().push(offset);
+
+            let data = &self.data[offset as usize..];
+            let mut pieces = vec![Ok(Bytes::from_static(data))];
+            if let Some(n) = self.interrupt_after.lock().unwrap().take() {
+                pieces = vec![
+                    Ok(Bytes::from_static(&data[..n])),
+                    Err(SnapshotError::IoError(
+                        std::io::ErrorKind::ConnectionReset.into(),
+                    )),
+                ];
+            }
+            Ok(ObjectStream {
+                info: self.stat(key).await?,
+                offset,
+                content_length: Some(data.len() as u64),
+                stream: Box::pin(futures_util::stream::iter(pieces)),
+            })
+        }
+    }
+
+    fn context(source: Arc<FixedSource>, snapshot_dir: &std::path::Path) -> DownloadContext {
+        DownloadContext {
+            source,
+            limiter: BandwidthLimiter::unlimited(),
+            concurrency: Arc::new(ConcurrencyController::new(1, None)),
+            retry: RetryPolicy::default(),
+            progress: Arc::new(NoProgress),
+            cancel: CancellationToken::new(),
+            state: Arc::new(ChunkStateStore::new(snapshot_dir.to_str().unwrap(), false)),
+            multipart_part_size: None,
+        }
+    }
+
+    fn md5_hex(data: &[u8]) -> String {
+        use md5::Digest;
+        format!("{:x}", md5::Md5::digest(data))
+    }
+
+    #[tokio::test]
+    async fn test_chunk_only_appears_after_verification() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("chunk_0000.bin");
+        let filename = filename.to_str().unwrap();
+
+        let result = download_file_simple(
+            &context(FixedSource::new("0123456789abcdef"), dir.path()),
+            0,
+            "key",
+            filename,
+            None,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(SnapshotError::ChecksumMismatch { .. })
+        ));
+        assert!(!std::path::Path::new(filename).exists());
+        assert!(!std::path::Path::new(&partial_path(filename)).exists());
+
+        let ctx = context(FixedSource::new(&md5_hex(b"chunk data")), dir.path());
+        download_file_simple(&ctx, 0, "key", filename, None)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(filename).unwrap(), b"chunk data");
+        assert!(!std::path::Path::new(&partial_path(filename)).exists());
+        assert_eq!(ctx.state.verified_size(0, "key", filename), Some(10));
+    }
+
+    #[tokio::test]
+    async fn test_size_checked_chunk_is_not_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("chunk_0000.bin");
+        let filename = filename.to_str().unwrap();
+
+        // The part size of this multipart ETag can't be inferred for a 10 byte object
+        let etag = format!("{}-2", md5_hex(b""));
+        let ctx = context(FixedSource::new(&etag), dir.path());
+        download_file_simple(&ctx, 0, "key", filename, None)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(filename).unwrap(), b"chunk data");
+        assert_eq!(ctx.state.verified_size(0, "key", filename), None);
+
+        // The local check accepts it by size, but doesn't record it either
+        assert!(ctx
+            .check_local_chunk(0, "key", filename, None, false)
+            .await
+            .unwrap());
+        assert_eq!(ctx.state.verified_size(0, "key", filename), None);
+    }
+
+    #[tokio::test]
+    async fn test_local_check_reports_io_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("chunk_0000.bin");
+        let filename = filename.to_str().unwrap();
+        std::fs::write(filename, b"chunk").unwrap();
+
+        // The truncated chunk is kept for resuming, but its ETag can't be written
+        std::fs::create_dir(partial_etag_path(filename)).unwrap();
+        let ctx = context(FixedSource::new(&md5_hex(b"chunk data")), dir.path());
+        assert!(matches!(
+            ctx.check_local_chunk(0, "key", filename, None, false).await,
+            Err(SnapshotError::IoError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_published_checksum_takes_precedence_over_etag() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("chunk_0000.bin");
+        let filename = filename.to_str().unwrap();
+        // The ETag is not an MD5 of the data, as with some storage providers
+        let ctx = context(FixedSource::new("0123456789abcdef"), dir.path());
+
+        let wrong = ChunkChecksum {
+            size: Some(10),
+            blake3: Some(blake3::hash(b"other data").to_hex().to_string()),
+            ..Default::default()
+        };
+        let result = download_file_simple(&ctx, 0, "key", filename, Some(&wrong)).await;
+        assert!(matches!(
+            result,
+            Err(SnapshotError::ChecksumMismatch { ref expected, .. }) if expected.starts_with("blake3:")
+        ));
+
+        let published = ChunkChecksum {
+            size: Some(10),
+            sha256: Some(hex::encode(<sha2::Sha256 as sha2::Digest>::digest(
+                b"chunk data",
+            ))),
+            blake3: Some(blake3::hash(b"chunk data").to_hex().to_string()),
+        };
+        download_file_simple(&ctx, 0, "key", filename, Some(&published))
+            .await
+            .unwrap();
+        assert!(ctx
+            .check_local_chunk(0, "other-key", filename, Some(&published), false)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_download_resumes_from_partial_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("chunk_0000.bin");
+        let filename = filename.to_str().unwrap();
+        let etag = md5_hex(b"chunk data");
+        let source = FixedSource::new(&etag);
+        *source.interrupt_after.lock().unwrap() = Some(4);
+        let ctx = context(Arc::clone(&source), dir.path());
+
+        // The interrupted transfer leaves the prefix and the ETag it belongs to
+        let result = download_file_simple(&ctx, 0, "key", filename, None).await;
+        assert!(matches!(result, Err(SnapshotError::IoError(_))));
+        assert!(!std::path::Path::new(filename).exists());
+        assert_eq!(std::fs::read(partial_path(filename)).unwrap(), b"chun");
+        assert_eq!(
+            std::fs::read_to_string(partial_etag_path(filename)).unwrap(),
+            etag
+        );
+
+        // The retry appends the rest and verifies the MD5 over the whole file
+        download_file_simple(&ctx, 0, "key", filename, None)
+            .await
+            .unwrap();
+        assert_eq!(*source.offsets.lock().unwrap(), [0, 4]);
+        assert_eq!(std::fs::read(filename).unwrap(), b"chunk data");
+        assert!(!std::path::Path::new(&partial_path(filename)).exists());
+        assert!(!std::path::Path::new(&partial_etag_path(filename)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_partial_file_of_other_object_version_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("chunk_0000.bin");
+        let filename = filename.to_str().unwrap();
+        std::fs::write(partial_path(filename), b"stale").unwrap();
+        std::fs::write(partial_etag_path(filename), "previous-etag").unwrap();
+        let source = FixedSource::new(&md5_hex(b"chunk data"));
+        let ctx = context(Arc::clone(&source), dir.path());
+
+        // If-Range doesn't match, so the whole object replaces the stale prefix
+        download_file_simple(&ctx, 0, "key", filename, None)
+            .await
+            .unwrap();
+        assert_eq!(*source.offsets.lock().unwrap(), [0]);
+        assert_eq!(std::fs::read(filename).unwrap(), b"chunk data");
+        assert!(!std::path::Path::new(&partial_etag_path(filename)).exists());
+
+        // Without a sidecar the partial file can't be trusted either
+        std::fs::remove_file(filename).unwrap();
+        std::fs::write(partial_path(filename), b"stale").unwrap();
+        download_file_simple(&ctx, 0, "key", filename, None)
+            .await
+            .unwrap();
+        assert_eq!(*source.offsets.lock().unwrap(), [0, 0]);
+        assert_eq!(std::fs::read(filename).unwrap(), b"chunk data");
+    }
+}
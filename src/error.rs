@@ -1,6 +1,8 @@
 //! Error types for snapshot operations.
 
 use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
 use thiserror::Error;
 
 /// Errors that can occur during snapshot operations.
@@ -8,7 +10,7 @@ use thiserror::Error;
 pub enum SnapshotError {
     /// I/O error during file operations.
     #[error(transparent)]
-    IoError(#[from] io::Error),
+    IoError(io::Error),
 
     /// HTTP request error during download.
     #[error(transparent)]
@@ -18,7 +20,259 @@ pub enum SnapshotError {
     #[error(transparent)]
     SerdeJsonError(#[from] serde_json::Error),
 
+    /// The requested object does not exist in the snapshot source.
+    #[error("Object not found: {0}")]
+    NotFound(String),
+
+    /// The server answered with an unsuccessful HTTP status.
+    #[error("HTTP {status} from {url}")]
+    HttpStatus {
+        /// Requested URL.
+        url: String,
+        /// HTTP status code.
+        status: u16,
+        /// Delay requested by the server with `Retry-After`, if any.
+        retry_after: Option<Duration>,
+    },
+
+    /// A downloaded chunk does not match its expected checksum.
+    #[error("Checksum mismatch for {chunk}: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        /// Local path of the chunk.
+        chunk: String,
+        /// Expected checksum (hex).
+        expected: String,
+        /// Checksum of the downloaded data (hex).
+        actual: String,
+    },
+
+    /// A downloaded chunk does not have the expected size.
+    #[error("Size mismatch for {chunk}: expected {expected} bytes, got {actual} bytes")]
+    SizeMismatch {
+        /// Local path of the chunk.
+        chunk: String,
+        /// Expected size in bytes.
+        expected: u64,
+        /// Size of the downloaded data in bytes.
+        actual: u64,
+    },
+
+    /// The source has no snapshot metadata for a shard.
+    #[error(
+        "Snapshot not found for network '{network}' shard {shard}. The metadata URL returned 404: {url}\n\
+         This usually means:\n\
+         - The shard doesn't exist for this network\n\
+         - The snapshot hasn't been created yet\n\
+         - The URL is incorrect\n\
+         Run `snapsync list` to see the networks and shards available from the source"
+    )]
+    MetadataNotFound {
+        /// Network name.
+        network: String,
+        /// Shard ID.
+        shard: u32,
+        /// Location of the missing metadata.
+        url: String,
+    },
+
+    /// A merge/extract-only run was asked for a shard missing from the local `metadata.json`.
+    #[error(
+        "Metadata not found for shard {shard}. Available shards in metadata: {available:?}. \
+         Run without --stage flag to download metadata first."
+    )]
+    ShardNotInLocalMetadata {
+        /// Requested shard ID.
+        shard: u32,
+        /// Shards present in the local metadata.
+        available: Vec<u32>,
+    },
+
+    /// No available snapshot of a shard matches the requested selector.
+    #[error(
+        "No snapshot of shard {shard} matches {selector}. Available snapshots: {}",
+        if available.is_empty() { "none".to_string() } else { available.join(", ") }
+    )]
+    NoMatchingSnapshot {
+        /// Shard ID.
+        shard: u32,
+        /// Description of the selector.
+        selector: String,
+        /// Key bases of the available snapshots, newest first.
+        available: Vec<String>,
+    },
+
+    /// `latest.json` points to another snapshot than the one being restored.
+    #[error(
+        "Snapshot of shard {shard} changed from {previous} to {latest} since the last run. \
+         Pass --on-snapshot-change resume to finish {previous}, or restart to download {latest}."
+    )]
+    SnapshotChanged {
+        /// Shard ID.
+        shard: u32,
+        /// Key base of the snapshot in the local `metadata.json`.
+        previous: String,
+        /// Key base `latest.json` now points to.
+        latest: String,
+    },
+
+    /// The database directory of a shard holds a restore of the snapshot being replaced.
+    #[error(
+        "{} holds a restore of {previous}, the previous snapshot of shard {shard}. \
+         Move it away, or pass --clear-db-on-snapshot-change to replace it.",
+        path.display()
+    )]
+    StaleDatabase {
+        /// Shard ID.
+        shard: u32,
+        /// Key base of the snapshot in the local `metadata.json`.
+        previous: String,
+        /// Database directory of the shard.
+        path: PathBuf,
+    },
+
+    /// The source can't enumerate its objects.
+    #[error("Listing objects is not supported for {0}; publish an index.json to list snapshots")]
+    ListingNotSupported(String),
+
+    /// Trusted keys are configured but the metadata has no signature.
+    #[error("Metadata at {url} is not signed: no signature found at {signature_url}")]
+    UnsignedMetadata {
+        /// Location of the metadata.
+        url: String,
+        /// Location the detached signature was expected at.
+        signature_url: String,
+    },
+
+    /// The metadata signature is malformed or was not made by a trusted key.
+    #[error("Invalid signature for metadata at {url}: {reason}")]
+    InvalidMetadataSignature {
+        /// Location of the metadata.
+        url: String,
+        /// What is wrong with the signature.
+        reason: String,
+    },
+
+    /// A configured trusted key could not be parsed.
+    #[error("Invalid trusted key: {0}")]
+    InvalidTrustedKey(String),
+
+    /// An SST file in the database directory is corrupt.
+    #[error("Corrupt SST file: {}", path.display())]
+    CorruptSst {
+        /// Path of the SST file.
+        path: PathBuf,
+    },
+
+    /// A write failed because the disk is full.
+    #[error("Insufficient disk space: {0}")]
+    InsufficientDiskSpace(io::Error),
+
+    /// The server answered with a response that doesn't match the request.
+    #[error("Invalid response from {url}: {reason}")]
+    InvalidResponse {
+        /// Requested URL.
+        url: String,
+        /// What is wrong with the response.
+        reason: String,
+    },
+
+    /// The operation was cancelled.
+    #[error("Operation cancelled")]
+    Cancelled,
+
     /// General snapshot download failure.
     #[error("Snapshot download failed: {0}")]
     DownloadFailed(String),
 }
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::StorageFull {
+            SnapshotError::InsufficientDiskSpace(e)
+        } else {
+            SnapshotError::IoError(e)
+        }
+    }
+}
+
+impl SnapshotError {
+    /// Returns true if retrying the failed operation may succeed.
+    ///
+    /// Network failures, timeouts, HTTP 408/429/5xx, inconsistent responses and
+    /// corrupted transfers are transient. Missing objects, denied access, changed
+    /// objects (HTTP 412), malformed data, configuration errors and local I/O
+    /// errors such as a full disk are permanent.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            SnapshotError::ReqwestError(e) => !e.is_builder() && !e.is_redirect(),
+            SnapshotError::HttpStatus { status, .. } => {
+                matches!(status, 408 | 429) || (500..600).contains(status)
+            }
+            SnapshotError::IoError(e) => matches!(
+                e.kind(),
+                io::ErrorKind::InvalidData
+                    | io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::Interrupted
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+            ),
+            SnapshotError::ChecksumMismatch { .. }
+            | SnapshotError::SizeMismatch { .. }
+            | SnapshotError::InvalidResponse { .. } => true,
+            SnapshotError::NotFound(_)
+            | SnapshotError::SerdeJsonError(_)
+            | SnapshotError::MetadataNotFound { .. }
+            | SnapshotError::ShardNotInLocalMetadata { .. }
+            | SnapshotError::NoMatchingSnapshot { .. }
+            | SnapshotError::SnapshotChanged { .. }
+            | SnapshotError::StaleDatabase { .. }
+            | SnapshotError::ListingNotSupported(_)
+            | SnapshotError::UnsignedMetadata { .. }
+            | SnapshotError::InvalidMetadataSignature { .. }
+            | SnapshotError::InvalidTrustedKey(_)
+            | SnapshotError::CorruptSst { .. }
+            | SnapshotError::InsufficientDiskSpace(_)
+            | SnapshotError::Cancelled
+            | SnapshotError::DownloadFailed(_) => false,
+        }
+    }
+
+    /// Returns the delay the server asked for before retrying, if any.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            SnapshotError::HttpStatus { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classification() {
+        let full = SnapshotError::from(io::Error::from(io::ErrorKind::StorageFull));
+        assert!(matches!(full, SnapshotError::InsufficientDiskSpace(_)));
+        assert!(!full.is_transient());
+
+        let mismatch = SnapshotError::ChecksumMismatch {
+            chunk: "chunk_0001.bin".to_string(),
+            expected: "aa".to_string(),
+            actual: "bb".to_string(),
+        };
+        assert!(mismatch.is_transient());
+        let content_range = SnapshotError::InvalidResponse {
+            url: "https://snapshots.example.com/chunk_0001.bin".to_string(),
+            reason: "unexpected Content-Range".to_string(),
+        };
+        assert!(content_range.is_transient());
+        assert!(!SnapshotError::Cancelled.is_transient());
+
+        // Misconfiguration and malformed metadata don't fix themselves
+        let unsupported = SnapshotError::DownloadFailed("Unsupported URL scheme".to_string());
+        assert!(!unsupported.is_transient());
+    }
+}
@@ -1,10 +1,41 @@
 //! Tar archive extraction logic.
 
 use crate::error::SnapshotError;
+use crate::progress::{ProgressEvent, ProgressObserver};
 use crate::sst_verify::verify_sst_magic_number;
 use tar::Archive;
+use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
+/// Checks whether every file in a tar archive exists in `db_dir` with its size.
+///
+/// This is the check extraction uses to skip entries, without the SST magic
+/// number verification, so it only reads the tar headers.
+///
+/// # Arguments
+///
+/// * `tar_filename` - Path to the tar file
+/// * `db_dir` - Directory the tar is extracted to
+///
+/// # Returns
+///
+/// `Ok(true)` if nothing is left to extract, or an error if the tar can't be read.
+pub(crate) fn is_extracted(tar_filename: &str, db_dir: &str) -> Result<bool, SnapshotError> {
+    let mut archive = Archive::new(std::fs::File::open(tar_filename)?);
+    let db_path = std::path::Path::new(db_dir);
+    for entry in archive.entries_with_seek()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_dir() {
+            continue;
+        }
+        let size = std::fs::metadata(db_path.join(entry.path()?)).map(|m| m.len());
+        if size.ok() != Some(entry.header().size()?) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
 /// Extracts a tar archive to a target directory with progress tracking.
 ///
 /// Supports resumable extraction by checking existing files:
@@ -15,8 +46,9 @@ use tracing::{info, warn};
 ///
 /// * `tar_filename` - Path to the tar file
 /// * `db_dir` - Target directory for extraction
-/// * `extract_pb` - Progress bar for visual feedback (should be pre-configured with total length)
-/// * `shard_id` - Shard identifier for logging
+/// * `progress` - Receives an event for every entry written or skipped
+/// * `shard_id` - Shard identifier for progress events
+/// * `cancel` - Stops the extraction before the next entry
 ///
 /// # Returns
 ///
@@ -24,8 +56,9 @@ use tracing::{info, warn};
 pub(crate) fn extract_tar(
     tar_filename: &str,
     db_dir: &str,
-    extract_pb: &indicatif::ProgressBar,
-    _shard_id: u32,
+    progress: &dyn ProgressObserver,
+    shard_id: u32,
+    cancel: &CancellationToken,
 ) -> Result<(), SnapshotError> {
     let file = std::fs::File::open(tar_filename)?;
     let mut archive = Archive::new(file);
@@ -37,7 +70,15 @@ pub(crate) fn extract_tar(
     let mut extracted_count = 0u64;
 
     // Extract entries with progress
-    for (index, entry) in archive.entries()?.enumerate() {
+    for entry in archive.entries()? {
+        // Entries are written whole, so stopping between them leaves no partial files
+        if cancel.is_cancelled() {
+            info!(
+                "⏹️  Extraction stopped after {} entries ({} new, {} skipped)",
+                file_count, extracted_count, skipped_count
+            );
+            return Err(SnapshotError::Cancelled);
+        }
         let mut entry = entry?;
 
         // Extract metadata before checking (to avoid borrow conflicts)
@@ -50,11 +91,17 @@ pub(crate) fn extract_tar(
         let expected_size = entry.header().size()?;
         let is_directory = entry.header().entry_type().is_dir();
 
-        file_count = (index + 1) as u64;
+        file_count += 1;
+        let entry_name = entry_path.to_string_lossy().to_string();
 
         // For directories, always extract (they're lightweight and size doesn't matter)
         if is_directory {
             entry.unpack_in(db_dir)?;
+            progress.on_event(&ProgressEvent::ExtractEntryWritten {
+                shard_id,
+                path: entry_name,
+                size: 0,
+            });
             continue;
         }
 
@@ -68,7 +115,7 @@ pub(crate) fn extract_tar(
                     if actual_size == expected_size {
                         // File size matches, check magic number for .sst files
                         if file_name.ends_with(".sst") {
-                            match verify_sst_magic_number(target_path.to_str().unwrap()) {
+                            match verify_sst_magic_number(&target_path.to_string_lossy()) {
                                 Ok(true) => {
                                     // Magic number valid, file is complete
                                     skipped_count += 1;
@@ -139,24 +186,29 @@ pub(crate) fn extract_tar(
             } else {
                 info!("✅ Extracted {} (size: {} bytes)", file_name, expected_size);
             }
-        }
-
-        // Update progress bar position
-        extract_pb.set_position(file_count);
-
-        // Update message more frequently at the beginning, then every 100 files
-        if index < 10 || index % 100 == 0 {
-            extract_pb.set_message(format!(
-                "| 📂 {} new, {} skipped | {}",
-                extracted_count, skipped_count, file_name
-            ));
+            progress.on_event(&ProgressEvent::ExtractEntryWritten {
+                shard_id,
+                path: entry_name,
+                size: expected_size,
+            });
+        } else {
+            progress.on_event(&ProgressEvent::ExtractEntrySkipped {
+                shard_id,
+                path: entry_name,
+                size: expected_size,
+            });
         }
     }
 
-    extract_pb.finish_with_message(format!(
+    info!(
         "✅ Extracted {} files to {} ({} new, {} skipped)",
         file_count, db_dir, extracted_count, skipped_count
-    ));
+    );
+    progress.on_event(&ProgressEvent::ExtractFinished {
+        shard_id,
+        written: extracted_count,
+        skipped: skipped_count,
+    });
 
     // Log final summary
     if skipped_count > 0 {
@@ -6,11 +6,20 @@
 //! # Features
 //!
 //! - **Resumable Downloads**: Automatically resume interrupted downloads
-//! - **MD5 Verification**: Verify data integrity using ETag/MD5 checksums
-//! - **Multi-Shard Support**: Download multiple shards efficiently
-//! - **Progress Tracking**: Real-time progress reporting
+//! - **Integrity Verification**: Verify chunks with published SHA-256/BLAKE3 digests or ETag/MD5 checksums
+//! - **Signed Metadata**: Refuse metadata not signed by a trusted ed25519 key
+//! - **Multi-Shard Support**: Download multiple shards efficiently, or discover every available shard
+//! - **Progress Tracking**: Typed progress events through a pluggable [`ProgressObserver`]
 //! - **Automatic Retry**: Built-in retry logic for transient failures
 //! - **Stage Control**: Execute download, merge, and extract stages independently
+//! - **Database Verification**: Check a restored RocksDB directory against its MANIFEST
+//! - **Restore Status**: Report how far each shard's restore has come and what runs next
+//! - **Snapshot History**: List older snapshots and pin one to restore instead of the latest
+//! - **Pluggable Sources**: Read from HTTP(S), S3-compatible buckets or a local directory
+//! - **Adaptive Concurrency**: Tune the number of parallel downloads to measured throughput
+//! - **Bandwidth Limiting**: Cap total download throughput, adjustable at runtime or by schedule
+//! - **Streaming Restore**: Download, decompress and unpack in one pass with minimal disk usage
+//! - **Cancellation**: Stop cleanly through a [`CancellationToken`] and resume later
 //!
 //! # Example
 //!
@@ -26,18 +35,46 @@
 //! # }
 //! ```
 
+mod bandwidth;
+mod concurrency;
+mod db_verify;
+mod discovery;
 mod download;
 mod error;
 mod extract;
 mod merge;
 mod metadata;
 mod orchestrator;
+mod progress;
+mod retry;
+mod signature;
+mod source;
 mod sst_verify;
+mod state;
+mod status;
+mod stream;
 mod types;
 mod verify;
 
 // Re-export public API
+pub use bandwidth::{BandwidthLimiter, BandwidthWindow};
+pub use concurrency::AdaptiveConcurrency;
+pub use db_verify::{verify_db, DbIssue, DbReport};
+pub use discovery::{available_shards, discover_networks, discover_shards, ShardInfo};
 pub use error::SnapshotError;
+pub use metadata::list_snapshots;
 pub use orchestrator::download_snapshots;
+pub use progress::{ProgressEvent, ProgressObserver};
+pub use retry::RetryPolicy;
+pub use signature::TrustedKey;
+pub use source::{
+    source_from_url, ByteRange, ByteStream, HttpSource, ListedObject, LocalSource, MirrorSelection,
+    MirrorSource, ObjectInfo, ObjectStream, S3Config, S3Credentials, S3Source, SnapshotSource,
+};
 pub use sst_verify::verify_sst_magic_number;
-pub use types::{DownloadConfig, ExecutionStage};
+pub use status::{restore_status, RestoreStage, ShardStatus};
+pub use tokio_util::sync::CancellationToken;
+pub use types::{
+    ChunkChecksum, DownloadConfig, ExecutionStage, SnapshotChangePolicy, SnapshotMetadata,
+    SnapshotSelector,
+};
@@ -3,10 +3,23 @@
 //! This binary provides a user-friendly CLI for downloading and restoring
 //! RocksDB snapshots from S3/R2 storage.
 
-use clap::{Parser, ValueEnum};
-use snapsync::{download_snapshots, DownloadConfig};
+mod cli_progress;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use cli_progress::IndicatifProgress;
+use snapsync::{
+    available_shards, discover_networks, discover_shards, download_snapshots, list_snapshots,
+    restore_status, verify_db, AdaptiveConcurrency, BandwidthLimiter, BandwidthWindow,
+    CancellationToken, DownloadConfig, MirrorSelection, RetryPolicy, S3Config, S3Credentials,
+    ShardInfo, ShardStatus, SnapshotChangePolicy, SnapshotError, SnapshotSelector, TrustedKey,
+};
 use std::path::PathBuf;
-use tracing::info;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Exit code after an interrupted run, as for a process stopped by SIGINT.
+const EXIT_CANCELLED: i32 = 130;
 
 /// Execution stage for the snapshot download process
 #[derive(Debug, Clone, ValueEnum)]
@@ -19,6 +32,63 @@ enum Stage {
     Merge,
     /// Only extract tar to RocksDB directory (requires merged tar)
     Extract,
+    /// Download, decompress and extract in a single pass without an intermediate tar
+    Stream,
+}
+
+/// Order in which snapshot mirrors are tried
+#[derive(Debug, Clone, ValueEnum)]
+enum Selection {
+    /// Try mirrors in the order given (--snapshot-url first)
+    Priority,
+    /// Try the mirror with the highest measured throughput first
+    Fastest,
+}
+
+/// What to do when latest.json moved on to another snapshot since the last run
+#[derive(Debug, Clone, ValueEnum)]
+enum SnapshotChange {
+    /// Finish restoring the previous snapshot and pin it
+    Resume,
+    /// Discard the files of the previous snapshot and restore the new one
+    Restart,
+    /// Stop with an error
+    Fail,
+}
+
+/// A `--shards` value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShardArg {
+    /// Every shard the snapshot source provides for the network
+    All,
+    /// A single shard
+    Id(u32),
+}
+
+/// Commands other than restoring snapshots
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List the networks and shards available from the snapshot source, with the
+    /// timestamp, chunk count and size of each shard's latest snapshot
+    List {
+        /// Networks to list (default: every network found on the source)
+        networks: Vec<String>,
+    },
+    /// Report the local restore state of each shard (default or "all": every shard in
+    /// the temp directory's metadata.json) and the stage to run next
+    Status {
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check a restored RocksDB directory (or a directory of shard databases): CURRENT,
+    /// the MANIFEST, the size of every referenced SST file and every SST footer.
+    /// Exits non-zero if files are missing, extra or corrupt
+    Verify {
+        /// RocksDB directory to verify
+        #[arg(long, default_value = ".rocks")]
+        db: PathBuf,
+    },
 }
 
 /// SnapSync - RocksDB Snapshot Downloader
@@ -27,78 +97,583 @@ enum Stage {
 #[command(about = "Download and restore RocksDB snapshots from S3/R2", long_about = None)]
 #[command(version)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Network name (FARCASTER_NETWORK_MAINNET, FARCASTER_NETWORK_TESTNET, FARCASTER_NETWORK_DEVNET)
-    #[arg(short, long, default_value = "FARCASTER_NETWORK_MAINNET")]
+    #[arg(
+        short,
+        long,
+        global = true,
+        default_value = "FARCASTER_NETWORK_MAINNET"
+    )]
     network: String,
 
-    /// Shard IDs to download (comma-separated, e.g., "0,1")
-    #[arg(short, long, value_delimiter = ',')]
-    shards: Vec<u32>,
+    /// Shard IDs to download (comma-separated, e.g., "0,1"), or "all" for every available shard
+    #[arg(short, long, global = true, value_delimiter = ',', value_parser = parse_shard)]
+    shards: Vec<ShardArg>,
+
+    /// Snapshot to restore instead of the latest: "latest", a key_base, "at:<time>" or
+    /// "before:<time>" (time: Unix timestamp, RFC 3339 date or duration ago like "2d").
+    /// The choice is pinned for later runs until another --snapshot is given
+    #[arg(long)]
+    snapshot: Option<SnapshotSelector>,
+
+    /// What to do when latest.json points to another snapshot than the previous run restored
+    #[arg(long, default_value = "restart")]
+    on_snapshot_change: SnapshotChange,
+
+    /// Delete a shard's restored database when its snapshot changes, instead of stopping
+    #[arg(long)]
+    clear_db_on_snapshot_change: bool,
+
+    /// List the available snapshots of the requested shards and exit
+    #[arg(long)]
+    list_snapshots: bool,
 
     /// Output directory for RocksDB data
-    #[arg(short, long, default_value = ".rocks")]
+    #[arg(short, long, global = true, default_value = ".rocks")]
     output: PathBuf,
 
-    /// Snapshot download base URL
+    /// Snapshot download base URL (http(s)://, s3://bucket/prefix, file:///path or a local path)
     #[arg(
         long,
+        global = true,
         default_value = "https://pub-d352dd8819104a778e20d08888c5a661.r2.dev"
     )]
     snapshot_url: String,
 
+    /// Additional mirror URL, tried when --snapshot-url fails (repeatable)
+    #[arg(long = "mirror", global = true)]
+    mirrors: Vec<String>,
+
+    /// Order in which --snapshot-url and mirrors are tried
+    #[arg(long, global = true, default_value = "priority")]
+    mirror_selection: Selection,
+
+    /// Endpoint for s3:// snapshot URLs (default: AWS S3 for the region)
+    #[arg(long, global = true)]
+    s3_endpoint: Option<String>,
+
+    /// Region for s3:// snapshot URLs (use "auto" for R2)
+    #[arg(long, global = true, default_value = "us-east-1")]
+    s3_region: String,
+
+    /// Use path-style addressing for s3:// snapshot URLs (needed by most MinIO setups)
+    #[arg(long, global = true)]
+    s3_path_style: bool,
+
+    /// Access key ID for s3:// snapshot URLs (default: AWS_ACCESS_KEY_ID or the profile)
+    #[arg(long, global = true, requires = "s3_secret_access_key")]
+    s3_access_key_id: Option<String>,
+
+    /// Secret access key for s3:// snapshot URLs (default: AWS_SECRET_ACCESS_KEY or the profile)
+    #[arg(long, global = true, requires = "s3_access_key_id")]
+    s3_secret_access_key: Option<String>,
+
+    /// Profile in ~/.aws/credentials for s3:// snapshot URLs (default: AWS_PROFILE or "default")
+    #[arg(long, global = true)]
+    s3_profile: Option<String>,
+
     /// Temporary download directory
-    #[arg(long, default_value = ".rocks.snapshot")]
+    #[arg(long, global = true, default_value = ".rocks.snapshot")]
     temp_dir: String,
 
     /// Number of concurrent downloads (default: 4)
-    #[arg(short, long, default_value = "4")]
+    #[arg(short, long, global = true, default_value = "4")]
     workers: usize,
 
+    /// Adapt the number of concurrent downloads to measured throughput, starting at --workers
+    #[arg(long)]
+    adaptive_workers: bool,
+
+    /// Lower bound for --adaptive-workers
+    #[arg(long, default_value = "1", requires = "adaptive_workers")]
+    min_workers: usize,
+
+    /// Upper bound for --adaptive-workers
+    #[arg(long, default_value = "32", requires = "adaptive_workers")]
+    max_workers: usize,
+
     /// Skip all verification, trust existing files completely (use with caution)
     #[arg(long)]
     skip_verify: bool,
 
+    /// Ignore the local download state and fully re-verify existing chunks
+    #[arg(long, conflicts_with = "skip_verify")]
+    reverify: bool,
+
+    /// Part size the snapshot's multipart uploads were made with (e.g. "8MiB"; default: inferred)
+    #[arg(long, value_parser = parse_byte_size)]
+    multipart_part_size: Option<usize>,
+
+    /// Ed25519 public keys (hex or base64, comma-separated) trusted to sign the metadata.
+    /// When set, metadata without a valid latest.json.sig signature is refused
+    #[arg(long, global = true, value_delimiter = ',', value_parser = parse_trusted_key)]
+    trusted_key: Vec<TrustedKey>,
+
+    /// Limit on total download throughput (e.g. "200MiB/s"; default: unlimited).
+    /// Send SIGUSR1 to halve and SIGUSR2 to double the limit while running
+    #[arg(long, value_parser = parse_bandwidth)]
+    max_bandwidth: Option<u64>,
+
+    /// Daily UTC bandwidth windows overriding --max-bandwidth
+    /// (e.g. "08:00-20:00=50MiB/s,20:00-08:00=unlimited")
+    #[arg(long, value_delimiter = ',', value_parser = parse_bandwidth_window)]
+    bandwidth_schedule: Vec<BandwidthWindow>,
+
+    /// Maximum attempts per chunk, including the first one
+    #[arg(long, default_value = "10")]
+    retry_max_attempts: usize,
+
+    /// Give up retrying a chunk after this long (e.g. "15m", "1h")
+    #[arg(long, default_value = "15m", value_parser = humantime::parse_duration)]
+    retry_max_elapsed: Duration,
+
+    /// Delay before the first retry, doubled on each further retry (e.g. "1s")
+    #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+    retry_initial_delay: Duration,
+
+    /// Upper bound for a single retry delay (e.g. "60s")
+    #[arg(long, default_value = "60s", value_parser = humantime::parse_duration)]
+    retry_max_delay: Duration,
+
+    /// Memory ceiling for decompressed data while merging (e.g. "256MiB", "1GiB")
+    #[arg(long, default_value = "256MiB", value_parser = parse_byte_size)]
+    merge_memory: usize,
+
     /// Stage to execute (default: all)
     #[arg(long, default_value = "all")]
     stage: Stage,
 
     /// Verbose logging
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     verbose: bool,
 }
 
+/// Parses a human-readable byte size such as "512MiB", "2GB" or "1048576".
+fn parse_byte_size(value: &str) -> Result<usize, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", value))?;
+    let multiplier: f64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "k" | "kb" => 1e3,
+        "m" | "mb" => 1e6,
+        "g" | "gb" => 1e9,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        other => return Err(format!("unknown size unit '{}'", other)),
+    };
+    Ok((number * multiplier) as usize)
+}
+
+/// Parses a shard ID or "all".
+fn parse_shard(value: &str) -> Result<ShardArg, String> {
+    match value.trim() {
+        "all" => Ok(ShardArg::All),
+        id => id
+            .parse()
+            .map(ShardArg::Id)
+            .map_err(|_| format!("invalid shard '{}', expected a shard ID or \"all\"", id)),
+    }
+}
+
+/// Parses a hex or base64 encoded ed25519 public key.
+fn parse_trusted_key(value: &str) -> Result<TrustedKey, String> {
+    value.parse().map_err(|e: SnapshotError| e.to_string())
+}
+
+/// Parses a bandwidth such as "200MiB/s". Zero is refused rather than meaning unlimited.
+fn parse_bandwidth(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    match parse_byte_size(value.strip_suffix("/s").unwrap_or(value))? {
+        0 => Err(format!(
+            "bandwidth '{}' is below 1 byte/s; omit the limit (or use \"unlimited\" in a schedule) for no limit",
+            value
+        )),
+        bytes => Ok(bytes as u64),
+    }
+}
+
+/// Parses a schedule window such as "08:00-20:00=50MiB/s" or "20:00-08:00=unlimited".
+fn parse_bandwidth_window(window: &str) -> Result<BandwidthWindow, String> {
+    let parse_time = |time: &str| -> Result<u32, String> {
+        let (hours, minutes) = time
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| format!("invalid time '{}', expected HH:MM", time))?;
+        let hours: u32 = hours
+            .parse()
+            .map_err(|_| format!("invalid time '{}'", time))?;
+        let minutes: u32 = minutes
+            .parse()
+            .map_err(|_| format!("invalid time '{}'", time))?;
+        if hours > 24 || minutes > 59 || (hours == 24 && minutes > 0) {
+            return Err(format!("invalid time '{}'", time));
+        }
+        Ok(hours * 3600 + minutes * 60)
+    };
+
+    let invalid = || format!("invalid window '{}', expected HH:MM-HH:MM=RATE", window);
+    let (times, rate) = window.split_once('=').ok_or_else(invalid)?;
+    let (start, end) = times.split_once('-').ok_or_else(invalid)?;
+    let bytes_per_second = match rate.trim() {
+        "unlimited" => None,
+        rate => Some(parse_bandwidth(rate)?),
+    };
+    Ok(BandwidthWindow {
+        start: parse_time(start)?,
+        end: parse_time(end)?,
+        bytes_per_second,
+    })
+}
+
+/// Halves the bandwidth limit on SIGUSR1 and doubles it on SIGUSR2.
+#[cfg(unix)]
+fn spawn_bandwidth_signal_handler(limiter: BandwidthLimiter) -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut halve = signal(SignalKind::user_defined1())?;
+    let mut double = signal(SignalKind::user_defined2())?;
+    tokio::spawn(async move {
+        loop {
+            let factor = tokio::select! {
+                _ = halve.recv() => 0.5,
+                _ = double.recv() => 2.0,
+            };
+            match limiter.rate() {
+                Some(rate) => {
+                    let rate = ((rate as f64 * factor) as u64).max(1);
+                    limiter.set_rate(Some(rate));
+                    info!(
+                        "🚦 Bandwidth limit set to {}/s",
+                        indicatif::HumanBytes(rate)
+                    );
+                }
+                None => info!("🚦 Bandwidth is unlimited, ignoring signal"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Cancels the restore on the first SIGINT/SIGTERM and exits right away on the second.
+fn spawn_shutdown_signal_handler(cancel: CancellationToken) -> std::io::Result<()> {
+    #[cfg(unix)]
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+
+    tokio::spawn(async move {
+        for attempt in 0.. {
+            #[cfg(unix)]
+            let signal = tokio::select! {
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+                _ = terminate.recv() => "SIGTERM",
+            };
+            #[cfg(not(unix))]
+            let signal = match tokio::signal::ctrl_c().await {
+                Ok(()) => "Ctrl-C",
+                Err(_) => return,
+            };
+
+            if attempt > 0 {
+                eprintln!("❌ Received {} again, exiting immediately", signal);
+                std::process::exit(EXIT_CANCELLED);
+            }
+            warn!(
+                "⏹️  Received {}, finishing in-flight work (press Ctrl-C again to force exit)",
+                signal
+            );
+            cancel.cancel();
+        }
+    });
+    Ok(())
+}
+
+/// Formats a Unix timestamp as an RFC 3339 date.
+fn format_timestamp(timestamp: i64) -> String {
+    let time = std::time::UNIX_EPOCH + Duration::from_secs(u64::try_from(timestamp).unwrap_or(0));
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+/// Prints the snapshots of a shard, newest first.
+fn print_snapshots(shard_id: u32, snapshots: &[snapsync::SnapshotMetadata]) {
+    println!("Shard {}: {} snapshot(s)", shard_id, snapshots.len());
+    for snapshot in snapshots {
+        println!(
+            "  {}  {:>6} chunks  {}",
+            format_timestamp(snapshot.timestamp),
+            snapshot.chunks.len(),
+            snapshot.key_base
+        );
+    }
+}
+
+/// Prints the latest snapshot of every shard of a network.
+fn print_shards(network: &str, shards: &[ShardInfo]) {
+    println!("{}: {} shard(s)", network, shards.len());
+    for shard in shards {
+        let size = shard.total_size.map_or_else(
+            || "unknown size".to_string(),
+            |size| indicatif::HumanBytes(size).to_string(),
+        );
+        println!(
+            "  Shard {:<3}  {}  {:>6} chunks  {:>12}  {}",
+            shard.shard_id,
+            format_timestamp(shard.timestamp),
+            shard.chunks,
+            size,
+            shard.key_base
+        );
+    }
+}
+
+/// Prints the restore state of each shard as a table.
+fn print_status(statuses: &[ShardStatus]) {
+    if statuses.is_empty() {
+        println!("No shards in the local metadata, run a download first");
+        return;
+    }
+    println!(
+        "{:<6} {:>9} {:>9} {:>9} {:<9} {:<10} {:<9} SNAPSHOT",
+        "SHARD", "CHUNKS", "VERIFIED", "MERGED", "TAR", "DB", "NEXT"
+    );
+    for status in statuses {
+        let Some(ref key_base) = status.key_base else {
+            println!(
+                "{:<6} {:>9} {:>9} {:>9} {:<9} {:<10} {:<9} (no metadata)",
+                status.shard_id, "-", "-", "-", "-", "-", status.next_stage
+            );
+            continue;
+        };
+        let count = |n: usize| format!("{}/{}", n, status.chunks);
+        println!(
+            "{:<6} {:>9} {:>9} {:>9} {:<9} {:<10} {:<9} {}{}",
+            status.shard_id,
+            count(status.chunks_present),
+            count(status.chunks_verified),
+            count(status.merged_chunks),
+            match (status.tar_complete, status.merged_chunks) {
+                (true, _) => "complete",
+                (false, 0) => "missing",
+                (false, _) => "partial",
+            },
+            if status.extracted {
+                "extracted"
+            } else {
+                "missing"
+            },
+            status.next_stage,
+            key_base,
+            if status.pinned { " (pinned)" } else { "" }
+        );
+    }
+}
+
+/// Lists the shards of the given networks, or of every network on the source.
+async fn list_shards(config: &DownloadConfig, networks: Vec<String>) -> Result<(), SnapshotError> {
+    let networks = if networks.is_empty() {
+        discover_networks(config).await?
+    } else {
+        networks
+    };
+    if networks.is_empty() {
+        println!("No snapshots found");
+    }
+    for network in networks {
+        let config = DownloadConfig {
+            network: network.clone(),
+            ..config.clone()
+        };
+        print_shards(&network, &discover_shards(&config).await?);
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
-    // Initialize tracing
+    // Initialize tracing; logs go to stderr so that stdout stays parseable (e.g. status --json)
     let log_level = if args.verbose { "debug" } else { "info" };
     tracing_subscriber::fmt()
         .with_env_filter(format!("snapsync={}", log_level))
+        .with_writer(std::io::stderr)
         .init();
 
     info!("🚀 SnapSync - RocksDB Snapshot Downloader");
     info!("Network: {}", args.network);
-    info!("Shards: {:?}", args.shards);
-    info!("Output directory: {:?}", args.output);
 
-    // Validate shard IDs
-    if args.shards.is_empty() {
-        eprintln!("Error: At least one shard ID must be specified");
-        std::process::exit(1);
-    }
-
-    // Create output directory if it doesn't exist
-    std::fs::create_dir_all(&args.output)?;
+    let bandwidth_limiter = BandwidthLimiter::new(args.max_bandwidth);
+    bandwidth_limiter.set_schedule(args.bandwidth_schedule);
+    #[cfg(unix)]
+    spawn_bandwidth_signal_handler(bandwidth_limiter.clone())?;
+    let cancel = CancellationToken::new();
 
     let config = DownloadConfig {
         snapshot_download_url: args.snapshot_url,
+        snapshot_mirror_urls: args.mirrors,
+        mirror_selection: match args.mirror_selection {
+            Selection::Priority => MirrorSelection::Priority,
+            Selection::Fastest => MirrorSelection::Fastest,
+        },
         snapshot_download_dir: args.temp_dir,
         network: args.network,
         max_concurrent_downloads: args.workers,
+        adaptive_concurrency: args.adaptive_workers.then_some(AdaptiveConcurrency {
+            min: args.min_workers,
+            max: args.max_workers,
+        }),
         skip_verify: args.skip_verify,
+        reverify: args.reverify,
+        multipart_part_size: args.multipart_part_size.map(|size| size as u64),
+        trusted_keys: args.trusted_key,
+        snapshot: args.snapshot,
+        on_snapshot_change: match args.on_snapshot_change {
+            SnapshotChange::Resume => SnapshotChangePolicy::Resume,
+            SnapshotChange::Restart => SnapshotChangePolicy::Restart,
+            SnapshotChange::Fail => SnapshotChangePolicy::Fail,
+        },
+        clear_db_on_snapshot_change: args.clear_db_on_snapshot_change,
+        bandwidth_limiter,
+        retry_policy: RetryPolicy {
+            initial_delay: args.retry_initial_delay,
+            max_delay: args.retry_max_delay,
+            max_attempts: args.retry_max_attempts,
+            max_elapsed: args.retry_max_elapsed,
+            jitter: true,
+        },
+        merge_memory_limit: args.merge_memory,
+        s3: S3Config {
+            endpoint: args.s3_endpoint,
+            region: args.s3_region,
+            path_style: args.s3_path_style,
+            credentials: args.s3_access_key_id.zip(args.s3_secret_access_key).map(
+                |(access_key_id, secret_access_key)| S3Credentials {
+                    access_key_id,
+                    secret_access_key,
+                    session_token: None,
+                },
+            ),
+            profile: args.s3_profile,
+        },
+        source: None,
+        progress: Some(Arc::new(IndicatifProgress::new())),
+        cancel,
     };
 
+    if let Some(Command::List { networks }) = args.command {
+        if let Err(e) = list_shards(&config, networks).await {
+            eprintln!("❌ Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Status { json }) = args.command {
+        // No shard IDs report every shard in the local metadata.json, so does "all"
+        let shard_ids: Vec<u32> = if args.shards.contains(&ShardArg::All) {
+            Vec::new()
+        } else {
+            args.shards
+                .iter()
+                .filter_map(|shard| match shard {
+                    ShardArg::Id(id) => Some(*id),
+                    ShardArg::All => None,
+                })
+                .collect()
+        };
+        let db_dir = args.output.to_str().unwrap();
+        match restore_status(&config, db_dir, &shard_ids) {
+            Ok(statuses) if json => println!("{}", serde_json::to_string_pretty(&statuses)?),
+            Ok(statuses) => print_status(&statuses),
+            Err(e) => {
+                eprintln!("❌ Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Verify { db }) = args.command {
+        match verify_db(&db) {
+            Ok(report) => {
+                for issue in &report.issues {
+                    println!("{}", issue);
+                }
+                println!(
+                    "{} {} database(s), {} SST file(s) checked, {} problem(s)",
+                    if report.is_ok() { "✅" } else { "❌" },
+                    report.databases.len(),
+                    report.files_checked,
+                    report.issues.len()
+                );
+                if !report.is_ok() {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("❌ Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    let shard_ids = if args.shards.contains(&ShardArg::All) {
+        match available_shards(&config).await {
+            Ok(shard_ids) => shard_ids,
+            Err(e) => {
+                eprintln!("❌ Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        args.shards
+            .iter()
+            .filter_map(|shard| match shard {
+                ShardArg::Id(id) => Some(*id),
+                ShardArg::All => None,
+            })
+            .collect()
+    };
+    info!("Shards: {:?}", shard_ids);
+    info!("Output directory: {:?}", args.output);
+
+    // Validate shard IDs
+    if shard_ids.is_empty() {
+        if args.shards.is_empty() {
+            eprintln!("Error: At least one shard ID must be specified");
+        } else {
+            eprintln!("Error: No shards available for {}", config.network);
+        }
+        std::process::exit(1);
+    }
+
+    if args.list_snapshots {
+        for &shard_id in &shard_ids {
+            match list_snapshots(&config, shard_id).await {
+                Ok(snapshots) => print_snapshots(shard_id, &snapshots),
+                Err(e) => {
+                    eprintln!("❌ Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // The commands above exit on the first Ctrl-C, the restore stops gracefully
+    spawn_shutdown_signal_handler(config.cancel.clone())?;
+
+    // Create output directory if it doesn't exist
+    std::fs::create_dir_all(&args.output)?;
     let db_dir = args.output.to_str().unwrap().to_string();
 
     // Convert CLI stage to execution stage
@@ -107,16 +682,66 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Stage::Download => snapsync::ExecutionStage::DownloadOnly,
         Stage::Merge => snapsync::ExecutionStage::MergeOnly,
         Stage::Extract => snapsync::ExecutionStage::ExtractOnly,
+        Stage::Stream => snapsync::ExecutionStage::Stream,
     };
 
-    match download_snapshots(&config, db_dir, args.shards, execution_stage).await {
+    match download_snapshots(&config, db_dir, shard_ids, execution_stage).await {
         Ok(_) => {
             info!("✅ Snapshot download and restore completed successfully!");
             Ok(())
         }
+        Err(SnapshotError::Cancelled) => {
+            eprintln!("⏹️  Cancelled, run the same command again to resume");
+            std::process::exit(exit_code(&SnapshotError::Cancelled));
+        }
         Err(e) => {
             eprintln!("❌ Error: {}", e);
-            std::process::exit(1);
+            std::process::exit(exit_code(&e));
         }
     }
 }
+
+/// Returns the exit code for a failed restore.
+fn exit_code(error: &SnapshotError) -> i32 {
+    match error {
+        SnapshotError::Cancelled => EXIT_CANCELLED,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use snapsync::ExecutionStage;
+
+    #[test]
+    fn test_zero_bandwidth_is_rejected() {
+        assert_eq!(parse_bandwidth("50MiB/s"), Ok(50 * 1024 * 1024));
+        assert!(parse_bandwidth("0").is_err());
+        assert!(parse_bandwidth("0.1/s").is_err());
+        assert!(parse_bandwidth_window("08:00-20:00=0MiB/s").is_err());
+        assert_eq!(
+            parse_bandwidth_window("20:00-08:00=unlimited").map(|w| w.bytes_per_second),
+            Ok(None)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_restore_exits_with_130() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = DownloadConfig {
+            snapshot_download_url: dir.path().join("source").to_str().unwrap().to_string(),
+            snapshot_download_dir: dir.path().join("snapshot").to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        config.cancel.cancel();
+
+        let db_dir = dir.path().join("db").to_str().unwrap().to_string();
+        let error = download_snapshots(&config, db_dir, vec![0], ExecutionStage::All)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, SnapshotError::Cancelled));
+        assert_eq!(exit_code(&error), EXIT_CANCELLED);
+        assert_eq!(exit_code(&SnapshotError::NotFound("chunk".to_string())), 1);
+    }
+}
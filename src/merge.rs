@@ -1,94 +1,574 @@
 //! Chunk merging and decompression logic.
 
 use crate::error::SnapshotError;
+use crate::progress::{ProgressEvent, ProgressObserver};
 use flate2::read::GzDecoder;
-use std::io::Read;
+use md5::Digest;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{Read, Seek};
 use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Size of the blocks decompressed data is handed over in.
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+/// First line of a merge journal, naming the snapshot the tar belongs to.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct MergeJournalHeader {
+    /// Base path of the snapshot whose chunks are merged.
+    key_base: String,
+}
+
+/// Journal line recorded after a chunk has been fully written to the tar.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct MergeCheckpoint {
+    /// Position of the chunk in the snapshot's chunk list.
+    index: usize,
+    /// Chunk file name, to detect a journal from a different chunk list.
+    chunk: String,
+    /// Tar length after this chunk was written.
+    offset: u64,
+    /// MD5 of the decompressed bytes this chunk contributed.
+    md5: String,
+}
+
+/// Returns the path of the tar a shard's chunks are merged into.
+pub(crate) fn tar_path(snapshot_dir: &str, shard_id: u32) -> String {
+    format!("{}/shard_{}_snapshot.tar", snapshot_dir, shard_id)
+}
+
+/// Returns the path of the merge journal kept next to a tar file.
+pub(crate) fn journal_path(tar_filename: &str) -> String {
+    format!("{}.journal", tar_filename)
+}
+
+fn chunk_file_name(path: &str) -> &str {
+    std::path::Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("")
+}
+
+/// Reads the checkpoints matching `key_base` and `local_chunks`, stopping at the
+/// first unreadable or inconsistent line (e.g. one cut short by a crash).
+///
+/// A journal of another snapshot, whose chunks may have the same names, yields
+/// no checkpoints.
+fn load_checkpoints(
+    tar_filename: &str,
+    key_base: &str,
+    local_chunks: &[String],
+) -> Vec<MergeCheckpoint> {
+    let Ok(content) = std::fs::read_to_string(journal_path(tar_filename)) else {
+        return Vec::new();
+    };
+    let mut lines = content.lines();
+    let same_snapshot = lines
+        .next()
+        .and_then(|line| serde_json::from_str::<MergeJournalHeader>(line).ok())
+        .is_some_and(|header| header.key_base == key_base);
+    if !same_snapshot {
+        return Vec::new();
+    }
+
+    let mut checkpoints: Vec<MergeCheckpoint> = Vec::new();
+    for line in lines {
+        let Ok(checkpoint) = serde_json::from_str::<MergeCheckpoint>(line) else {
+            break;
+        };
+        let previous_offset = checkpoints.last().map_or(0, |c| c.offset);
+        let consistent = checkpoint.index == checkpoints.len()
+            && checkpoint.offset >= previous_offset
+            && local_chunks
+                .get(checkpoint.index)
+                .is_some_and(|path| chunk_file_name(path) == checkpoint.chunk);
+        if !consistent {
+            break;
+        }
+        checkpoints.push(checkpoint);
+    }
+    checkpoints
+}
+
+/// Returns the number of chunks the journal records as merged whose data is in the tar.
+///
+/// Unlike a resumed merge, the data is not re-hashed.
+pub(crate) fn merged_chunks(tar_filename: &str, key_base: &str, local_chunks: &[String]) -> usize {
+    let tar_len = std::fs::metadata(tar_filename).map_or(0, |metadata| metadata.len());
+    load_checkpoints(tar_filename, key_base, local_chunks)
+        .iter()
+        .take_while(|checkpoint| checkpoint.offset <= tar_len)
+        .count()
+}
+
+/// Computes the MD5 of the tar bytes in `start..end`.
+fn hash_tar_range(tar_filename: &str, start: u64, end: u64) -> Result<String, SnapshotError> {
+    let mut file = std::fs::File::open(tar_filename)?;
+    file.seek(std::io::SeekFrom::Start(start))?;
+    let mut reader = std::io::BufReader::with_capacity(4 * 1024 * 1024, file).take(end - start);
+
+    let mut hasher = md5::Md5::new();
+    let mut buffer = vec![0u8; BLOCK_SIZE];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Finds the last checkpoint whose data is fully present and intact in the tar,
+/// then truncates the tar and the journal to it.
+///
+/// Returns the checkpoints that are still valid.
+fn restore_checkpoints(
+    tar_filename: &str,
+    key_base: &str,
+    local_chunks: &[String],
+) -> Result<Vec<MergeCheckpoint>, SnapshotError> {
+    let mut checkpoints = load_checkpoints(tar_filename, key_base, local_chunks);
+    let tar_len = match std::fs::metadata(tar_filename) {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+
+    // Walk back until the last checkpoint's bytes are on disk and match their hash
+    while let Some(last) = checkpoints.last() {
+        let start = checkpoints
+            .len()
+            .checked_sub(2)
+            .map_or(0, |i| checkpoints[i].offset);
+        if last.offset <= tar_len && hash_tar_range(tar_filename, start, last.offset)? == last.md5 {
+            break;
+        }
+        warn!(
+            "Merge checkpoint for chunk {} is not intact, rolling back",
+            last.index + 1
+        );
+        checkpoints.pop();
+    }
+
+    let good_offset = checkpoints.last().map_or(0, |c| c.offset);
+    if tar_len != good_offset {
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(tar_filename)?;
+        file.set_len(good_offset)?;
+    }
+
+    std::fs::write(
+        journal_path(tar_filename),
+        journal_content(key_base, &checkpoints)?,
+    )?;
+
+    Ok(checkpoints)
+}
+
+/// Serializes a journal: the header followed by one line per checkpoint.
+fn journal_content(
+    key_base: &str,
+    checkpoints: &[MergeCheckpoint],
+) -> Result<String, SnapshotError> {
+    let header = MergeJournalHeader {
+        key_base: key_base.to_string(),
+    };
+    let mut journal = serde_json::to_string(&header)?;
+    journal.push('\n');
+    for checkpoint in checkpoints {
+        journal.push_str(&serde_json::to_string(checkpoint)?);
+        journal.push('\n');
+    }
+    Ok(journal)
+}
+
+/// A chunk being decompressed ahead of the writer.
+struct PendingChunk {
+    index: usize,
+    task: tokio::task::JoinHandle<Result<(), SnapshotError>>,
+    receiver: mpsc::Receiver<Vec<u8>>,
+}
+
+/// Decompresses one chunk file into fixed-size blocks sent over `sender`.
+///
+/// Blocks when the channel is full, so a chunk is never held in memory as a whole.
+fn decompress_chunk_blocks(
+    filename: &str,
+    sender: mpsc::Sender<Vec<u8>>,
+) -> Result<(), SnapshotError> {
+    let file = std::fs::File::open(filename).map_err(SnapshotError::IoError)?;
+    let reader = std::io::BufReader::with_capacity(4 * 1024 * 1024, file);
+    let mut gz_decoder = GzDecoder::new(reader);
+
+    loop {
+        let mut block = vec![0u8; BLOCK_SIZE];
+        let mut filled = 0;
+        while filled < BLOCK_SIZE {
+            let n = gz_decoder
+                .read(&mut block[filled..])
+                .map_err(SnapshotError::IoError)?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        if filled == 0 {
+            return Ok(());
+        }
+        block.truncate(filled);
+        if sender.blocking_send(block).is_err() {
+            // The writer gave up, nothing left to do
+            return Ok(());
+        }
+        if filled < BLOCK_SIZE {
+            return Ok(());
+        }
+    }
+}
 
 /// Merges and decompresses chunk files into a single tar archive.
 ///
-/// Uses a sliding window approach for parallel decompression to control memory usage.
+/// Uses a sliding window approach for parallel decompression. Each chunk is
+/// streamed to the writer through a bounded channel of fixed-size blocks, so
+/// the decompressed data held in memory stays around `memory_limit` bytes no
+/// matter how large the chunks are.
+///
+/// After each chunk is written, a checkpoint with the tar offset and the MD5 of
+/// the chunk's decompressed bytes is appended to `<tar>.journal`. An interrupted
+/// merge truncates the tar to the last intact checkpoint and continues from the
+/// next chunk instead of starting over. The journal names the snapshot, and a
+/// tar left by another snapshot is merged again from the start.
 ///
 /// # Arguments
 ///
+/// * `key_base` - Base path of the snapshot the chunks belong to
 /// * `local_chunks` - List of chunk file paths to merge
 /// * `tar_filename` - Output tar file path
-/// * `merge_pb` - Progress bar for visual feedback
+/// * `progress` - Receives merge progress events
 /// * `shard_id` - Shard identifier for logging
+/// * `memory_limit` - Approximate ceiling for buffered decompressed data in bytes
+/// * `cancel` - Stops the merge once the chunk being written is checkpointed
 ///
 /// # Returns
 ///
 /// `Ok(())` on success, or an error if merging fails.
 pub(crate) async fn merge_chunks(
+    key_base: &str,
     local_chunks: &[String],
     tar_filename: &str,
-    merge_pb: &indicatif::ProgressBar,
+    progress: &dyn ProgressObserver,
     shard_id: u32,
+    memory_limit: usize,
+    cancel: &CancellationToken,
 ) -> Result<(), SnapshotError> {
-    let mut tar_file = BufWriter::new(tokio::fs::File::create(tar_filename).await?);
+    let checkpoints = tokio::task::spawn_blocking({
+        let tar_filename = tar_filename.to_string();
+        let key_base = key_base.to_string();
+        let local_chunks = local_chunks.to_vec();
+        move || restore_checkpoints(&tar_filename, &key_base, &local_chunks)
+    })
+    .await
+    .map_err(|e| {
+        SnapshotError::IoError(std::io::Error::other(format!("Task join error: {}", e)))
+    })??;
+
+    let resume_index = checkpoints.len();
+    let mut tar_offset = checkpoints.last().map_or(0, |c| c.offset);
+    progress.on_event(&ProgressEvent::MergeStarted {
+        shard_id,
+        total_chunks: local_chunks.len(),
+        resumed_chunks: resume_index,
+    });
+    if resume_index == local_chunks.len() && resume_index > 0 {
+        info!(
+            "✅ Shard {} already merged ({} chunks)",
+            shard_id, resume_index
+        );
+        progress.on_event(&ProgressEvent::MergeFinished {
+            shard_id,
+            total_chunks: resume_index,
+        });
+        return Ok(());
+    }
+    if resume_index > 0 {
+        info!(
+            "⏩ Resuming merge of shard {} at chunk {}/{}",
+            shard_id,
+            resume_index + 1,
+            local_chunks.len()
+        );
+    }
+
+    let file = tokio::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(tar_filename)
+        .await?;
+    let mut tar_file = BufWriter::new(file);
+    let mut journal = tokio::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(journal_path(tar_filename))
+        .await?;
 
     // Use sliding window for parallel decompression with controlled memory
     let total_files = local_chunks.len();
-    // Auto-detect CPU cores for optimal merge performance
+    let total_blocks = (memory_limit / BLOCK_SIZE).max(1);
+    // Auto-detect CPU cores for optimal merge performance, bounded by the memory budget
     let window_size = std::thread::available_parallelism()
         .map(|n| n.get())
-        .unwrap_or(4);
+        .unwrap_or(4)
+        .min(total_blocks);
+    let blocks_per_chunk = (total_blocks / window_size).max(1);
 
-    let mut current_index = 0;
-    let mut pending_tasks: Vec<tokio::task::JoinHandle<Result<Vec<u8>, SnapshotError>>> =
-        Vec::new();
+    let mut current_index = resume_index;
+    let mut pending_tasks: VecDeque<PendingChunk> = VecDeque::new();
 
     while current_index < total_files || !pending_tasks.is_empty() {
+        // Everything written so far is checkpointed, stop here if asked to
+        if cancel.is_cancelled() {
+            tar_file.flush().await?;
+            info!(
+                "⏹️  Merge of shard {} stopped at chunk {}/{}",
+                shard_id,
+                pending_tasks.front().map_or(current_index, |p| p.index),
+                total_files
+            );
+            return Err(SnapshotError::Cancelled);
+        }
+
         // Spawn new tasks up to window size
         while pending_tasks.len() < window_size && current_index < total_files {
             let filename = local_chunks[current_index].clone();
-            let index = current_index;
-            let merge_pb_clone = merge_pb.clone();
-
-            let task = tokio::task::spawn_blocking(move || {
-                let chunk_name = std::path::Path::new(&filename)
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("");
-
-                merge_pb_clone.set_message(format!(
-                    "| 🔄 Decompressing: {}/{} | {}",
-                    index + 1,
-                    total_files,
-                    chunk_name
-                ));
-
-                let file = std::fs::File::open(&filename).map_err(SnapshotError::IoError)?;
-                let reader = std::io::BufReader::with_capacity(4 * 1024 * 1024, file);
-                let mut gz_decoder = GzDecoder::new(reader);
-                let mut buffer = Vec::new();
-                gz_decoder
-                    .read_to_end(&mut buffer)
-                    .map_err(SnapshotError::IoError)?;
-                Ok::<Vec<u8>, SnapshotError>(buffer)
-            });
+            let (sender, receiver) = mpsc::channel(blocks_per_chunk);
+
+            let task =
+                tokio::task::spawn_blocking(move || decompress_chunk_blocks(&filename, sender));
 
-            pending_tasks.push(task);
+            pending_tasks.push_back(PendingChunk {
+                index: current_index,
+                task,
+                receiver,
+            });
             current_index += 1;
         }
 
-        // Wait for first task to complete and write it
-        if !pending_tasks.is_empty() {
-            let task = pending_tasks.remove(0);
-            let buffer = task.await.map_err(|e| {
+        // Drain the oldest chunk in order and write it
+        if let Some(mut pending) = pending_tasks.pop_front() {
+            let chunk_name = chunk_file_name(&local_chunks[pending.index]);
+
+            let mut hasher = md5::Md5::new();
+            while let Some(block) = pending.receiver.recv().await {
+                hasher.update(&block);
+                tar_offset += block.len() as u64;
+                tar_file.write_all(&block).await?;
+            }
+            pending.task.await.map_err(|e| {
                 SnapshotError::IoError(std::io::Error::other(format!("Task join error: {}", e)))
             })??;
 
-            tar_file.write_all(&buffer).await?;
-            merge_pb.inc(1);
+            // Make the chunk durable before recording it as a checkpoint
+            tar_file.flush().await?;
+            tar_file.get_ref().sync_data().await?;
+            let checkpoint = MergeCheckpoint {
+                index: pending.index,
+                chunk: chunk_name.to_string(),
+                offset: tar_offset,
+                md5: format!("{:x}", hasher.finalize()),
+            };
+            let mut line = serde_json::to_string(&checkpoint)?;
+            line.push('\n');
+            journal.write_all(line.as_bytes()).await?;
+            journal.flush().await?;
+
+            progress.on_event(&ProgressEvent::MergeProgress {
+                shard_id,
+                chunk: chunk_name.to_string(),
+                merged_chunks: pending.index + 1,
+                total_chunks: total_files,
+            });
         }
     }
     tar_file.flush().await?;
-    merge_pb.finish_with_message(format!(
+    info!(
         "✅ Merged {} chunks for shard {}",
         local_chunks.len(),
         shard_id
-    ));
+    );
+    progress.on_event(&ProgressEvent::MergeFinished {
+        shard_id,
+        total_chunks: local_chunks.len(),
+    });
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::NoProgress;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    /// Records the number of resumed chunks reported by each merge.
+    #[derive(Debug, Default)]
+    struct ResumedChunks(Mutex<Vec<usize>>);
+
+    impl ProgressObserver for ResumedChunks {
+        fn on_event(&self, event: &ProgressEvent) {
+            if let ProgressEvent::MergeStarted { resumed_chunks, .. } = event {
+                self.0.lock().unwrap().push(*resumed_chunks);
+            }
+        }
+    }
+
+    /// Writes `count` gzipped chunks and returns their paths and decompressed contents.
+    fn write_chunks(dir: &std::path::Path, count: u8) -> (Vec<String>, Vec<u8>) {
+        let mut chunks = Vec::new();
+        let mut expected = Vec::new();
+        for i in 0..count {
+            let data = vec![i; 1000 + i as usize];
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(&data).unwrap();
+            let path = dir.join(format!("chunk_{:04}.bin", i));
+            std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+            chunks.push(path.to_str().unwrap().to_string());
+            expected.extend_from_slice(&data);
+        }
+        (chunks, expected)
+    }
+
+    #[tokio::test]
+    async fn test_merge_chunks_with_small_memory_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut expected = Vec::new();
+        let mut chunks = Vec::new();
+
+        for i in 0..5u8 {
+            // Larger than one block so chunks span several channel sends
+            let data: Vec<u8> = (0..BLOCK_SIZE * 2 + 17 * i as usize)
+                .map(|n| (n % 251) as u8 ^ i)
+                .collect();
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(&data).unwrap();
+            let path = dir.path().join(format!("chunk_{}", i));
+            std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+            expected.extend_from_slice(&data);
+            chunks.push(path.to_str().unwrap().to_string());
+        }
+
+        let tar_path = dir.path().join("out.tar");
+        merge_chunks(
+            "net/0/snap",
+            &chunks,
+            tar_path.to_str().unwrap(),
+            &NoProgress,
+            0,
+            BLOCK_SIZE,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(std::fs::read(&tar_path).unwrap(), expected);
+
+        // Simulate an interruption in the middle of the fourth chunk with a torn journal line
+        let tar_filename = tar_path.to_str().unwrap();
+        let checkpoints = load_checkpoints(tar_filename, "net/0/snap", &chunks);
+        assert_eq!(checkpoints.len(), chunks.len());
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&tar_path)
+            .unwrap();
+        file.set_len(checkpoints[2].offset + 1234).unwrap();
+        let mut journal = journal_content("net/0/snap", &checkpoints[..3]).unwrap();
+        journal.push_str("{\"index\":3,\"chu");
+        std::fs::write(journal_path(tar_filename), journal).unwrap();
+
+        assert_eq!(
+            restore_checkpoints(tar_filename, "net/0/snap", &chunks)
+                .unwrap()
+                .len(),
+            3
+        );
+        merge_chunks(
+            "net/0/snap",
+            &chunks,
+            tar_filename,
+            &NoProgress,
+            0,
+            BLOCK_SIZE,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(std::fs::read(&tar_path).unwrap(), expected);
+        assert_eq!(
+            load_checkpoints(tar_filename, "net/0/snap", &chunks).len(),
+            chunks.len()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_merge_resumes_only_journal_of_same_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let (chunks, expected) = write_chunks(dir.path(), 4);
+        let tar_path = dir.path().join("out.tar");
+        let tar_filename = tar_path.to_str().unwrap();
+        let progress = ResumedChunks::default();
+        let merge = |key_base: &'static str| {
+            let (chunks, progress) = (&chunks, &progress);
+            async move {
+                merge_chunks(
+                    key_base,
+                    chunks,
+                    tar_filename,
+                    progress,
+                    0,
+                    BLOCK_SIZE,
+                    &CancellationToken::new(),
+                )
+                .await
+                .unwrap()
+            }
+        };
+
+        // Interrupt after two chunks: the tar holds a partial third chunk
+        merge("net/0/snapshot-1").await;
+        let checkpoints = load_checkpoints(tar_filename, "net/0/snapshot-1", &chunks);
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&tar_path)
+            .unwrap()
+            .set_len(checkpoints[1].offset + 10)
+            .unwrap();
+        std::fs::write(
+            journal_path(tar_filename),
+            journal_content("net/0/snapshot-1", &checkpoints[..2]).unwrap(),
+        )
+        .unwrap();
+
+        // The same snapshot continues at the third chunk
+        merge("net/0/snapshot-1").await;
+        assert_eq!(std::fs::read(&tar_path).unwrap(), expected);
+
+        // Another snapshot with the same chunk names starts over
+        merge("net/0/snapshot-2").await;
+        assert_eq!(std::fs::read(&tar_path).unwrap(), expected);
+        assert_eq!(*progress.0.lock().unwrap(), [0, 2, 0]);
+        assert!(load_checkpoints(tar_filename, "net/0/snapshot-1", &chunks).is_empty());
+        assert_eq!(
+            merged_chunks(tar_filename, "net/0/snapshot-2", &chunks),
+            chunks.len()
+        );
+    }
+}
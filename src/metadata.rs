@@ -1,8 +1,12 @@
 //! Metadata fetching and management.
 
 use crate::error::SnapshotError;
-use crate::types::{DownloadConfig, SnapshotMetadata};
-use tracing::info;
+use crate::signature::{verify_signature, TrustedKey};
+use crate::source::{ListedObject, SnapshotSource};
+use crate::types::{DownloadConfig, SnapshotChangePolicy, SnapshotMetadata, SnapshotSelector};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use tracing::{info, warn};
 
 /// Constructs the S3/R2 path to the metadata file for a given network and shard.
 ///
@@ -18,61 +22,671 @@ pub(crate) fn metadata_path(network: &str, shard_id: u32) -> String {
     format!("{}/{}/latest.json", network, shard_id)
 }
 
+/// Returns the path of the signed document a shard's metadata was resolved from,
+/// kept in the snapshot directory to re-verify pinned metadata offline.
+pub(crate) fn signed_metadata_path(snapshot_dir: &str, shard_id: u32) -> String {
+    format!("{}/shard_{}_signed_metadata.json", snapshot_dir, shard_id)
+}
+
+/// A signed `latest.json` or `index.json`, exactly as published.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct SignedDocument {
+    /// Key of the document in the source.
+    key: String,
+    /// Raw document the signature covers.
+    document: String,
+    /// Contents of the detached signature file.
+    signature: String,
+}
+
+impl SignedDocument {
+    /// Returns true if the document publishes `metadata`, pinned or not.
+    fn vouches_for(&self, metadata: &SnapshotMetadata) -> bool {
+        let metadata = SnapshotMetadata {
+            pinned: false,
+            ..metadata.clone()
+        };
+        if let Ok(latest) = serde_json::from_str::<SnapshotMetadata>(&self.document) {
+            return latest == metadata;
+        }
+        serde_json::from_str::<SnapshotIndex>(&self.document)
+            .is_ok_and(|index| index.snapshots.contains(&metadata))
+    }
+}
+
+/// Reads the local `metadata.json`, which maps shard IDs to their snapshot metadata.
+///
+/// A missing or unreadable file yields no shards.
+pub(crate) fn load_local_metadata(metadata_file_path: &str) -> HashMap<String, SnapshotMetadata> {
+    let Ok(content) = std::fs::read_to_string(metadata_file_path) else {
+        return HashMap::new();
+    };
+    match serde_json::from_str(&content) {
+        Ok(metadata) => {
+            info!("Loaded existing metadata from {}", metadata_file_path);
+            metadata
+        }
+        Err(_) => HashMap::new(),
+    }
+}
+
+/// Constructs the path to the optional index listing all snapshots of a shard.
+pub(crate) fn index_path(network: &str, shard_id: u32) -> String {
+    format!("{}/{}/index.json", network, shard_id)
+}
+
+/// The `index.json` document.
+#[derive(Deserialize)]
+struct SnapshotIndex {
+    snapshots: Vec<SnapshotMetadata>,
+}
+
+/// Lists the snapshots available for a shard, newest first.
+///
+/// Snapshots are read from `{network}/{shard}/index.json` when it exists, a
+/// document of the form `{"snapshots": [<metadata>, ...]}` whose entries have
+/// the same format as `latest.json`. Otherwise they are reconstructed from a
+/// listing of the shard's directory, for sources that support listing (local
+/// directories and S3).
+///
+/// When [`DownloadConfig::trusted_keys`] is set, the index must be signed
+/// (`index.json.sig`) and bucket listings, which can't be signed, are refused.
+///
+/// # Arguments
+///
+/// * `config` - Download configuration (source, network and trusted keys)
+/// * `shard_id` - The shard identifier
+///
+/// # Returns
+///
+/// The available snapshots, newest first, or an error.
+pub async fn list_snapshots(
+    config: &DownloadConfig,
+    shard_id: u32,
+) -> Result<Vec<SnapshotMetadata>, SnapshotError> {
+    let source = config.snapshot_source()?;
+    fetch_snapshot_list(
+        source.as_ref(),
+        &config.network,
+        shard_id,
+        &config.trusted_keys,
+    )
+    .await
+}
+
+/// Lists the snapshots of a shard from its index or a listing, newest first.
+pub(crate) async fn fetch_snapshot_list(
+    source: &dyn SnapshotSource,
+    network: &str,
+    shard_id: u32,
+    trusted_keys: &[TrustedKey],
+) -> Result<Vec<SnapshotMetadata>, SnapshotError> {
+    let (snapshots, _) =
+        fetch_signed_snapshot_list(source, network, shard_id, trusted_keys).await?;
+    Ok(snapshots)
+}
+
+/// Like [`fetch_snapshot_list`], also returning the signed index when trusted keys are given.
+async fn fetch_signed_snapshot_list(
+    source: &dyn SnapshotSource,
+    network: &str,
+    shard_id: u32,
+    trusted_keys: &[TrustedKey],
+) -> Result<(Vec<SnapshotMetadata>, Option<SignedDocument>), SnapshotError> {
+    let index_key = index_path(network, shard_id);
+    let mut signed = None;
+    let mut snapshots = match source.fetch(&index_key).await {
+        Ok(data) => {
+            if !trusted_keys.is_empty() {
+                signed =
+                    Some(verify_document_signature(source, &index_key, &data, trusted_keys).await?);
+            }
+            let index: SnapshotIndex = serde_json::from_slice(&data).map_err(|e| {
+                SnapshotError::DownloadFailed(format!(
+                    "Invalid snapshot index from {}: {}",
+                    source.describe(&index_key),
+                    e
+                ))
+            })?;
+            index.snapshots
+        }
+        Err(SnapshotError::NotFound(_)) => {
+            let prefix = format!("{}/{}/", network, shard_id);
+            if !trusted_keys.is_empty() {
+                return Err(SnapshotError::UnsignedMetadata {
+                    url: format!("{} (bucket listing)", source.describe(&prefix)),
+                    signature_url: source.describe(&format!("{}.sig", index_key)),
+                });
+            }
+            info!(
+                "No snapshot index at {}, listing {}",
+                source.describe(&index_key),
+                source.describe(&prefix)
+            );
+            snapshots_from_listing(&prefix, &source.list(&prefix).await?)
+        }
+        Err(e) => return Err(e),
+    };
+
+    snapshots.sort_by_key(|snapshot| std::cmp::Reverse(snapshot.timestamp));
+    Ok((snapshots, signed))
+}
+
+/// Reconstructs snapshot metadata from a listing of a shard's directory.
+///
+/// Every directory directly below `prefix` is a snapshot whose chunks are the
+/// files in it. Its timestamp is taken from a trailing Unix timestamp in the
+/// directory name (e.g. `snapshot-2025-01-31-1738281600`), or else from the
+/// newest modification time of its chunks.
+fn snapshots_from_listing(prefix: &str, objects: &[ListedObject]) -> Vec<SnapshotMetadata> {
+    let mut directories: BTreeMap<&str, (Vec<String>, i64)> = BTreeMap::new();
+    for object in objects {
+        let Some((directory, name)) = object
+            .key
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.split_once('/'))
+        else {
+            continue;
+        };
+        if name.contains('/') || name.ends_with(".json") || name.ends_with(".sig") {
+            continue;
+        }
+        let (chunks, modified) = directories.entry(directory).or_default();
+        chunks.push(name.to_string());
+        *modified = (*modified).max(object.last_modified.unwrap_or(0));
+    }
+
+    directories
+        .into_iter()
+        .map(|(directory, (mut chunks, modified))| {
+            chunks.sort();
+            let timestamp = directory
+                .rsplit(|c: char| !c.is_ascii_digit())
+                .next()
+                .filter(|digits| digits.len() >= 9)
+                .and_then(|digits| digits.parse().ok())
+                .unwrap_or(modified);
+            SnapshotMetadata {
+                key_base: format!("{}{}", prefix, directory),
+                chunks,
+                timestamp,
+                checksums: HashMap::new(),
+                pinned: false,
+            }
+        })
+        .collect()
+}
+
+/// Resolves the snapshot to restore for a shard according to [`DownloadConfig::snapshot`]
+/// and [`DownloadConfig::on_snapshot_change`].
+///
+/// # Arguments
+///
+/// * `source` - Snapshot source to read from
+/// * `config` - Download configuration
+/// * `shard_id` - The shard identifier
+/// * `previous` - Metadata of the shard from the local `metadata.json`, if any
+///
+/// # Returns
+///
+/// The metadata of the snapshot to restore, marked as pinned if it was selected explicitly
+/// or kept by [`SnapshotChangePolicy::Resume`].
+///
+/// When [`DownloadConfig::trusted_keys`] is set, the signed document the metadata
+/// comes from is kept in the snapshot directory (see [`signed_metadata_path`]), and
+/// metadata taken from the local `metadata.json` is only used if that document
+/// still verifies and publishes it.
+pub(crate) async fn resolve_snapshot(
+    source: &dyn SnapshotSource,
+    config: &DownloadConfig,
+    shard_id: u32,
+    previous: Option<&SnapshotMetadata>,
+) -> Result<SnapshotMetadata, SnapshotError> {
+    let signed_path = signed_metadata_path(&config.snapshot_download_dir, shard_id);
+    if let (None, Some(previous)) = (&config.snapshot, previous.filter(|m| m.pinned)) {
+        if !config.trusted_keys.is_empty() {
+            verify_local_metadata(&signed_path, previous, &config.trusted_keys).await?;
+        }
+        info!(
+            "📌 Using pinned snapshot {} for shard {} (select another with --snapshot)",
+            previous.key_base, shard_id
+        );
+        return Ok(previous.clone());
+    }
+
+    let (metadata, signed) = select_snapshot(source, config, shard_id, previous).await?;
+    match signed {
+        Some(signed) if signed.vouches_for(&metadata) => {
+            tokio::fs::write(&signed_path, serde_json::to_vec_pretty(&signed)?).await?;
+        }
+        // The previous snapshot was kept, its own signed document still applies
+        _ if !config.trusted_keys.is_empty() => {
+            verify_local_metadata(&signed_path, &metadata, &config.trusted_keys).await?;
+        }
+        _ => {}
+    }
+    Ok(metadata)
+}
+
+/// Picks the snapshot to restore when no pinned snapshot applies.
+///
+/// # Returns
+///
+/// The metadata along with the signed document it was read from, when trusted keys are given.
+async fn select_snapshot(
+    source: &dyn SnapshotSource,
+    config: &DownloadConfig,
+    shard_id: u32,
+    previous: Option<&SnapshotMetadata>,
+) -> Result<(SnapshotMetadata, Option<SignedDocument>), SnapshotError> {
+    let (network, trusted_keys) = (&config.network, &config.trusted_keys);
+    match config.snapshot {
+        None => {
+            let (latest, signed) =
+                download_signed_metadata(source, network, shard_id, trusted_keys).await?;
+            match previous {
+                Some(previous) if !previous.is_same_snapshot(&latest) => {
+                    snapshot_changed(config.on_snapshot_change, shard_id, previous, latest)
+                        .map(|metadata| (metadata, signed))
+                }
+                _ => Ok((latest, signed)),
+            }
+        }
+        Some(SnapshotSelector::Latest) => {
+            download_signed_metadata(source, network, shard_id, trusted_keys).await
+        }
+        Some(ref selector) => {
+            let (snapshots, signed) =
+                fetch_signed_snapshot_list(source, network, shard_id, trusted_keys).await?;
+            let Some(selected) = selector.select(&snapshots) else {
+                return Err(SnapshotError::NoMatchingSnapshot {
+                    shard: shard_id,
+                    selector: selector.to_string(),
+                    available: snapshots.into_iter().map(|m| m.key_base).collect(),
+                });
+            };
+            info!(
+                "📌 Selected snapshot {} (timestamp {}) for shard {}",
+                selected.key_base, selected.timestamp, shard_id
+            );
+            let selected = SnapshotMetadata {
+                pinned: true,
+                ..selected.clone()
+            };
+            Ok((selected, signed))
+        }
+    }
+}
+
+/// Checks metadata from the local `metadata.json` against the signed document kept for it.
+///
+/// # Arguments
+///
+/// * `signed_path` - Path of the kept signed document
+/// * `metadata` - Metadata about to be restored
+/// * `trusted_keys` - Keys allowed to sign the metadata
+async fn verify_local_metadata(
+    signed_path: &str,
+    metadata: &SnapshotMetadata,
+    trusted_keys: &[TrustedKey],
+) -> Result<(), SnapshotError> {
+    let signed = tokio::fs::read(signed_path)
+        .await
+        .ok()
+        .and_then(|data| serde_json::from_slice::<SignedDocument>(&data).ok())
+        .ok_or_else(|| SnapshotError::UnsignedMetadata {
+            url: format!("local snapshot {}", metadata.key_base),
+            signature_url: signed_path.to_string(),
+        })?;
+    let invalid = |reason: String| SnapshotError::InvalidMetadataSignature {
+        url: signed_path.to_string(),
+        reason,
+    };
+    verify_signature(
+        signed.document.as_bytes(),
+        signed.signature.as_bytes(),
+        trusted_keys,
+    )
+    .map_err(invalid)?;
+    if !signed.vouches_for(metadata) {
+        return Err(invalid(format!(
+            "{} does not publish the local snapshot {}",
+            signed.key, metadata.key_base
+        )));
+    }
+    info!(
+        "🔏 Local metadata signature verified for {}",
+        metadata.key_base
+    );
+    Ok(())
+}
+
+/// Applies the [`SnapshotChangePolicy`] when `latest.json` no longer points to
+/// the snapshot of the previous run.
+fn snapshot_changed(
+    policy: SnapshotChangePolicy,
+    shard_id: u32,
+    previous: &SnapshotMetadata,
+    latest: SnapshotMetadata,
+) -> Result<SnapshotMetadata, SnapshotError> {
+    match policy {
+        SnapshotChangePolicy::Resume => {
+            warn!(
+                "📌 Snapshot of shard {} changed to {}, finishing {} and pinning it (--snapshot latest moves on)",
+                shard_id, latest.key_base, previous.key_base
+            );
+            Ok(SnapshotMetadata {
+                pinned: true,
+                ..previous.clone()
+            })
+        }
+        SnapshotChangePolicy::Restart => {
+            warn!(
+                "🔄 Snapshot of shard {} changed from {} to {}, restarting with the new snapshot",
+                shard_id, previous.key_base, latest.key_base
+            );
+            Ok(latest)
+        }
+        SnapshotChangePolicy::Fail => Err(SnapshotError::SnapshotChanged {
+            shard: shard_id,
+            previous: previous.key_base.clone(),
+            latest: latest.key_base,
+        }),
+    }
+}
+
 /// Downloads and parses the snapshot metadata for a shard.
 ///
+/// When trusted keys are given, the metadata must come with a detached
+/// signature (`latest.json.sig`) made by one of them; unsigned or badly
+/// signed metadata is refused.
+///
 /// # Arguments
 ///
+/// * `source` - Snapshot source to read from
 /// * `network` - The network name
 /// * `shard_id` - The shard identifier
-/// * `config` - Download configuration
+/// * `trusted_keys` - Keys allowed to sign the metadata; empty to accept unsigned metadata
 ///
 /// # Returns
 ///
 /// The parsed metadata or an error.
 pub(crate) async fn download_metadata(
+    source: &dyn SnapshotSource,
     network: &str,
     shard_id: u32,
-    config: &DownloadConfig,
+    trusted_keys: &[TrustedKey],
 ) -> Result<SnapshotMetadata, SnapshotError> {
-    let metadata_url = format!(
-        "{}/{}",
-        config.snapshot_download_url,
-        metadata_path(network, shard_id)
-    );
+    let (metadata, _) = download_signed_metadata(source, network, shard_id, trusted_keys).await?;
+    Ok(metadata)
+}
+
+/// Like [`download_metadata`], also returning the signed `latest.json` when trusted keys are given.
+async fn download_signed_metadata(
+    source: &dyn SnapshotSource,
+    network: &str,
+    shard_id: u32,
+    trusted_keys: &[TrustedKey],
+) -> Result<(SnapshotMetadata, Option<SignedDocument>), SnapshotError> {
+    let metadata_url = source.describe(&metadata_path(network, shard_id));
     info!("Retrieving metadata from {}", metadata_url);
 
-    let response = reqwest::get(&metadata_url).await?;
-
-    // Check HTTP status code
-    let status = response.status();
-    if !status.is_success() {
-        if status.as_u16() == 404 {
-            return Err(SnapshotError::DownloadFailed(format!(
-                "Snapshot not found for network '{}' shard {}. The metadata URL returned 404: {}\n\
-                 This usually means:\n\
-                 - The shard doesn't exist for this network\n\
-                 - The snapshot hasn't been created yet\n\
-                 - The URL is incorrect\n\
-                 Available shards for FARCASTER_NETWORK_MAINNET: 0, 1, 2\n\
-                 Available shards for FARCASTER_NETWORK_TESTNET: 0, 1",
-                network, shard_id, metadata_url
-            )));
-        } else {
-            return Err(SnapshotError::DownloadFailed(format!(
-                "Failed to fetch metadata from {}: HTTP {}",
-                metadata_url, status
-            )));
+    let metadata = match source.fetch_metadata(network, shard_id).await {
+        Err(SnapshotError::NotFound(_)) => Err(SnapshotError::MetadataNotFound {
+            network: network.to_string(),
+            shard: shard_id,
+            url: metadata_url,
+        }),
+        Err(SnapshotError::ReqwestError(e)) => Err(SnapshotError::DownloadFailed(format!(
+            "Failed to fetch metadata from {}: {}",
+            metadata_url, e
+        ))),
+        result => result,
+    }?;
+
+    if trusted_keys.is_empty() {
+        return Ok((metadata, None));
+    }
+    let signed =
+        verify_metadata_signature(source, network, shard_id, &metadata, trusted_keys).await?;
+    Ok((metadata, Some(signed)))
+}
+
+/// Checks that `metadata` is exactly the document signed by a trusted key.
+///
+/// The signature covers the raw bytes of `latest.json`, so those are fetched
+/// again along with the signature and must parse to the metadata in use.
+async fn verify_metadata_signature(
+    source: &dyn SnapshotSource,
+    network: &str,
+    shard_id: u32,
+    metadata: &SnapshotMetadata,
+    trusted_keys: &[TrustedKey],
+) -> Result<SignedDocument, SnapshotError> {
+    let key = metadata_path(network, shard_id);
+    let data = source.fetch(&key).await?;
+    let document = verify_document_signature(source, &key, &data, trusted_keys).await?;
+
+    let invalid = |reason: String| SnapshotError::InvalidMetadataSignature {
+        url: source.describe(&key),
+        reason,
+    };
+    let signed: SnapshotMetadata = serde_json::from_slice(&data)
+        .map_err(|e| invalid(format!("signed document is not valid metadata: {}", e)))?;
+    if signed != *metadata {
+        return Err(invalid(
+            "metadata changed while its signature was checked, try again".to_string(),
+        ));
+    }
+
+    info!("🔏 Metadata signature verified for shard {}", shard_id);
+    Ok(document)
+}
+
+/// Checks the detached signature (`<key>.sig`) of a document against the trusted keys.
+///
+/// # Returns
+///
+/// The document and its signature, to be kept for checking it again later.
+async fn verify_document_signature(
+    source: &dyn SnapshotSource,
+    key: &str,
+    data: &[u8],
+    trusted_keys: &[TrustedKey],
+) -> Result<SignedDocument, SnapshotError> {
+    let signature_key = format!("{}.sig", key);
+    let signature = match source.fetch(&signature_key).await {
+        Err(SnapshotError::NotFound(_)) => {
+            return Err(SnapshotError::UnsignedMetadata {
+                url: source.describe(key),
+                signature_url: source.describe(&signature_key),
+            })
         }
+        result => result?,
+    };
+    let invalid = |reason: String| SnapshotError::InvalidMetadataSignature {
+        url: source.describe(key),
+        reason,
+    };
+    verify_signature(data, &signature, trusted_keys).map_err(invalid)?;
+    Ok(SignedDocument {
+        key: key.to_string(),
+        document: String::from_utf8(data.to_vec())
+            .map_err(|_| invalid("signed document is not UTF-8".to_string()))?,
+        signature: String::from_utf8_lossy(&signature).into_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::LocalSource;
+
+    #[tokio::test]
+    async fn test_snapshots_listed_from_directories_and_selected() {
+        let dir = tempfile::tempdir().unwrap();
+        for (snapshot, chunks) in [
+            ("snapshot-1700000000", 2),
+            ("snapshot-1700086400", 3),
+            ("snapshot-1700172800", 1),
+        ] {
+            let path = dir.path().join("net/0").join(snapshot);
+            std::fs::create_dir_all(&path).unwrap();
+            for chunk in 0..chunks {
+                std::fs::write(path.join(format!("chunk_{:04}.bin", chunk)), b"data").unwrap();
+            }
+        }
+        std::fs::write(dir.path().join("net/0/latest.json"), b"{}").unwrap();
+        let source = LocalSource::new(dir.path());
+
+        let snapshots = fetch_snapshot_list(&source, "net", 0, &[]).await.unwrap();
+        let timestamps: Vec<_> = snapshots.iter().map(|m| m.timestamp).collect();
+        assert_eq!(timestamps, [1700172800, 1700086400, 1700000000]);
+        assert_eq!(
+            snapshots[1].chunks,
+            ["chunk_0000.bin", "chunk_0001.bin", "chunk_0002.bin"]
+        );
+
+        let before: SnapshotSelector = "before:1700172800".parse().unwrap();
+        assert_eq!(
+            before.select(&snapshots).unwrap().key_base,
+            "net/0/snapshot-1700086400"
+        );
+        let by_name: SnapshotSelector = "snapshot-1700000000".parse().unwrap();
+        assert_eq!(by_name.select(&snapshots).unwrap().timestamp, 1700000000);
+        let missing: SnapshotSelector = "at:1600000000".parse().unwrap();
+        assert!(missing.select(&snapshots).is_none());
     }
 
-    // Try to parse as JSON
-    let metadata = response.json::<SnapshotMetadata>().await.map_err(|e| {
-        SnapshotError::DownloadFailed(format!(
-            "Invalid metadata format from {}: {}\n\
-             Expected JSON with fields: key_base, chunks, timestamp",
-            metadata_url, e
-        ))
-    })?;
+    #[tokio::test]
+    async fn test_snapshot_change_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("net/0")).unwrap();
+        std::fs::write(
+            dir.path().join("net/0/latest.json"),
+            br#"{"key_base":"net/0/snapshot-2","chunks":["chunk_0000.bin"],"timestamp":2}"#,
+        )
+        .unwrap();
+        let source = LocalSource::new(dir.path());
+        let previous = SnapshotMetadata {
+            key_base: "net/0/snapshot-1".to_string(),
+            chunks: vec!["chunk_0000.bin".to_string()],
+            timestamp: 1,
+            checksums: HashMap::new(),
+            pinned: false,
+        };
+        let resolve = |on_snapshot_change| {
+            let config = DownloadConfig {
+                network: "net".to_string(),
+                on_snapshot_change,
+                ..Default::default()
+            };
+            let (source, previous) = (&source, &previous);
+            async move { resolve_snapshot(source, &config, 0, Some(previous)).await }
+        };
 
-    Ok(metadata)
+        let resumed = resolve(SnapshotChangePolicy::Resume).await.unwrap();
+        assert_eq!(resumed.key_base, "net/0/snapshot-1");
+        assert!(resumed.pinned);
+        let restarted = resolve(SnapshotChangePolicy::Restart).await.unwrap();
+        assert_eq!(restarted.key_base, "net/0/snapshot-2");
+        assert!(!restarted.pinned);
+        assert!(matches!(
+            resolve(SnapshotChangePolicy::Fail).await,
+            Err(SnapshotError::SnapshotChanged { shard: 0, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_pinned_metadata_is_checked_against_kept_signature() {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let dir = tempfile::tempdir().unwrap();
+        let signer = SigningKey::from_bytes(&[7; 32]);
+        let publish = |key: &str, data: Vec<u8>| {
+            let path = dir.path().join("source").join(key);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let signature = hex::encode(signer.sign(&data).to_bytes());
+            std::fs::write(path.with_extension("json.sig"), signature).unwrap();
+            std::fs::write(path, data).unwrap();
+        };
+        let snapshot = |timestamp: i64| SnapshotMetadata {
+            key_base: format!("net/0/snapshot-{}", timestamp),
+            chunks: vec!["chunk_0000.bin".to_string()],
+            timestamp,
+            checksums: HashMap::new(),
+            pinned: false,
+        };
+        publish(
+            "net/0/latest.json",
+            serde_json::to_vec(&snapshot(2)).unwrap(),
+        );
+        let index = serde_json::json!({ "snapshots": [snapshot(1), snapshot(2)] });
+        publish("net/0/index.json", serde_json::to_vec(&index).unwrap());
+
+        let snapshot_dir = dir.path().join("snapshot");
+        std::fs::create_dir_all(&snapshot_dir).unwrap();
+        let trusted = TrustedKey::from_bytes(signer.verifying_key().as_bytes()).unwrap();
+        let config = DownloadConfig {
+            network: "net".to_string(),
+            snapshot_download_dir: snapshot_dir.to_str().unwrap().to_string(),
+            trusted_keys: vec![trusted],
+            snapshot: Some("snapshot-1".parse().unwrap()),
+            ..Default::default()
+        };
+        let source = LocalSource::new(dir.path().join("source"));
+        let pinned = resolve_snapshot(&source, &config, 0, None).await.unwrap();
+        assert_eq!(pinned.key_base, "net/0/snapshot-1");
+        assert!(pinned.pinned);
+
+        // Later runs check the pinned snapshot against the index kept for it
+        let config = DownloadConfig {
+            snapshot: None,
+            ..config
+        };
+        let resolved = resolve_snapshot(&source, &config, 0, Some(&pinned)).await;
+        assert_eq!(resolved.unwrap(), pinned);
+
+        // Metadata swapped in locally is refused, as is metadata without a kept signature
+        let swapped = SnapshotMetadata {
+            chunks: vec!["other.bin".to_string()],
+            ..pinned.clone()
+        };
+        assert!(matches!(
+            resolve_snapshot(&source, &config, 0, Some(&swapped)).await,
+            Err(SnapshotError::InvalidMetadataSignature { .. })
+        ));
+        std::fs::remove_file(signed_metadata_path(&config.snapshot_download_dir, 0)).unwrap();
+        assert!(matches!(
+            resolve_snapshot(&source, &config, 0, Some(&pinned)).await,
+            Err(SnapshotError::UnsignedMetadata { .. })
+        ));
+        let unverified = DownloadConfig {
+            trusted_keys: Vec::new(),
+            ..config.clone()
+        };
+        let resolved = resolve_snapshot(&source, &unverified, 0, Some(&swapped)).await;
+        assert_eq!(resolved.unwrap(), swapped);
+
+        // A snapshot kept after latest.json moved on is checked the same way
+        let latest = resolve_snapshot(&source, &config, 0, None).await.unwrap();
+        assert_eq!(latest, snapshot(2));
+        publish(
+            "net/0/latest.json",
+            serde_json::to_vec(&snapshot(3)).unwrap(),
+        );
+        let resume = DownloadConfig {
+            on_snapshot_change: SnapshotChangePolicy::Resume,
+            ..config
+        };
+        let resumed = resolve_snapshot(&source, &resume, 0, Some(&latest)).await;
+        assert_eq!(resumed.unwrap().key_base, "net/0/snapshot-2");
+        let swapped = SnapshotMetadata {
+            chunks: vec!["other.bin".to_string()],
+            ..latest
+        };
+        assert!(resolve_snapshot(&source, &resume, 0, Some(&swapped))
+            .await
+            .is_err());
+    }
 }
@@ -1,15 +1,22 @@
 //! Main orchestration logic for downloading snapshots.
 
-use crate::download::download_file_simple;
+use crate::concurrency::ConcurrencyController;
+use crate::download::{download_file_simple, DownloadContext};
 use crate::error::SnapshotError;
 use crate::extract::extract_tar;
-use crate::merge::merge_chunks;
-use crate::metadata::download_metadata;
-use crate::types::{DownloadConfig, ExecutionStage, SnapshotMetadata};
-use crate::verify::verify_local_file;
+use crate::merge::{self, merge_chunks};
+use crate::metadata::{load_local_metadata, resolve_snapshot};
+use crate::progress::{ProgressEvent, ProgressObserver};
+use crate::state::{state_path, ChunkStateStore};
+use crate::stream::{self, stream_restore, StreamRestoreContext};
+use crate::types::{ChunkChecksum, DownloadConfig, ExecutionStage, SnapshotMetadata};
+use futures_util::future::try_join_all;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use tokio_retry2::{Retry, RetryError};
 use tracing::{error, info, warn};
 
@@ -17,11 +24,23 @@ use tracing::{error, info, warn};
 ///
 /// This is the main entry point for downloading snapshots. It performs the following steps:
 ///
-/// 1. Fetches metadata for all requested shards
+/// 1. Fetches metadata for all requested shards (see [`DownloadConfig::snapshot`])
 /// 2. Downloads chunks with resumable support (skips verified files)
 /// 3. Decompresses and merges chunks into tar archives
 /// 4. Extracts tar archives to the RocksDB directory
 ///
+/// Shards are processed concurrently: chunks of all shards share one download
+/// budget (`max_concurrent_downloads`), and a shard is merged and extracted as
+/// soon as its own chunks are downloaded.
+///
+/// With [`ExecutionStage::Stream`], steps 2-4 run as a single pass that unpacks
+/// chunks as they arrive, without writing an intermediate tar.
+///
+/// Cancelling `config.cancel` stops the restore at the next safe point and
+/// returns [`SnapshotError::Cancelled`]; partial chunks, merge and stream
+/// journals are kept so that a later call resumes from there. When one shard
+/// fails, the other shards are stopped the same way and its error is returned.
+///
 /// # Arguments
 ///
 /// * `config` - Download configuration
@@ -52,32 +71,43 @@ pub async fn download_snapshots(
 ) -> Result<(), SnapshotError> {
     let snapshot_dir = config.snapshot_download_dir.clone();
     std::fs::create_dir_all(snapshot_dir.clone())?;
+    let source = config.snapshot_source()?;
+    let progress = config.progress_observer();
 
     // Load or fetch metadata
     let metadata_file_path = format!("{}/metadata.json", snapshot_dir);
 
     // Always try to load existing metadata first (to preserve other shards)
-    let mut all_metadata: HashMap<String, SnapshotMetadata> =
-        if let Ok(content) = std::fs::read_to_string(&metadata_file_path) {
-            match serde_json::from_str(&content) {
-                Ok(metadata) => {
-                    info!("Loaded existing metadata from {}", metadata_file_path);
-                    metadata
-                }
-                Err(_) => HashMap::new(),
-            }
-        } else {
-            HashMap::new()
-        };
+    let mut all_metadata = load_local_metadata(&metadata_file_path);
 
     // Fetch or update metadata for requested shards
-    let should_fetch_metadata =
-        stage == ExecutionStage::All || stage == ExecutionStage::DownloadOnly;
+    let should_fetch_metadata = stage == ExecutionStage::All
+        || stage == ExecutionStage::DownloadOnly
+        || stage == ExecutionStage::Stream;
 
     if should_fetch_metadata {
         // Download metadata for requested shards (merge with existing)
         for &shard_id in &shard_ids {
-            let metadata = download_metadata(&config.network, shard_id, config).await?;
+            if config.cancel.is_cancelled() {
+                return Err(cancelled(progress.as_ref()));
+            }
+            let previous = all_metadata.get(&shard_id.to_string());
+            let metadata = resolve_snapshot(source.as_ref(), config, shard_id, previous).await?;
+            source
+                .confirm_snapshot(&config.network, shard_id, &metadata)
+                .await;
+            if let Some(previous) = previous.filter(|p| !p.is_same_snapshot(&metadata)) {
+                // Chunks of different snapshots share file names, never mix them
+                if stage != ExecutionStage::DownloadOnly {
+                    discard_shard_db(config, &snapshot_dir, &db_dir, shard_id, previous)?;
+                }
+                discard_shard_files(&snapshot_dir, shard_id)?;
+            }
+            progress.on_event(&ProgressEvent::MetadataFetched {
+                shard_id,
+                key_base: metadata.key_base.clone(),
+                chunks: metadata.chunks.len(),
+            });
             all_metadata.insert(shard_id.to_string(), metadata);
         }
 
@@ -92,304 +122,451 @@ pub async fn download_snapshots(
         // For merge/extract only stages, verify requested shards exist
         for &shard_id in &shard_ids {
             if !all_metadata.contains_key(&shard_id.to_string()) {
-                return Err(SnapshotError::DownloadFailed(format!(
-                    "Metadata not found for shard {}. Available shards: {:?}. \
-                     Run without --stage flag to download metadata first.",
-                    shard_id,
-                    all_metadata.keys().collect::<Vec<_>>()
-                )));
+                return Err(shard_not_in_local_metadata(shard_id, &all_metadata));
             }
         }
     }
 
+    // Announce the download stage with its total size
+    if stage == ExecutionStage::All || stage == ExecutionStage::DownloadOnly {
+        let total_chunks: usize = shard_ids
+            .iter()
+            .filter_map(|shard_id| all_metadata.get(&shard_id.to_string()))
+            .map(|m| m.chunks.len())
+            .sum();
+        progress.on_event(&ProgressEvent::DownloadStarted {
+            total_chunks,
+            shards: shard_ids.len(),
+        });
+    }
+
+    // Shared download state, including the limit on concurrent downloads.
+    // Its token is a child of `config.cancel`, so a failed shard can stop the
+    // others without cancelling the caller's token.
+    let downloads = DownloadContext {
+        source: Arc::clone(&source),
+        limiter: config.bandwidth_limiter.clone(),
+        concurrency: Arc::new(ConcurrencyController::new(
+            config.max_concurrent_downloads,
+            config.adaptive_concurrency,
+        )),
+        retry: config.retry_policy,
+        progress: Arc::clone(&progress),
+        cancel: config.cancel.child_token(),
+        state: Arc::new(ChunkStateStore::new(&snapshot_dir, config.reverify)),
+        multipart_part_size: config.multipart_part_size,
+    };
+
+    // Run all shard pipelines concurrently under the shared download budget
+    let merge_slot = Semaphore::new(1);
+    let ctx = ShardRestoreContext {
+        config,
+        snapshot_dir: &snapshot_dir,
+        db_dir: &db_dir,
+        all_metadata: &all_metadata,
+        downloads: &downloads,
+        stage,
+        merge_slot: &merge_slot,
+    };
+    let result = try_join_all(
+        shard_ids
+            .iter()
+            .map(|&shard_id| restore_shard(ctx, shard_id)),
+    )
+    .await;
+    if config.cancel.is_cancelled() {
+        return Err(cancelled(progress.as_ref()));
+    }
+    if result.is_err() {
+        // Stop the download tasks and blocking threads of the remaining shards
+        downloads.cancel.cancel();
+    }
+    result?;
+
+    progress.on_event(&ProgressEvent::Finished);
+    info!("✅ All operations completed successfully!");
+    Ok(())
+}
+
+/// Reports a cancelled restore and returns the matching error.
+fn cancelled(progress: &dyn ProgressObserver) -> SnapshotError {
+    warn!("⏹️  Restore cancelled, rerun to resume where it stopped");
+    progress.on_event(&ProgressEvent::Cancelled);
+    SnapshotError::Cancelled
+}
+
+/// Removes the local files of a shard's previous snapshot.
+///
+/// Deletes the downloaded and partial chunks, the download state, the merged
+/// tar and the merge and stream journals, so the next snapshot starts clean.
+/// The RocksDB output directory is left alone, see [`discard_shard_db`].
+fn discard_shard_files(snapshot_dir: &str, shard_id: u32) -> Result<(), SnapshotError> {
+    let tar_filename = merge::tar_path(snapshot_dir, shard_id);
+    let shard_dir = format!("{}/shard-{}", snapshot_dir, shard_id);
+    ignore_not_found(std::fs::remove_dir_all(&shard_dir))?;
+    for path in [
+        state_path(snapshot_dir, shard_id),
+        merge::journal_path(&tar_filename),
+        stream::journal_path(snapshot_dir, shard_id),
+        tar_filename,
+    ] {
+        ignore_not_found(std::fs::remove_file(&path))?;
+    }
+    info!(
+        "🧹 Discarded local files of the previous snapshot of shard {}",
+        shard_id
+    );
+    Ok(())
+}
+
+/// Makes sure the new snapshot of a shard is not restored on top of the previous one.
+///
+/// Extraction keeps existing files of the right size, so files of the previous
+/// snapshot would end up in the new restore. The shard's database directory is
+/// deleted when it provably holds an unfinished streaming restore of the previous
+/// snapshot, or when [`DownloadConfig::clear_db_on_snapshot_change`] is set.
+/// Otherwise it may be a complete database in use, so it is kept and
+/// [`SnapshotError::StaleDatabase`] is returned.
+///
+/// # Arguments
+///
+/// * `config` - Download configuration
+/// * `snapshot_dir` - Directory holding the stream journal
+/// * `db_dir` - Target directory for RocksDB data
+/// * `shard_id` - Shard whose snapshot changed
+/// * `previous` - Metadata of the previous snapshot
+fn discard_shard_db(
+    config: &DownloadConfig,
+    snapshot_dir: &str,
+    db_dir: &str,
+    shard_id: u32,
+    previous: &SnapshotMetadata,
+) -> Result<(), SnapshotError> {
+    let shard_db_dir = format!("{}/shard-{}", db_dir, shard_id);
+    if !std::path::Path::new(&shard_db_dir).exists() {
+        return Ok(());
+    }
+    let unfinished =
+        stream::stream_state(snapshot_dir, shard_id, &previous.key_base) == Some(false);
+    if !unfinished && !config.clear_db_on_snapshot_change {
+        return Err(SnapshotError::StaleDatabase {
+            shard: shard_id,
+            previous: previous.key_base.clone(),
+            path: shard_db_dir.into(),
+        });
+    }
+    warn!(
+        "🧹 Deleting {}, restored from {}, the previous snapshot of shard {}",
+        shard_db_dir, previous.key_base, shard_id
+    );
+    std::fs::remove_dir_all(&shard_db_dir)?;
+    Ok(())
+}
+
+fn ignore_not_found(result: std::io::Result<()>) -> std::io::Result<()> {
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Builds the error for a shard missing from the local `metadata.json`.
+fn shard_not_in_local_metadata(
+    shard_id: u32,
+    all_metadata: &HashMap<String, SnapshotMetadata>,
+) -> SnapshotError {
+    let mut available: Vec<u32> = all_metadata.keys().filter_map(|k| k.parse().ok()).collect();
+    available.sort_unstable();
+    SnapshotError::ShardNotInLocalMetadata {
+        shard: shard_id,
+        available,
+    }
+}
+
+/// Shared state for the restore pipelines of all shards
+#[derive(Clone, Copy)]
+struct ShardRestoreContext<'a> {
+    config: &'a DownloadConfig,
+    snapshot_dir: &'a str,
+    db_dir: &'a str,
+    all_metadata: &'a HashMap<String, SnapshotMetadata>,
+    downloads: &'a DownloadContext,
+    stage: ExecutionStage,
+    merge_slot: &'a Semaphore,
+}
+
+/// Downloads, merges and extracts one shard, reporting a failure as a progress event.
+async fn restore_shard(ctx: ShardRestoreContext<'_>, shard_id: u32) -> Result<(), SnapshotError> {
+    let result = run_shard_pipeline(ctx, shard_id).await;
+    match result {
+        Err(ref e) if !ctx.downloads.cancel.is_cancelled() => {
+            ctx.downloads
+                .progress
+                .on_event(&ProgressEvent::ShardFailed {
+                    shard_id,
+                    error: e.to_string(),
+                });
+        }
+        _ => {}
+    }
+    result
+}
+
+/// Runs the requested stages for one shard.
+///
+/// Runs concurrently with the pipelines of the other shards: downloads share
+/// the global concurrency budget in `downloads`, and merges take turns on
+/// `merge_slot` so the merge memory limit holds across shards.
+async fn run_shard_pipeline(
+    ctx: ShardRestoreContext<'_>,
+    shard_id: u32,
+) -> Result<(), SnapshotError> {
+    let ShardRestoreContext {
+        config,
+        snapshot_dir,
+        db_dir,
+        all_metadata,
+        downloads,
+        stage,
+        merge_slot,
+    } = ctx;
+
     // Determine which stages to execute
     let should_download = stage == ExecutionStage::All || stage == ExecutionStage::DownloadOnly;
     let should_merge = stage == ExecutionStage::All || stage == ExecutionStage::MergeOnly;
     let should_extract = stage == ExecutionStage::All || stage == ExecutionStage::ExtractOnly;
 
-    // Create download progress bar only if downloading
-    let pb = if should_download {
-        let total_chunks: usize = all_metadata.values().map(|m| m.chunks.len()).sum();
-        let progress_bar = indicatif::ProgressBar::new(total_chunks as u64);
-        progress_bar.set_style(
-            indicatif::ProgressStyle::default_bar()
-                .template("{spinner:.cyan} [{bar:40.cyan/blue}] {pos}/{len} {msg} | {elapsed_precise} elapsed, ETA {eta_precise}")
-                .unwrap()
-                .progress_chars("█▓▒░ "),
+    let metadata_json = all_metadata
+        .get(&shard_id.to_string())
+        .ok_or_else(|| shard_not_in_local_metadata(shard_id, all_metadata))?;
+    let base_path = &metadata_json.key_base;
+
+    std::fs::create_dir_all(format!("{}/shard-{}", snapshot_dir, shard_id))?;
+
+    // Streaming mode replaces the download, merge and extract stages
+    if stage == ExecutionStage::Stream {
+        stream_restore(StreamRestoreContext {
+            config,
+            metadata: metadata_json,
+            snapshot_dir,
+            db_dir,
+            downloads,
+            shard_id,
+        })
+        .await?;
+        return Ok(());
+    }
+
+    // Download stage
+    let mut filenames_in_order = vec![];
+
+    if should_download {
+        let ctx = ShardDownloadContext {
+            config,
+            downloads,
+            metadata: metadata_json,
+            snapshot_dir,
+            shard_id,
+            base_path,
+        };
+        filenames_in_order = download_shard_chunks(ctx).await?;
+
+        info!(
+            "✅ Downloaded {} chunks for shard {}",
+            filenames_in_order.len(),
+            shard_id
         );
-        progress_bar.set_message(format!(
-            "📦 Downloading {} chunks from {} shard(s)",
-            total_chunks,
-            shard_ids.len()
-        ));
-        Some(progress_bar)
     } else {
-        None
-    };
+        // If not downloading, collect existing chunk files
+        for chunk in &metadata_json.chunks {
+            let filename = format!("{}/shard-{}/{}", snapshot_dir, shard_id, chunk);
+            filenames_in_order.push(filename);
+        }
+    }
 
-    // Create semaphore to limit concurrent downloads
-    let semaphore = Arc::new(Semaphore::new(config.max_concurrent_downloads));
+    let local_chunks = filenames_in_order;
 
-    // Process each shard sequentially
-    for &shard_id in &shard_ids {
-        let metadata_json = all_metadata.get(&shard_id.to_string()).ok_or_else(|| {
-            SnapshotError::DownloadFailed(format!(
-                "Metadata not found for shard {}. Available shards in metadata: {:?}. \
-                 This usually means the metadata.json file is incomplete or outdated. \
-                 Try running without --stage flag to re-download metadata.",
-                shard_id,
-                all_metadata.keys().collect::<Vec<_>>()
-            ))
-        })?;
-        let base_path = &metadata_json.key_base;
-
-        std::fs::create_dir_all(format!("{}/shard-{}", snapshot_dir, shard_id))?;
-
-        // Download stage
-        let mut filenames_in_order = vec![];
-
-        if should_download {
-            if let Some(ref pb) = pb {
-                let ctx = ShardDownloadContext {
-                    config,
-                    metadata: metadata_json,
-                    snapshot_dir: &snapshot_dir,
-                    shard_id,
-                    base_path,
-                    pb,
-                    semaphore: &semaphore,
-                    shard_ids: &shard_ids,
-                };
-                filenames_in_order = download_shard_chunks(ctx).await?;
+    // Return early if only downloading
+    if stage == ExecutionStage::DownloadOnly {
+        return Ok(());
+    }
 
-                pb.finish_with_message(format!(
-                    "✅ Downloaded {} chunks for shard {}",
-                    filenames_in_order.len(),
-                    shard_id
-                ));
-            }
-        } else {
-            // If not downloading, collect existing chunk files
-            for chunk in &metadata_json.chunks {
-                let filename = format!("{}/shard-{}/{}", snapshot_dir, shard_id, chunk);
-                filenames_in_order.push(filename);
-            }
-        }
+    // Define tar filename for both merge and extract stages
+    let tar_filename = merge::tar_path(snapshot_dir, shard_id);
 
-        let local_chunks = filenames_in_order;
+    // Merge stage
+    if !should_merge {
+        // Skip to extraction
+        info!("Skipping merge stage for shard {}", shard_id);
+    } else {
+        // One merge at a time, so the memory limit holds across shards
+        let _merge_permit = merge_slot.acquire().await.unwrap();
 
-        // Return early if only downloading
-        if stage == ExecutionStage::DownloadOnly {
-            continue;
-        }
+        merge_chunks(
+            base_path,
+            &local_chunks,
+            &tar_filename,
+            downloads.progress.as_ref(),
+            shard_id,
+            config.merge_memory_limit,
+            &downloads.cancel,
+        )
+        .await?;
+    }
 
-        // Define tar filename for both merge and extract stages
-        let tar_filename = format!("{}/shard_{}_snapshot.tar", snapshot_dir, shard_id);
-
-        // Merge stage
-        if !should_merge {
-            // Skip to extraction
-            info!("Skipping merge stage for shard {}", shard_id);
-        } else {
-            // Create new progress bar for merging phase
-            let merge_pb = indicatif::ProgressBar::new(local_chunks.len() as u64);
-            merge_pb.set_style(
-                indicatif::ProgressStyle::default_bar()
-                    .template("{spinner:.cyan} [{bar:40.cyan/blue}] {pos}/{len} {msg} | {elapsed_precise} elapsed, ETA {eta_precise}")
-                    .unwrap()
-                    .progress_chars("█▓▒░ "),
-            );
-            merge_pb.set_message(format!("🔄 Merging shard {} chunks", shard_id));
+    // Return early if only merging
+    if stage == ExecutionStage::MergeOnly {
+        return Ok(());
+    }
 
-            merge_chunks(&local_chunks, &tar_filename, &merge_pb, shard_id).await?;
-        }
+    // Extract stage
+    if !should_extract {
+        info!("Skipping extract stage for shard {}", shard_id);
+        return Ok(());
+    }
 
-        // Return early if only merging
-        if stage == ExecutionStage::MergeOnly {
-            continue;
-        }
+    // Estimate file count based on tar size to skip expensive counting
+    let tar_metadata = std::fs::metadata(&tar_filename)?;
+    let tar_size_bytes = tar_metadata.len();
+    let tar_size_gb = tar_size_bytes as f64 / 1_073_741_824.0;
 
-        // Extract stage
-        if !should_extract {
-            info!("Skipping extract stage for shard {}", shard_id);
-            continue;
-        }
+    // Smart estimation: scan first 100 entries to find max SST number
+    // Tar files are in reverse order (e.g., 004907.sst -> ... -> 000001.sst)
+    // So the first SST file we see has the maximum number
+    let estimated_files = tokio::task::spawn_blocking({
+        let tar_filename = tar_filename.clone();
+        move || -> Result<u64, SnapshotError> {
+            let file = std::fs::File::open(&tar_filename)?;
+            let mut archive = tar::Archive::new(file);
+            let mut max_sst_number = 0u64;
 
-        // Estimate file count based on tar size to skip expensive counting
-        let tar_metadata = std::fs::metadata(&tar_filename)?;
-        let tar_size_bytes = tar_metadata.len();
-        let tar_size_gb = tar_size_bytes as f64 / 1_073_741_824.0;
-
-        // Smart estimation: scan first 100 entries to find max SST number
-        // Tar files are in reverse order (e.g., 004907.sst -> ... -> 000001.sst)
-        // So the first SST file we see has the maximum number
-        let estimated_files = tokio::task::spawn_blocking({
-            let tar_filename = tar_filename.clone();
-            move || -> Result<u64, SnapshotError> {
-                let file = std::fs::File::open(&tar_filename)?;
-                let mut archive = tar::Archive::new(file);
-                let mut max_sst_number = 0u64;
-
-                // Scan first 100 entries (very fast, < 1 second)
-                for entry in archive.entries()?.take(100).flatten() {
-                    if let Ok(path) = entry.path() {
-                        let path_str = path.to_string_lossy();
-                        // Extract SST number from filename like "shard-2/004907.sst"
-                        if path_str.ends_with(".sst") {
-                            if let Some(filename) = path_str.split('/').next_back() {
-                                if let Some(number_str) = filename.strip_suffix(".sst") {
-                                    if let Ok(number) = number_str.parse::<u64>() {
-                                        max_sst_number = max_sst_number.max(number);
-                                    }
+            // Scan first 100 entries (very fast, < 1 second)
+            for entry in archive.entries()?.take(100).flatten() {
+                if let Ok(path) = entry.path() {
+                    let path_str = path.to_string_lossy();
+                    // Extract SST number from filename like "shard-2/004907.sst"
+                    if path_str.ends_with(".sst") {
+                        if let Some(filename) = path_str.split('/').next_back() {
+                            if let Some(number_str) = filename.strip_suffix(".sst") {
+                                if let Ok(number) = number_str.parse::<u64>() {
+                                    max_sst_number = max_sst_number.max(number);
                                 }
                             }
                         }
                     }
                 }
-
-                // Add some buffer for non-SST files (MANIFEST, CURRENT, LOG, etc.)
-                // Typically ~10-20 such files
-                Ok(max_sst_number + 20)
             }
-        })
-        .await
-        .map_err(|e| {
-            SnapshotError::IoError(std::io::Error::other(format!("Task join error: {}", e)))
-        })??;
 
-        info!(
-            "📊 Tar file size: {:.2} GB, estimated ~{} files (smart scan of first 100 entries)",
-            tar_size_gb, estimated_files
-        );
+            // Add some buffer for non-SST files (MANIFEST, CURRENT, LOG, etc.)
+            // Typically ~10-20 such files
+            Ok(max_sst_number + 20)
+        }
+    })
+    .await
+    .map_err(|e| {
+        SnapshotError::IoError(std::io::Error::other(format!("Task join error: {}", e)))
+    })??;
 
-        // Create progress bar with estimated total for ETA calculation
-        let extract_pb = indicatif::ProgressBar::new(estimated_files);
-        extract_pb.set_style(
-            indicatif::ProgressStyle::default_bar()
-                .template("{spinner:.cyan} [{bar:40.cyan/blue}] {pos}/{len} {msg} | {elapsed_precise} elapsed, ETA {eta_precise}")
-                .unwrap()
-                .progress_chars("█▓▒░ "),
-        );
-        extract_pb.set_message(format!("📂 Extracting shard {}", shard_id));
+    info!(
+        "📊 Tar file size: {:.2} GB, estimated ~{} files (smart scan of first 100 entries)",
+        tar_size_gb, estimated_files
+    );
 
-        extract_tar(&tar_filename, &db_dir, &extract_pb, shard_id)?;
-    }
+    // Report the estimated total for ETA calculation
+    downloads.progress.on_event(&ProgressEvent::ExtractStarted {
+        shard_id,
+        estimated_entries: estimated_files,
+    });
 
-    if let Some(pb) = pb {
-        pb.finish_with_message("✅ All snapshots downloaded and extracted successfully!");
-    } else {
-        info!("✅ All operations completed successfully!");
-    }
-    Ok(())
+    // Extract on a blocking thread so the other shards keep downloading
+    let db_dir = db_dir.to_string();
+    let progress = Arc::clone(&downloads.progress);
+    let cancel = downloads.cancel.clone();
+    tokio::task::spawn_blocking(move || {
+        extract_tar(&tar_filename, &db_dir, progress.as_ref(), shard_id, &cancel)
+    })
+    .await
+    .map_err(|e| SnapshotError::IoError(std::io::Error::other(format!("Task join error: {}", e))))?
 }
 
 /// Context for downloading shard chunks
 struct ShardDownloadContext<'a> {
     config: &'a DownloadConfig,
+    downloads: &'a DownloadContext,
     metadata: &'a SnapshotMetadata,
     snapshot_dir: &'a str,
     shard_id: u32,
     base_path: &'a str,
-    pb: &'a indicatif::ProgressBar,
-    semaphore: &'a Arc<Semaphore>,
-    shard_ids: &'a [u32],
 }
 
 /// Downloads all chunks for a single shard with parallel downloads.
+///
+/// The download tasks are owned by a [`JoinSet`], so they are aborted when the
+/// first one fails or when this future is dropped.
 async fn download_shard_chunks(
     ctx: ShardDownloadContext<'_>,
 ) -> Result<Vec<String>, SnapshotError> {
-    let mut download_tasks = vec![];
+    let mut download_tasks = JoinSet::new();
     let mut filenames_in_order = vec![];
 
     for chunk in &ctx.metadata.chunks {
-        let download_path = format!(
-            "{}/{}/{}",
-            ctx.config.snapshot_download_url, ctx.base_path, chunk
-        );
+        let key = format!("{}/{}", ctx.base_path, chunk);
         let filename = format!("{}/shard-{}/{}", ctx.snapshot_dir, ctx.shard_id, chunk);
 
+        let checksum = ctx.metadata.checksum(chunk).cloned();
+
         // Check if file already exists and is valid (resumable download support)
-        let chunk_display_name = chunk.clone();
-        match verify_local_file(&filename, &download_path, ctx.config.skip_verify).await {
-            Ok(true) => {
-                // File is already downloaded and verified, skip download
-                ctx.pb
-                    .set_message(format!("| ✅ Verified: {}", chunk_display_name));
-                ctx.pb.inc(1);
-                filenames_in_order.push(filename);
-                continue;
-            }
-            Ok(false) | Err(_) => {
-                // File needs to be downloaded
-            }
+        if ctx
+            .downloads
+            .check_local_chunk(
+                ctx.shard_id,
+                &key,
+                &filename,
+                checksum.as_ref(),
+                ctx.config.skip_verify,
+            )
+            .await?
+        {
+            // File is already downloaded and verified, skip download
+            filenames_in_order.push(filename);
+            continue;
         }
 
         // Prepare download task
-        let semaphore = Arc::clone(ctx.semaphore);
-        let pb_clone = ctx.pb.clone();
-        let _shard_idx = ctx
-            .shard_ids
-            .iter()
-            .position(|&s| s == ctx.shard_id)
-            .unwrap()
-            + 1;
-        let _total_shards = ctx.shard_ids.len();
-        let _total_chunks_in_shard = ctx.metadata.chunks.len();
-        let chunk_name = chunk.clone();
+        let downloads = ctx.downloads.clone();
+        let shard_id = ctx.shard_id;
         let filename_clone = filename.clone();
 
         filenames_in_order.push(filename.clone());
 
-        let task = tokio::spawn(async move {
-            // Acquire semaphore permit
-            let _permit = semaphore.acquire().await.unwrap();
-
-            // Update progress message with current chunk info
-            pb_clone.set_message(format!("| ⬇️  Downloading: {}", chunk_name));
-
-            let retry_strategy = tokio_retry2::strategy::FixedInterval::from_millis(10_000).take(5);
+        download_tasks.spawn(async move {
+            // Acquire a download slot
+            let _permit = downloads.concurrency.acquire().await;
 
-            let result = Retry::spawn(retry_strategy, || {
-                let download_path_clone = download_path.clone();
-                let filename_clone = filename_clone.clone();
-                let pb_inner = pb_clone.clone();
-
-                async move {
-                    let result =
-                        download_file_simple(&download_path_clone, &filename_clone, pb_inner).await;
-                    match result {
-                        Ok(_) => Ok(()),
-                        Err(e) => {
-                            warn!("Failed to download {} due to error: {}", filename_clone, e);
-                            RetryError::to_transient(e)
-                        }
-                    }
-                }
-            })
-            .await;
-
-            pb_clone.inc(1);
-            result
+            download_chunk_with_retry(
+                &downloads,
+                shard_id,
+                &key,
+                &filename_clone,
+                checksum.as_ref(),
+            )
+            .await
         });
-
-        download_tasks.push(task);
     }
 
     // Wait for all downloads in this shard to complete
-    for task in download_tasks {
-        match task.await {
+    while let Some(task) = download_tasks.join_next().await {
+        match task {
             Ok(Ok(_)) => {
                 // Download succeeded
             }
+            Ok(Err(SnapshotError::Cancelled)) => return Err(SnapshotError::Cancelled),
             Ok(Err(e)) => {
                 error!("Download task failed: {}", e);
-                ctx.pb.finish_with_message("❌ Download failed!");
                 return Err(e);
             }
             Err(e) => {
                 error!("Task join error: {}", e);
-                ctx.pb.finish_with_message("❌ Download failed!");
                 return Err(SnapshotError::DownloadFailed(format!("Task failed: {}", e)));
             }
         }
@@ -397,3 +574,912 @@ async fn download_shard_chunks(
 
     Ok(filenames_in_order)
 }
+
+/// Downloads a single chunk, retrying transient failures according to the retry policy.
+///
+/// Interrupted attempts leave a partial file behind, so each retry resumes
+/// where the previous attempt stopped. A `Retry-After` delay requested by the
+/// server is waited out before the regular backoff delay.
+///
+/// Cancellation stops the download right away; data received so far stays in
+/// the partial file and the chunk itself is never left half-written. A transfer
+/// in progress notices the cancellation itself so it can flush what it received,
+/// only the delays between attempts are cut short here.
+pub(crate) async fn download_chunk_with_retry(
+    ctx: &DownloadContext,
+    shard_id: u32,
+    key: &str,
+    filename: &str,
+    checksum: Option<&ChunkChecksum>,
+) -> Result<(), SnapshotError> {
+    let started = Instant::now();
+    let chunk = std::path::Path::new(filename)
+        .file_name()
+        .map_or_else(|| filename.to_string(), |n| n.to_string_lossy().to_string());
+    ctx.progress.on_event(&ProgressEvent::ChunkStarted {
+        shard_id,
+        chunk: chunk.clone(),
+    });
+
+    let transferring = AtomicBool::new(false);
+    let retry = Retry::spawn(ctx.retry.backoff(), || {
+        let chunk = &chunk;
+        let transferring = &transferring;
+
+        async move {
+            transferring.store(true, Ordering::SeqCst);
+            let result = download_file_simple(ctx, shard_id, key, filename, checksum).await;
+            transferring.store(false, Ordering::SeqCst);
+            match result {
+                Ok(_) => Ok(()),
+                Err(SnapshotError::Cancelled) => RetryError::to_permanent(SnapshotError::Cancelled),
+                Err(e) => {
+                    ctx.concurrency.record_failure(&e);
+                    if !e.is_transient() {
+                        error!(
+                            "Failed to download {} due to permanent error: {}",
+                            filename, e
+                        );
+                        return RetryError::to_permanent(e);
+                    }
+                    warn!("Failed to download {} due to error: {}", filename, e);
+                    ctx.progress.on_event(&ProgressEvent::ChunkRetrying {
+                        shard_id,
+                        chunk: chunk.clone(),
+                        error: e.to_string(),
+                    });
+
+                    if let Some(retry_after) = e.retry_after() {
+                        if started.elapsed() + retry_after > ctx.retry.max_elapsed {
+                            return RetryError::to_permanent(e);
+                        }
+                        info!(
+                            "⏳ Server asked to retry {} after {:?}",
+                            filename, retry_after
+                        );
+                        tokio::time::sleep(retry_after).await;
+                    }
+                    RetryError::to_transient(e)
+                }
+            }
+        }
+    });
+    let result = {
+        let mut retry = std::pin::pin!(retry);
+        tokio::select! {
+            result = &mut retry => result,
+            _ = ctx.cancel.cancelled() => {
+                if transferring.load(Ordering::SeqCst) {
+                    retry.await
+                } else {
+                    Err(SnapshotError::Cancelled)
+                }
+            }
+        }
+    };
+    if let Err(SnapshotError::Cancelled) = result {
+        return result;
+    }
+
+    let event = match result {
+        Ok(_) => ProgressEvent::ChunkFinished { shard_id, chunk },
+        Err(ref e) => ProgressEvent::ChunkFailed {
+            shard_id,
+            chunk,
+            error: e.to_string(),
+        },
+    };
+    ctx.progress.on_event(&event);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retry::RetryPolicy;
+    use crate::source::{ByteRange, LocalSource, ObjectInfo, ObjectStream, SnapshotSource};
+    use async_trait::async_trait;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use futures_util::StreamExt;
+    use std::collections::BTreeMap;
+    use std::io::Write;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tokio_util::sync::CancellationToken;
+
+    /// Magic number RocksDB writes at the end of every SST file.
+    const SST_MAGIC: u64 = 0x88e2_41b7_85f4_cff7;
+
+    /// Publishes a snapshot of a shard in `source_dir`, laid out like the bucket.
+    ///
+    /// Returns the files the restore must produce, keyed by their path in the database directory.
+    fn publish_snapshot(
+        source_dir: &Path,
+        shard_id: u32,
+        chunks: usize,
+    ) -> BTreeMap<String, Vec<u8>> {
+        publish_snapshot_version(source_dir, shard_id, chunks, 1)
+    }
+
+    /// Publishes version `version` of a shard's snapshot and points `latest.json` to it.
+    ///
+    /// Each version has one SST file less than the previous one, and files of the
+    /// same name have the same size but different contents.
+    fn publish_snapshot_version(
+        source_dir: &Path,
+        shard_id: u32,
+        chunks: usize,
+        version: u32,
+    ) -> BTreeMap<String, Vec<u8>> {
+        let mut files = BTreeMap::new();
+        files.insert(
+            format!("shard-{}/CURRENT", shard_id),
+            b"MANIFEST-000005\n".to_vec(),
+        );
+        for i in 0..7 - version {
+            let mut data: Vec<u8> = (0..40_000 + 997 * i)
+                .map(|n| (n % 251) as u8 ^ (i + shard_id + 7 * (version - 1)) as u8)
+                .collect();
+            data.extend_from_slice(&SST_MAGIC.to_le_bytes());
+            files.insert(format!("shard-{}/{:06}.sst", shard_id, 10 + i), data);
+        }
+
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in &files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, path, data.as_slice())
+                .unwrap();
+        }
+        let tar = builder.into_inner().unwrap();
+
+        let key_base = format!("net/{}/snapshot-{}", shard_id, version);
+        std::fs::create_dir_all(source_dir.join(&key_base)).unwrap();
+        let mut names = Vec::new();
+        for (i, piece) in tar.chunks(tar.len().div_ceil(chunks)).enumerate() {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(piece).unwrap();
+            let name = format!("chunk_{:04}.bin", i);
+            std::fs::write(
+                source_dir.join(&key_base).join(&name),
+                encoder.finish().unwrap(),
+            )
+            .unwrap();
+            names.push(name);
+        }
+        let metadata = SnapshotMetadata {
+            key_base,
+            chunks: names,
+            timestamp: version as i64,
+            checksums: HashMap::new(),
+            pinned: false,
+        };
+        std::fs::write(
+            source_dir.join(format!("net/{}/latest.json", shard_id)),
+            serde_json::to_vec(&metadata).unwrap(),
+        )
+        .unwrap();
+        files
+    }
+
+    /// Reads the files restored for a shard, keyed by their path in the database directory.
+    fn read_tree(db_dir: &Path, shard_id: u32) -> BTreeMap<String, Vec<u8>> {
+        let mut files = BTreeMap::new();
+        let Ok(entries) = std::fs::read_dir(db_dir.join(format!("shard-{}", shard_id))) else {
+            return files;
+        };
+        for entry in entries {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            files.insert(
+                format!("shard-{}/{}", shard_id, name),
+                std::fs::read(&path).unwrap(),
+            );
+        }
+        files
+    }
+
+    /// Configuration restoring from `dir/source` through `dir/snapshot`.
+    fn local_config(dir: &Path) -> DownloadConfig {
+        DownloadConfig {
+            snapshot_download_url: dir.join("source").to_str().unwrap().to_string(),
+            snapshot_download_dir: dir.join("snapshot").to_str().unwrap().to_string(),
+            network: "net".to_string(),
+            max_concurrent_downloads: 2,
+            retry_policy: RetryPolicy {
+                initial_delay: Duration::from_millis(1),
+                max_attempts: 2,
+                jitter: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Local source that tracks the chunk requests in flight.
+    ///
+    /// Chunk requests under `stalled` never complete, like a hung connection.
+    #[derive(Debug)]
+    struct TrackedSource {
+        inner: LocalSource,
+        stalled: Option<String>,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    impl TrackedSource {
+        fn new(root: &Path, stalled: Option<&str>) -> Arc<Self> {
+            Arc::new(Self {
+                inner: LocalSource::new(root),
+                stalled: stalled.map(str::to_string),
+                in_flight: AtomicUsize::new(0),
+                max_in_flight: AtomicUsize::new(0),
+            })
+        }
+    }
+
+    /// Counts a chunk request as in flight until dropped.
+    struct InFlight<'a>(&'a AtomicUsize);
+
+    impl Drop for InFlight<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait]
+    impl SnapshotSource for TrackedSource {
+        fn describe(&self, key: &str) -> String {
+            self.inner.describe(key)
+        }
+
+        async fn stat(&self, key: &str) -> Result<ObjectInfo, SnapshotError> {
+            self.inner.stat(key).await
+        }
+
+        async fn get(
+            &self,
+            key: &str,
+            range: Option<ByteRange>,
+        ) -> Result<ObjectStream, SnapshotError> {
+            if !key.ends_with(".bin") {
+                return self.inner.get(key, range).await;
+            }
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            let _guard = InFlight(&self.in_flight);
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            if self.stalled.as_ref().is_some_and(|s| key.starts_with(s)) {
+                std::future::pending::<()>().await;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.inner.get(key, range).await
+        }
+    }
+
+    /// Local source whose objects have an MD5 ETag, so partial files can be resumed.
+    ///
+    /// The first request for `interrupt`'s key fails after that many bytes,
+    /// or hangs there if `hang` is set.
+    #[derive(Debug)]
+    struct ResumableSource {
+        inner: LocalSource,
+        interrupt: Mutex<Option<(String, usize)>>,
+        hang: bool,
+    }
+
+    impl ResumableSource {
+        async fn etag(&self, key: &str) -> Result<String, SnapshotError> {
+            use md5::Digest;
+            let data = self.inner.fetch(key).await?;
+            Ok(format!("{:x}", md5::Md5::digest(&data)))
+        }
+    }
+
+    #[async_trait]
+    impl SnapshotSource for ResumableSource {
+        fn describe(&self, key: &str) -> String {
+            self.inner.describe(key)
+        }
+
+        async fn stat(&self, key: &str) -> Result<ObjectInfo, SnapshotError> {
+            let info = self.inner.stat(key).await?;
+            Ok(ObjectInfo {
+                etag: Some(self.etag(key).await?),
+                ..info
+            })
+        }
+
+        async fn get(
+            &self,
+            key: &str,
+            range: Option<ByteRange>,
+        ) -> Result<ObjectStream, SnapshotError> {
+            let etag = self.etag(key).await?;
+            // Honor the range like a server does for a matching If-Range
+            let range = range
+                .filter(|range| range.if_etag.as_deref() == Some(etag.as_str()))
+                .map(|range| ByteRange {
+                    if_etag: None,
+                    ..range
+                });
+            let mut object = self.inner.get(key, range).await?;
+            object.info.etag = Some(etag);
+
+            let interrupt = {
+                let mut interrupt = self.interrupt.lock().unwrap();
+                match interrupt.take() {
+                    Some((interrupted, after)) if interrupted == key => Some(after),
+                    other => {
+                        *interrupt = other;
+                        None
+                    }
+                }
+            };
+            if let Some(after) = interrupt {
+                let mut received = Vec::new();
+                while let Some(piece) = object.stream.next().await {
+                    received.extend_from_slice(&piece?);
+                }
+                received.truncate(after);
+                let received = futures_util::stream::iter([Ok(bytes::Bytes::from(received))]);
+                object.stream = if self.hang {
+                    Box::pin(received.chain(futures_util::stream::pending()))
+                } else {
+                    let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+                    Box::pin(received.chain(futures_util::stream::iter([Err(
+                        SnapshotError::from(reset),
+                    )])))
+                };
+            }
+            Ok(object)
+        }
+    }
+
+    /// Observer that records every event.
+    #[derive(Debug, Default)]
+    struct RecordingObserver(Mutex<Vec<ProgressEvent>>);
+
+    impl RecordingObserver {
+        /// Returns the events recorded so far and starts over.
+        fn take(&self) -> Vec<ProgressEvent> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    impl ProgressObserver for RecordingObserver {
+        fn on_event(&self, event: &ProgressEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    /// Records events and cancels the restore once bytes of `chunk` arrive.
+    #[derive(Debug)]
+    struct CancelOnBytes {
+        events: RecordingObserver,
+        cancel: CancellationToken,
+        chunk: &'static str,
+    }
+
+    impl ProgressObserver for CancelOnBytes {
+        fn on_event(&self, event: &ProgressEvent) {
+            if matches!(event, ProgressEvent::ChunkBytes { chunk, .. } if chunk == self.chunk) {
+                self.cancel.cancel();
+            }
+            self.events.on_event(event);
+        }
+    }
+
+    /// Returns the chunk an event is about, if any.
+    fn event_chunk(event: &ProgressEvent) -> Option<&str> {
+        match event {
+            ProgressEvent::ChunkVerified { chunk, .. }
+            | ProgressEvent::ChunkSized { chunk, .. }
+            | ProgressEvent::ChunkStarted { chunk, .. }
+            | ProgressEvent::ChunkTransferStarted { chunk, .. }
+            | ProgressEvent::ChunkBytes { chunk, .. }
+            | ProgressEvent::ChunkRetrying { chunk, .. }
+            | ProgressEvent::ChunkFinished { chunk, .. }
+            | ProgressEvent::ChunkFailed { chunk, .. } => Some(chunk),
+            _ => None,
+        }
+    }
+
+    async fn restore(
+        config: &DownloadConfig,
+        db_dir: &Path,
+        shard_ids: Vec<u32>,
+        stage: ExecutionStage,
+    ) -> Result<(), SnapshotError> {
+        let db_dir = db_dir.to_str().unwrap().to_string();
+        download_snapshots(config, db_dir, shard_ids, stage).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_restore_from_local_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = publish_snapshot(&dir.path().join("source"), 0, 3);
+        let config = local_config(dir.path());
+        let db_dir = dir.path().join("db");
+
+        // A partial chunk left over from another version of the object is not resumed
+        let partial = dir.path().join("snapshot/shard-0/chunk_0001.bin");
+        std::fs::create_dir_all(partial.parent().unwrap()).unwrap();
+        std::fs::write(partial.with_extension("bin.part"), b"stale bytes").unwrap();
+        std::fs::write(partial.with_extension("bin.part.etag"), "stale-etag").unwrap();
+
+        restore(&config, &db_dir, vec![0], ExecutionStage::All)
+            .await
+            .unwrap();
+        assert_eq!(read_tree(&db_dir, 0), files);
+
+        // A rerun finds everything in place
+        restore(&config, &db_dir, vec![0], ExecutionStage::All)
+            .await
+            .unwrap();
+        assert_eq!(read_tree(&db_dir, 0), files);
+
+        // Stage by stage and by streaming give the same result
+        let staged_db_dir = dir.path().join("staged");
+        for stage in [
+            ExecutionStage::DownloadOnly,
+            ExecutionStage::MergeOnly,
+            ExecutionStage::ExtractOnly,
+        ] {
+            restore(&config, &staged_db_dir, vec![0], stage)
+                .await
+                .unwrap();
+        }
+        assert_eq!(read_tree(&staged_db_dir, 0), files);
+
+        let streamed_db_dir = dir.path().join("streamed");
+        let config = DownloadConfig {
+            snapshot_download_dir: dir.path().join("stream").to_str().unwrap().to_string(),
+            ..config
+        };
+        restore(&config, &streamed_db_dir, vec![0], ExecutionStage::Stream)
+            .await
+            .unwrap();
+        assert_eq!(read_tree(&streamed_db_dir, 0), files);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shards_share_download_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = dir.path().join("source");
+        let files = [
+            publish_snapshot(&source_dir, 0, 3),
+            publish_snapshot(&source_dir, 1, 3),
+        ];
+        let source = TrackedSource::new(&source_dir, None);
+        let config = DownloadConfig {
+            source: Some(source.clone()),
+            ..local_config(dir.path())
+        };
+        let db_dir = dir.path().join("db");
+
+        restore(&config, &db_dir, vec![0, 1], ExecutionStage::All)
+            .await
+            .unwrap();
+        assert_eq!(read_tree(&db_dir, 0), files[0]);
+        assert_eq!(read_tree(&db_dir, 1), files[1]);
+        // Chunks of both shards were fetched concurrently, never above the budget
+        assert_eq!(source.max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_failed_shard_stops_other_shards() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = dir.path().join("source");
+        publish_snapshot(&source_dir, 0, 3);
+        publish_snapshot(&source_dir, 1, 3);
+        std::fs::remove_file(source_dir.join("net/1/snapshot-1/chunk_0001.bin")).unwrap();
+
+        // Shard 0 hangs, so only the failure of shard 1 can end the restore
+        let source = TrackedSource::new(&source_dir, Some("net/0/"));
+        let config = DownloadConfig {
+            source: Some(source.clone()),
+            max_concurrent_downloads: 4,
+            ..local_config(dir.path())
+        };
+
+        let result = restore(
+            &config,
+            &dir.path().join("db"),
+            vec![0, 1],
+            ExecutionStage::All,
+        )
+        .await;
+        assert!(
+            matches!(result, Err(SnapshotError::NotFound(_))),
+            "{:?}",
+            result
+        );
+        assert!(!config.cancel.is_cancelled());
+
+        // The hung downloads of shard 0 are abandoned, not left running
+        for _ in 0..100 {
+            if source.in_flight.load(Ordering::SeqCst) == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(source.in_flight.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_progress_event_sequence() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = publish_snapshot(&dir.path().join("source"), 0, 3);
+        let observer = Arc::new(RecordingObserver::default());
+        let config = DownloadConfig {
+            progress: Some(observer.clone()),
+            ..local_config(dir.path())
+        };
+        let db_dir = dir.path().join("db");
+        let chunks = ["chunk_0000.bin", "chunk_0001.bin", "chunk_0002.bin"];
+
+        restore(&config, &db_dir, vec![0], ExecutionStage::All)
+            .await
+            .unwrap();
+        let events = observer.take();
+        assert_eq!(
+            events[..2],
+            [
+                ProgressEvent::MetadataFetched {
+                    shard_id: 0,
+                    key_base: "net/0/snapshot-1".to_string(),
+                    chunks: 3,
+                },
+                ProgressEvent::DownloadStarted {
+                    total_chunks: 3,
+                    shards: 1,
+                },
+            ]
+        );
+
+        // Each chunk starts, transfers from the beginning and finishes, in that order
+        let merge_started = events
+            .iter()
+            .position(|e| matches!(e, ProgressEvent::MergeStarted { .. }))
+            .unwrap();
+        for chunk in chunks {
+            let kinds: Vec<&str> = events
+                .iter()
+                .filter(|e| event_chunk(e) == Some(chunk))
+                .map(|e| match e {
+                    ProgressEvent::ChunkStarted { .. } => "started",
+                    ProgressEvent::ChunkTransferStarted { offset: 0, .. } => "transfer",
+                    ProgressEvent::ChunkSized { .. } => "sized",
+                    ProgressEvent::ChunkBytes { .. } => "bytes",
+                    ProgressEvent::ChunkFinished { .. } => "finished",
+                    e => panic!("unexpected event {:?}", e),
+                })
+                .collect();
+            assert_eq!(kinds[..3], ["started", "transfer", "sized"], "{}", chunk);
+            assert!(kinds[3..kinds.len() - 1].iter().all(|k| *k == "bytes"));
+            assert_eq!(kinds.last(), Some(&"finished"));
+            let finished = events
+                .iter()
+                .position(
+                    |e| matches!(e, ProgressEvent::ChunkFinished { chunk: c, .. } if c == chunk),
+                )
+                .unwrap();
+            assert!(finished < merge_started);
+        }
+
+        // Then the shard is merged and extracted
+        let stages: Vec<&ProgressEvent> = events[merge_started..].iter().collect();
+        assert_eq!(
+            *stages[0],
+            ProgressEvent::MergeStarted {
+                shard_id: 0,
+                total_chunks: 3,
+                resumed_chunks: 0,
+            }
+        );
+        for (i, chunk) in chunks.iter().enumerate() {
+            assert_eq!(
+                *stages[1 + i],
+                ProgressEvent::MergeProgress {
+                    shard_id: 0,
+                    chunk: chunk.to_string(),
+                    merged_chunks: i + 1,
+                    total_chunks: 3,
+                }
+            );
+        }
+        assert_eq!(
+            *stages[4],
+            ProgressEvent::MergeFinished {
+                shard_id: 0,
+                total_chunks: 3,
+            }
+        );
+        assert!(matches!(
+            stages[5],
+            ProgressEvent::ExtractStarted { shard_id: 0, .. }
+        ));
+        let written: Vec<(String, u64)> = stages[6..6 + files.len()]
+            .iter()
+            .map(|e| match e {
+                ProgressEvent::ExtractEntryWritten {
+                    shard_id: 0,
+                    path,
+                    size,
+                } => (path.clone(), *size),
+                e => panic!("unexpected event {:?}", e),
+            })
+            .collect();
+        let expected: Vec<(String, u64)> = files
+            .iter()
+            .map(|(path, data)| (path.clone(), data.len() as u64))
+            .collect();
+        assert_eq!(written, expected);
+        assert_eq!(
+            stages[6 + files.len()..],
+            [
+                &ProgressEvent::ExtractFinished {
+                    shard_id: 0,
+                    written: files.len() as u64,
+                    skipped: 0,
+                },
+                &ProgressEvent::Finished,
+            ]
+        );
+
+        // A rerun reports every chunk as verified and every entry as skipped
+        restore(&config, &db_dir, vec![0], ExecutionStage::All)
+            .await
+            .unwrap();
+        let events = observer.take();
+        let verified: Vec<&str> = events
+            .iter()
+            .filter_map(|e| match e {
+                ProgressEvent::ChunkVerified { chunk, .. } => Some(chunk.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(verified, chunks);
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, ProgressEvent::ChunkStarted { .. })));
+        assert!(events.contains(&ProgressEvent::MergeStarted {
+            shard_id: 0,
+            total_chunks: 3,
+            resumed_chunks: 3,
+        }));
+        let skipped = events
+            .iter()
+            .filter(|e| matches!(e, ProgressEvent::ExtractEntrySkipped { .. }))
+            .count();
+        assert_eq!(skipped, files.len());
+        assert_eq!(events.last(), Some(&ProgressEvent::Finished));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_progress_byte_accounting() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = dir.path().join("source");
+        let files = publish_snapshot(&source_dir, 0, 3);
+        let remote = source_dir.join("net/0/snapshot-1");
+        let local = dir.path().join("snapshot/shard-0");
+        let sizes: Vec<u64> = (0..3)
+            .map(|i| {
+                let path = remote.join(format!("chunk_{:04}.bin", i));
+                std::fs::metadata(path).unwrap().len()
+            })
+            .collect();
+
+        // chunk_0000 is complete, chunk_0002 was interrupted by a previous run,
+        // and chunk_0001 is interrupted halfway through this run
+        std::fs::create_dir_all(&local).unwrap();
+        std::fs::copy(remote.join("chunk_0000.bin"), local.join("chunk_0000.bin")).unwrap();
+        let resumed = sizes[2] / 3;
+        let data = std::fs::read(remote.join("chunk_0002.bin")).unwrap();
+        std::fs::write(local.join("chunk_0002.bin.part"), &data[..resumed as usize]).unwrap();
+        let source = ResumableSource {
+            inner: LocalSource::new(&source_dir),
+            interrupt: Mutex::new(Some((
+                "net/0/snapshot-1/chunk_0001.bin".to_string(),
+                sizes[1] as usize / 2,
+            ))),
+            hang: false,
+        };
+        let etag = source
+            .etag("net/0/snapshot-1/chunk_0002.bin")
+            .await
+            .unwrap();
+        std::fs::write(local.join("chunk_0002.bin.part.etag"), etag).unwrap();
+
+        let observer = Arc::new(RecordingObserver::default());
+        let config = DownloadConfig {
+            source: Some(Arc::new(source)),
+            progress: Some(observer.clone()),
+            ..local_config(dir.path())
+        };
+        let db_dir = dir.path().join("db");
+        restore(&config, &db_dir, vec![0], ExecutionStage::DownloadOnly)
+            .await
+            .unwrap();
+        let events = observer.take();
+
+        // The complete chunk is reported with its size and not transferred
+        assert!(events.contains(&ProgressEvent::ChunkVerified {
+            shard_id: 0,
+            chunk: "chunk_0000.bin".to_string(),
+            size: sizes[0],
+        }));
+        assert!(!events
+            .iter()
+            .any(|e| event_chunk(e) == Some("chunk_0000.bin")
+                && !matches!(e, ProgressEvent::ChunkVerified { .. })));
+
+        // Transfers continue from the bytes already on disk
+        let offsets = |chunk: &str| -> Vec<u64> {
+            events
+                .iter()
+                .filter_map(|e| match e {
+                    ProgressEvent::ChunkTransferStarted {
+                        chunk: c, offset, ..
+                    } if c == chunk => Some(*offset),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(offsets("chunk_0001.bin"), vec![0, sizes[1] / 2]);
+        assert_eq!(offsets("chunk_0002.bin"), vec![resumed]);
+
+        // Replaying the events like a progress bar counts every byte exactly once
+        let mut on_disk: HashMap<String, u64> = HashMap::new();
+        let mut received = 0;
+        for event in &events {
+            match event {
+                ProgressEvent::ChunkVerified { chunk, size, .. } => {
+                    on_disk.insert(chunk.clone(), *size);
+                }
+                ProgressEvent::ChunkTransferStarted { chunk, offset, .. } => {
+                    on_disk.insert(chunk.clone(), *offset);
+                }
+                ProgressEvent::ChunkBytes { chunk, bytes, .. } => {
+                    *on_disk.get_mut(chunk).unwrap() += bytes;
+                    received += bytes;
+                }
+                _ => {}
+            }
+            assert!(on_disk.values().sum::<u64>() <= sizes.iter().sum());
+        }
+        for (i, size) in sizes.iter().enumerate() {
+            assert_eq!(on_disk[&format!("chunk_{:04}.bin", i)], *size);
+        }
+        assert_eq!(received, sizes[1] + sizes[2] - resumed);
+
+        for stage in [ExecutionStage::MergeOnly, ExecutionStage::ExtractOnly] {
+            restore(&config, &db_dir, vec![0], stage).await.unwrap();
+        }
+        assert_eq!(read_tree(&db_dir, 0), files);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cancelled_restore_keeps_partial_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = dir.path().join("source");
+        let files = publish_snapshot(&source_dir, 0, 3);
+        let key = "net/0/snapshot-1/chunk_0001.bin";
+        let data = std::fs::read(source_dir.join(key)).unwrap();
+        let received = data.len() / 2;
+
+        // chunk_0001 stalls halfway through and the restore is cancelled meanwhile
+        let source: Arc<ResumableSource> = Arc::new(ResumableSource {
+            inner: LocalSource::new(&source_dir),
+            interrupt: Mutex::new(Some((key.to_string(), received))),
+            hang: true,
+        });
+        let cancel = CancellationToken::new();
+        let observer = Arc::new(CancelOnBytes {
+            events: RecordingObserver::default(),
+            cancel: cancel.clone(),
+            chunk: "chunk_0001.bin",
+        });
+        let config = DownloadConfig {
+            source: Some(source.clone()),
+            progress: Some(observer.clone()),
+            cancel,
+            ..local_config(dir.path())
+        };
+        let db_dir = dir.path().join("db");
+
+        let result = restore(&config, &db_dir, vec![0], ExecutionStage::All).await;
+        assert!(
+            matches!(result, Err(SnapshotError::Cancelled)),
+            "{:?}",
+            result
+        );
+        let events = observer.events.take();
+        assert_eq!(events.last(), Some(&ProgressEvent::Cancelled));
+        assert!(!events.iter().any(|e| matches!(
+            e,
+            ProgressEvent::ChunkFailed { .. } | ProgressEvent::ShardFailed { .. }
+        )));
+
+        // The bytes received so far are kept for the next run
+        let partial = dir.path().join("snapshot/shard-0/chunk_0001.bin");
+        assert!(!partial.exists());
+        let part = std::fs::read(partial.with_extension("bin.part")).unwrap();
+        assert_eq!(part, data[..received]);
+        assert!(partial.with_extension("bin.part.etag").exists());
+        assert!(read_tree(&db_dir, 0).is_empty());
+
+        // A run cancelled before it starts leaves them alone
+        let result = restore(&config, &db_dir, vec![0], ExecutionStage::All).await;
+        assert!(
+            matches!(result, Err(SnapshotError::Cancelled)),
+            "{:?}",
+            result
+        );
+        assert_eq!(
+            std::fs::read(partial.with_extension("bin.part")).unwrap(),
+            part
+        );
+
+        // The next run resumes the partial chunk
+        let config = DownloadConfig {
+            cancel: CancellationToken::new(),
+            ..config
+        };
+        restore(&config, &db_dir, vec![0], ExecutionStage::All)
+            .await
+            .unwrap();
+        assert!(observer
+            .events
+            .take()
+            .contains(&ProgressEvent::ChunkTransferStarted {
+                shard_id: 0,
+                chunk: "chunk_0001.bin".to_string(),
+                offset: received as u64,
+            }));
+        assert_eq!(read_tree(&db_dir, 0), files);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_new_snapshot_keeps_restored_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = dir.path().join("source");
+        let mut config = local_config(dir.path());
+        let db_dir = dir.path().join("db");
+
+        let restored = publish_snapshot_version(&source_dir, 0, 3, 1);
+        restore(&config, &db_dir, vec![0], ExecutionStage::All)
+            .await
+            .unwrap();
+        assert_eq!(read_tree(&db_dir, 0), restored);
+
+        // latest.json moves on: the complete database is neither deleted nor overwritten
+        publish_snapshot_version(&source_dir, 0, 3, 2);
+        assert!(matches!(
+            restore(&config, &db_dir, vec![0], ExecutionStage::All).await,
+            Err(SnapshotError::StaleDatabase { shard: 0, .. })
+        ));
+        assert_eq!(read_tree(&db_dir, 0), restored);
+        restore(&config, &db_dir, vec![0], ExecutionStage::DownloadOnly)
+            .await
+            .unwrap();
+        assert_eq!(read_tree(&db_dir, 0), restored);
+
+        // Replacing it must be asked for, and leaves nothing of the previous snapshot
+        let files = publish_snapshot_version(&source_dir, 0, 3, 3);
+        config.clear_db_on_snapshot_change = true;
+        restore(&config, &db_dir, vec![0], ExecutionStage::All)
+            .await
+            .unwrap();
+        assert_eq!(
+            read_tree(&db_dir, 0).keys().collect::<Vec<_>>(),
+            files.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(read_tree(&db_dir, 0), files);
+    }
+}
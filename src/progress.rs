@@ -0,0 +1,242 @@
+//! Typed progress events for embedding applications.
+//!
+//! The library never draws progress bars itself. Every stage reports what it
+//! is doing to a [`ProgressObserver`], which can render bars, export metrics or
+//! ignore the events altogether.
+
+use std::fmt::Debug;
+
+/// Something that happened while restoring a snapshot.
+///
+/// Chunk events carry the chunk file name (e.g. `chunk_0001.bin`); extract
+/// events carry the entry path relative to the database directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProgressEvent {
+    /// Metadata for a shard was fetched from the source.
+    MetadataFetched {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Snapshot base path.
+        key_base: String,
+        /// Number of chunks in the snapshot.
+        chunks: usize,
+    },
+    /// The download stage is about to start.
+    DownloadStarted {
+        /// Number of chunks across all requested shards.
+        total_chunks: usize,
+        /// Number of requested shards.
+        shards: usize,
+    },
+    /// A local chunk was verified and does not need to be downloaded.
+    ChunkVerified {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Chunk file name.
+        chunk: String,
+        /// Chunk size in bytes.
+        size: u64,
+    },
+    /// The size of a chunk became known, from a metadata lookup or a response's Content-Length.
+    ///
+    /// May be reported before [`ProgressEvent::ChunkStarted`], which allows
+    /// sizing the whole download up front.
+    ChunkSized {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Chunk file name.
+        chunk: String,
+        /// Chunk size in bytes.
+        size: u64,
+    },
+    /// A chunk download started.
+    ChunkStarted {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Chunk file name.
+        chunk: String,
+    },
+    /// A transfer attempt for a chunk began.
+    ///
+    /// `offset` bytes of the chunk are already on disk from an earlier
+    /// attempt or run; it is 0 when the chunk is downloaded from the start.
+    ChunkTransferStarted {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Chunk file name.
+        chunk: String,
+        /// Bytes already on disk.
+        offset: u64,
+    },
+    /// Bytes of a chunk were received.
+    ChunkBytes {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Chunk file name.
+        chunk: String,
+        /// Number of bytes received since the previous event.
+        bytes: u64,
+    },
+    /// A chunk download attempt failed with a transient error.
+    ChunkRetrying {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Chunk file name.
+        chunk: String,
+        /// Description of the failure.
+        error: String,
+    },
+    /// A chunk was downloaded and verified.
+    ChunkFinished {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Chunk file name.
+        chunk: String,
+    },
+    /// A chunk download failed for good.
+    ChunkFailed {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Chunk file name.
+        chunk: String,
+        /// Description of the failure.
+        error: String,
+    },
+    /// Merging the chunks of a shard into a tar started.
+    MergeStarted {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Number of chunks to merge.
+        total_chunks: usize,
+        /// Number of chunks already merged by a previous run.
+        resumed_chunks: usize,
+    },
+    /// A chunk was appended to the tar.
+    MergeProgress {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Chunk file name.
+        chunk: String,
+        /// Number of chunks merged so far.
+        merged_chunks: usize,
+        /// Number of chunks to merge.
+        total_chunks: usize,
+    },
+    /// All chunks of a shard were merged.
+    MergeFinished {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Number of chunks merged.
+        total_chunks: usize,
+    },
+    /// Extraction of a shard's tar started.
+    ExtractStarted {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Estimated number of entries in the tar.
+        estimated_entries: u64,
+    },
+    /// An entry was written to the database directory.
+    ExtractEntryWritten {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Entry path.
+        path: String,
+        /// Entry size in bytes.
+        size: u64,
+    },
+    /// An entry already present in the database directory was kept.
+    ExtractEntrySkipped {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Entry path.
+        path: String,
+        /// Entry size in bytes.
+        size: u64,
+    },
+    /// Extraction of a shard finished.
+    ExtractFinished {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Number of entries written.
+        written: u64,
+        /// Number of entries skipped.
+        skipped: u64,
+    },
+    /// A streaming restore of a shard started.
+    StreamStarted {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Number of chunks in the snapshot.
+        total_chunks: usize,
+        /// Number of chunks already unpacked by a previous run.
+        resumed_chunks: usize,
+    },
+    /// A chunk was fully unpacked during a streaming restore.
+    StreamChunkUnpacked {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Chunk file name.
+        chunk: String,
+    },
+    /// A streaming restore of a shard finished.
+    StreamFinished {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Number of tar entries written.
+        entries_written: u64,
+    },
+    /// Restoring a shard failed.
+    ShardFailed {
+        /// Shard identifier.
+        shard_id: u32,
+        /// Description of the failure.
+        error: String,
+    },
+    /// All requested shards were restored.
+    Finished,
+    /// The restore was cancelled; rerunning it resumes where it stopped.
+    Cancelled,
+}
+
+/// Receives [`ProgressEvent`]s.
+///
+/// Events are delivered from download tasks and blocking merge/extract threads,
+/// so implementations must be cheap and must not block.
+///
+/// # Example
+///
+/// ```
+/// use snapsync::{DownloadConfig, ProgressEvent, ProgressObserver};
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::sync::Arc;
+///
+/// #[derive(Debug, Default)]
+/// struct ByteCounter(AtomicU64);
+///
+/// impl ProgressObserver for ByteCounter {
+///     fn on_event(&self, event: &ProgressEvent) {
+///         if let ProgressEvent::ChunkBytes { bytes, .. } = event {
+///             self.0.fetch_add(*bytes, Ordering::Relaxed);
+///         }
+///     }
+/// }
+///
+/// let config = DownloadConfig {
+///     progress: Some(Arc::new(ByteCounter::default())),
+///     ..Default::default()
+/// };
+/// ```
+pub trait ProgressObserver: Send + Sync + Debug {
+    /// Handles one event.
+    fn on_event(&self, event: &ProgressEvent);
+}
+
+/// Observer that ignores all events, used when none is configured.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct NoProgress;
+
+impl ProgressObserver for NoProgress {
+    fn on_event(&self, _event: &ProgressEvent) {}
+}
@@ -0,0 +1,109 @@
+//! Retry policy for chunk downloads.
+
+use std::time::Duration;
+use tokio_retry2::strategy::{jitter, ExponentialFactorBackoff, MaxInterval};
+
+/// How failed chunk downloads are retried.
+///
+/// Delays grow exponentially from `initial_delay` up to `max_delay`, optionally
+/// randomized by ±50% so that concurrent downloads don't retry in lockstep.
+/// Randomized delays are still capped at `max_delay`.
+/// Retrying stops after `max_attempts` attempts or once `max_elapsed` has passed,
+/// whichever comes first. Permanent errors (see
+/// [`SnapshotError::is_transient`](crate::SnapshotError::is_transient)) are never retried.
+///
+/// # Example
+///
+/// ```
+/// use snapsync::{DownloadConfig, RetryPolicy};
+/// use std::time::Duration;
+///
+/// let config = DownloadConfig {
+///     retry_policy: RetryPolicy {
+///         max_elapsed: Duration::from_secs(3600),
+///         ..Default::default()
+///     },
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Delay before the first retry (default: 1 second).
+    pub initial_delay: Duration,
+    /// Upper bound for a single delay, jitter included (default: 60 seconds).
+    pub max_delay: Duration,
+    /// Maximum number of attempts, including the first one (default: 10).
+    pub max_attempts: usize,
+    /// Give up once this much time has passed since the first attempt (default: 15 minutes).
+    pub max_elapsed: Duration,
+    /// Randomize delays (default: true).
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_attempts: 10,
+            max_elapsed: Duration::from_secs(15 * 60),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delays between attempts, starting now.
+    pub(crate) fn backoff(&self) -> impl Iterator<Item = Duration> {
+        let (with_jitter, max_delay) = (self.jitter, self.max_delay);
+        ExponentialFactorBackoff::from_millis(self.initial_delay.as_millis() as u64, 2.0)
+            .max_delay(max_delay)
+            .map(move |delay| {
+                if with_jitter {
+                    jitter(delay).min(max_delay)
+                } else {
+                    delay
+                }
+            })
+            .max_duration(self.max_elapsed)
+            .take(self.max_attempts.saturating_sub(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_and_stops() {
+        let policy = RetryPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            max_attempts: 6,
+            jitter: false,
+            ..Default::default()
+        };
+        let delays: Vec<u64> = policy.backoff().map(|d| d.as_millis() as u64).collect();
+        assert_eq!(delays, vec![100, 200, 400, 500, 500]);
+    }
+
+    #[test]
+    fn test_jitter_stays_below_max_delay() {
+        let policy = RetryPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            max_attempts: 50,
+            max_elapsed: Duration::from_secs(3600),
+            jitter: true,
+        };
+        for _ in 0..20 {
+            let delays: Vec<Duration> = policy.backoff().collect();
+            assert_eq!(delays.len(), 49);
+            assert!(
+                delays.iter().all(|d| *d <= policy.max_delay),
+                "{:?}",
+                delays
+            );
+        }
+    }
+}
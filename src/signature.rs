@@ -0,0 +1,126 @@
+//! Detached ed25519 signatures over snapshot metadata.
+//!
+//! A signed snapshot publishes `latest.json.sig` next to `latest.json`. The
+//! signature file holds a 64-byte ed25519 signature over the exact bytes of
+//! `latest.json`, encoded as hex or base64. When trusted keys are configured,
+//! metadata is only accepted if one of them made the signature.
+
+use crate::error::SnapshotError;
+use base64::Engine;
+use ed25519_dalek::{Signature, VerifyingKey};
+use std::str::FromStr;
+
+/// An ed25519 public key trusted to sign snapshot metadata.
+///
+/// # Example
+///
+/// ```
+/// use snapsync::TrustedKey;
+///
+/// let key: TrustedKey = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+///     .parse()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrustedKey(VerifyingKey);
+
+impl TrustedKey {
+    /// Creates a key from its 32 raw bytes.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Result<Self, SnapshotError> {
+        VerifyingKey::from_bytes(bytes)
+            .map(Self)
+            .map_err(|e| SnapshotError::InvalidTrustedKey(e.to_string()))
+    }
+}
+
+impl FromStr for TrustedKey {
+    type Err = SnapshotError;
+
+    /// Parses a hex or base64 encoded key.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = decode(s).ok_or_else(|| {
+            SnapshotError::InvalidTrustedKey(format!("'{}' is not hex or base64", s))
+        })?;
+        let bytes: [u8; 32] = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            SnapshotError::InvalidTrustedKey(format!("expected 32 bytes, got {}", bytes.len()))
+        })?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// Decodes a hex or base64 string, ignoring surrounding whitespace.
+fn decode(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim();
+    hex::decode(s)
+        .ok()
+        .or_else(|| base64::engine::general_purpose::STANDARD.decode(s).ok())
+}
+
+/// Checks a detached signature over metadata against the trusted keys.
+///
+/// # Arguments
+///
+/// * `data` - Raw bytes of the metadata document
+/// * `signature` - Contents of the signature file
+/// * `trusted_keys` - Keys allowed to sign the metadata
+///
+/// # Returns
+///
+/// `Ok(())` if one of the keys made the signature, or a description of the problem.
+pub(crate) fn verify_signature(
+    data: &[u8],
+    signature: &[u8],
+    trusted_keys: &[TrustedKey],
+) -> Result<(), String> {
+    let signature = std::str::from_utf8(signature)
+        .ok()
+        .and_then(decode)
+        .ok_or("signature is not hex or base64")?;
+    let signature = Signature::from_slice(&signature).map_err(|_| {
+        format!(
+            "expected a 64-byte signature, got {} bytes",
+            signature.len()
+        )
+    })?;
+
+    if trusted_keys
+        .iter()
+        .any(|key| key.0.verify_strict(data, &signature).is_ok())
+    {
+        Ok(())
+    } else {
+        Err("not signed by any trusted key".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn test_signature_must_come_from_a_trusted_key() {
+        let signer = SigningKey::from_bytes(&[7; 32]);
+        let other = SigningKey::from_bytes(&[9; 32]);
+        let trusted: TrustedKey = hex::encode(signer.verifying_key().as_bytes())
+            .parse()
+            .unwrap();
+        let data = br#"{"key_base":"snap","chunks":[],"timestamp":0}"#;
+        let signature = hex::encode(signer.sign(data).to_bytes());
+
+        assert_eq!(
+            verify_signature(data, signature.as_bytes(), &[trusted]),
+            Ok(())
+        );
+        let base64 = base64::engine::general_purpose::STANDARD.encode(signer.sign(data).to_bytes());
+        assert_eq!(
+            verify_signature(data, base64.as_bytes(), &[trusted]),
+            Ok(())
+        );
+
+        assert!(verify_signature(b"tampered", signature.as_bytes(), &[trusted]).is_err());
+        let untrusted = TrustedKey(other.verifying_key());
+        assert!(verify_signature(data, signature.as_bytes(), &[untrusted]).is_err());
+        assert!("not a key".parse::<TrustedKey>().is_err());
+    }
+}
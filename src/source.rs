@@ -0,0 +1,172 @@
+//! Pluggable storage backends that snapshots are read from.
+//!
+//! All remote I/O goes through the [`SnapshotSource`] trait, so metadata,
+//! verification and chunk downloads work the same way whether snapshots come
+//! from the public HTTP(S) bucket, a local directory (e.g. an NFS mirror) or an
+//! S3-compatible endpoint. The backend is selected by URL scheme, see
+//! [`source_from_url`], and several mirrors can be combined with [`MirrorSource`].
+
+mod http;
+mod local;
+mod mirror;
+mod s3;
+mod sigv4;
+
+pub use http::HttpSource;
+pub use local::LocalSource;
+pub use mirror::{MirrorSelection, MirrorSource};
+pub use s3::{S3Config, S3Source};
+pub use sigv4::S3Credentials;
+
+use crate::error::SnapshotError;
+use crate::metadata::metadata_path;
+use crate::types::SnapshotMetadata;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Size and version information about a stored object.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectInfo {
+    /// Total size of the object in bytes, if known.
+    pub size: Option<u64>,
+    /// Entity tag without surrounding quotes (MD5 for simple S3/R2 uploads).
+    pub etag: Option<String>,
+}
+
+/// An object found by [`SnapshotSource::list`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedObject {
+    /// Key of the object within the source.
+    pub key: String,
+    /// Size of the object in bytes.
+    pub size: u64,
+    /// Last modification time as a Unix timestamp, if known.
+    pub last_modified: Option<i64>,
+}
+
+/// Request to read an object starting at a byte offset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByteRange {
+    /// First byte to return.
+    pub start: u64,
+    /// Only honor the range if the object still has this ETag (HTTP `If-Range`).
+    pub if_etag: Option<String>,
+}
+
+/// Stream of object bytes.
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, SnapshotError>> + Send>>;
+
+/// An object body being read from a source.
+pub struct ObjectStream {
+    /// Information about the whole object.
+    pub info: ObjectInfo,
+    /// Offset of the first byte in `stream`: the requested range start if the
+    /// range was honored, `0` if the whole object is returned.
+    pub offset: u64,
+    /// Number of bytes in `stream`, if known.
+    pub content_length: Option<u64>,
+    /// The object bytes.
+    pub stream: ByteStream,
+}
+
+/// A storage backend holding snapshot metadata and chunks.
+///
+/// Keys are `/`-separated paths relative to the source root, such as
+/// `FARCASTER_NETWORK_MAINNET/0/latest.json` or `<key_base>/<chunk>`.
+/// Missing objects are reported as [`SnapshotError::NotFound`].
+#[async_trait]
+pub trait SnapshotSource: Send + Sync + Debug {
+    /// Returns a human-readable location for `key`, used in logs and errors.
+    fn describe(&self, key: &str) -> String;
+
+    /// Returns the size and ETag of an object.
+    async fn stat(&self, key: &str) -> Result<ObjectInfo, SnapshotError>;
+
+    /// Streams an object, optionally starting at a byte offset.
+    ///
+    /// Implementations may ignore `range` and return the whole object, which
+    /// callers detect through [`ObjectStream::offset`].
+    async fn get(&self, key: &str, range: Option<ByteRange>)
+        -> Result<ObjectStream, SnapshotError>;
+
+    /// Lists all objects whose keys start with `prefix`.
+    ///
+    /// Sources that can't enumerate objects, such as plain HTTP, return
+    /// [`SnapshotError::ListingNotSupported`] (the default).
+    async fn list(&self, prefix: &str) -> Result<Vec<ListedObject>, SnapshotError> {
+        Err(SnapshotError::ListingNotSupported(self.describe(prefix)))
+    }
+
+    /// Reads a whole (small) object into memory.
+    async fn fetch(&self, key: &str) -> Result<Bytes, SnapshotError> {
+        let mut object = self.get(key, None).await?;
+        let mut data = Vec::new();
+        while let Some(piece) = object.stream.next().await {
+            data.extend_from_slice(&piece?);
+        }
+        Ok(Bytes::from(data))
+    }
+
+    /// Called with the snapshot about to be restored, before any of its chunks is read.
+    ///
+    /// Sources that combine several locations use it to keep the snapshot's chunks
+    /// to locations publishing the same snapshot. The default does nothing.
+    async fn confirm_snapshot(&self, _network: &str, _shard_id: u32, _snapshot: &SnapshotMetadata) {
+    }
+
+    /// Fetches and parses the latest snapshot metadata for a shard.
+    async fn fetch_metadata(
+        &self,
+        network: &str,
+        shard_id: u32,
+    ) -> Result<SnapshotMetadata, SnapshotError> {
+        let key = metadata_path(network, shard_id);
+        let data = self.fetch(&key).await?;
+        serde_json::from_slice(&data).map_err(|e| {
+            SnapshotError::DownloadFailed(format!(
+                "Invalid metadata format from {}: {}\n\
+                 Expected JSON with fields: key_base, chunks, timestamp",
+                self.describe(&key),
+                e
+            ))
+        })
+    }
+}
+
+/// Creates the source for a snapshot URL, selected by scheme.
+///
+/// * `http://` and `https://` - [`HttpSource`]
+/// * `s3://bucket/prefix` - [`S3Source`], configured by `s3`
+/// * `file:///path` or a plain path - [`LocalSource`]
+///
+/// # Example
+///
+/// ```
+/// use snapsync::{source_from_url, S3Config};
+///
+/// let source = source_from_url("file:///mnt/snapshots", &S3Config::default()).unwrap();
+/// assert_eq!(
+///     source.describe("FARCASTER_NETWORK_MAINNET/0/latest.json"),
+///     "/mnt/snapshots/FARCASTER_NETWORK_MAINNET/0/latest.json"
+/// );
+/// ```
+pub fn source_from_url(url: &str, s3: &S3Config) -> Result<Arc<dyn SnapshotSource>, SnapshotError> {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        Ok(Arc::new(HttpSource::new(url)))
+    } else if let Some(location) = url.strip_prefix("s3://") {
+        Ok(Arc::new(S3Source::new(location, s3.clone())?))
+    } else if let Some(path) = url.strip_prefix("file://") {
+        Ok(Arc::new(LocalSource::new(path)))
+    } else if url.contains("://") {
+        Err(SnapshotError::DownloadFailed(format!(
+            "Unsupported snapshot URL scheme: {}",
+            url
+        )))
+    } else {
+        Ok(Arc::new(LocalSource::new(url)))
+    }
+}
@@ -0,0 +1,306 @@
+//! HTTP(S) snapshot source.
+
+use super::{ByteRange, ObjectInfo, ObjectStream, SnapshotSource};
+use crate::error::SnapshotError;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use reqwest::header::{CONTENT_LENGTH, CONTENT_RANGE, ETAG, IF_RANGE, RANGE, RETRY_AFTER};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::time::{Duration, SystemTime};
+use tracing::info;
+
+/// Reads snapshots from a plain HTTP(S) base URL, such as a public R2 bucket.
+#[derive(Debug, Clone)]
+pub struct HttpSource {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl HttpSource {
+    /// Creates a source rooted at `base_url` (e.g. `<https://pub-xxx.r2.dev>`).
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl SnapshotSource for HttpSource {
+    fn describe(&self, key: &str) -> String {
+        format!("{}/{}", self.base_url, key)
+    }
+
+    async fn stat(&self, key: &str) -> Result<ObjectInfo, SnapshotError> {
+        let url = self.describe(key);
+        head_object(self.client.head(&url), &url).await
+    }
+
+    async fn get(
+        &self,
+        key: &str,
+        range: Option<ByteRange>,
+    ) -> Result<ObjectStream, SnapshotError> {
+        let url = self.describe(key);
+        get_object(|| self.client.get(&url), &url, range).await
+    }
+}
+
+/// Extracts size and ETag from response headers.
+fn object_info(response: &Response) -> ObjectInfo {
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string())
+    };
+
+    // For 206 responses the total size is the part after the slash in Content-Range
+    let size = match response.status() {
+        StatusCode::PARTIAL_CONTENT => header(CONTENT_RANGE)
+            .and_then(|range| range.rsplit('/').next().and_then(|s| s.parse().ok())),
+        _ => header(CONTENT_LENGTH).and_then(|s| s.parse().ok()),
+    };
+
+    ObjectInfo {
+        size,
+        etag: header(ETAG).map(|s| s.trim_matches('"').to_string()),
+    }
+}
+
+/// Turns 404 into [`SnapshotError::NotFound`] and other failures into
+/// [`SnapshotError::HttpStatus`].
+pub(super) fn check_status(response: Response, url: &str) -> Result<Response, SnapshotError> {
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND {
+        return Err(SnapshotError::NotFound(url.to_string()));
+    }
+    if status.is_client_error() || status.is_server_error() {
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        return Err(SnapshotError::HttpStatus {
+            url: url.to_string(),
+            status: status.as_u16(),
+            retry_after,
+        });
+    }
+    Ok(response)
+}
+
+/// Parses a `Retry-After` value: delay seconds or an HTTP date
+/// (e.g. `Wed, 21 Oct 2015 07:28:00 GMT`).
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let mut parts = value.split_whitespace().skip(1);
+    let (day, month, year, time) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    let month = MONTHS.iter().position(|m| *m == month)? + 1;
+    let date =
+        humantime::parse_rfc3339(&format!("{}-{:02}-{:0>2}T{}Z", year, month, day, time)).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Sends a prepared HEAD request and returns the object information.
+pub(super) async fn head_object(
+    request: RequestBuilder,
+    url: &str,
+) -> Result<ObjectInfo, SnapshotError> {
+    let response = check_status(request.send().await?, url)?;
+    Ok(object_info(&response))
+}
+
+/// Sends a GET request built by `request`, adding `Range`/`If-Range` headers when resuming.
+///
+/// `request` is called again without a range if the server rejects the range
+/// as not satisfiable (e.g. the local prefix is longer than the object).
+pub(super) async fn get_object(
+    request: impl Fn() -> RequestBuilder,
+    url: &str,
+    range: Option<ByteRange>,
+) -> Result<ObjectStream, SnapshotError> {
+    let mut builder = request();
+    if let Some(ref range) = range {
+        builder = builder.header(RANGE, format!("bytes={}-", range.start));
+        if let Some(ref etag) = range.if_etag {
+            builder = builder.header(IF_RANGE, format!("\"{}\"", etag));
+        }
+    }
+    let mut response = builder.send().await?;
+
+    if range.is_some() && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        info!("Range not satisfiable for {}, requesting whole object", url);
+        response = request().send().await?;
+    }
+    let response = check_status(response, url)?;
+
+    // 206 means the server honored both Range and If-Range; anything else is the whole object
+    let offset = match range {
+        Some(range) if response.status() == StatusCode::PARTIAL_CONTENT => {
+            let start = response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|s| s.strip_prefix("bytes "))
+                .and_then(|s| s.split('-').next())
+                .and_then(|s| s.parse::<u64>().ok());
+            if start != Some(range.start) {
+                return Err(SnapshotError::InvalidResponse {
+                    url: url.to_string(),
+                    reason: format!(
+                        "unexpected Content-Range when requesting byte {}",
+                        range.start
+                    ),
+                });
+            }
+            range.start
+        }
+        _ => 0,
+    };
+
+    Ok(ObjectStream {
+        info: object_info(&response),
+        offset,
+        content_length: response.content_length(),
+        stream: Box::pin(
+            response
+                .bytes_stream()
+                .map(|piece| piece.map_err(SnapshotError::from)),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    /// Serves one object over HTTP/1.1, honoring `Range` and `If-Range` like S3 does.
+    async fn serve_object(data: &'static [u8], etag: &'static str) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut stream = BufReader::new(stream);
+                let (mut range, mut if_range) = (None, None);
+                let mut line = String::new();
+                while stream.read_line(&mut line).await.unwrap() > 2 {
+                    let lower = line.trim().to_ascii_lowercase();
+                    if let Some(start) = lower.strip_prefix("range: bytes=") {
+                        range = start.trim_end_matches('-').parse::<usize>().ok();
+                    } else if let Some(value) = lower.strip_prefix("if-range: ") {
+                        if_range = Some(value.to_string());
+                    }
+                    line.clear();
+                }
+
+                let quoted = format!("\"{}\"", etag);
+                let (status, extra, body) = match range {
+                    Some(start) if start >= data.len() => (
+                        "416 Range Not Satisfiable",
+                        format!("Content-Range: bytes */{}\r\n", data.len()),
+                        &b""[..],
+                    ),
+                    Some(start) if if_range.is_none_or(|value| value == quoted) => (
+                        "206 Partial Content",
+                        format!(
+                            "Content-Range: bytes {}-{}/{}\r\n",
+                            start,
+                            data.len() - 1,
+                            data.len()
+                        ),
+                        &data[start..],
+                    ),
+                    _ => ("200 OK", String::new(), data),
+                };
+                let head = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nETag: {}\r\n{}Connection: close\r\n\r\n",
+                    status,
+                    body.len(),
+                    quoted,
+                    extra
+                );
+                let stream = stream.get_mut();
+                stream.write_all(head.as_bytes()).await.unwrap();
+                stream.write_all(body).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn test_ranged_get_falls_back_to_whole_object() {
+        let source = HttpSource::new(&serve_object(b"0123456789", "v1").await);
+        let get = |start: u64, if_etag: &str| {
+            let range = ByteRange {
+                start,
+                if_etag: Some(if_etag.to_string()),
+            };
+            let source = &source;
+            async move {
+                let object = source.get("chunk", Some(range)).await.unwrap();
+                let data: Vec<u8> = object
+                    .stream
+                    .map(|piece| piece.unwrap().to_vec())
+                    .concat()
+                    .await;
+                (object.offset, object.info.etag, data)
+            }
+        };
+
+        // 206: the range is honored and the stream starts at the requested byte
+        let (offset, etag, data) = get(4, "v1").await;
+        assert_eq!((offset, etag.as_deref()), (4, Some("v1")));
+        assert_eq!(data, b"456789");
+
+        // 200: the object changed, so the whole new object is returned
+        let (offset, _, data) = get(4, "v0").await;
+        assert_eq!(offset, 0);
+        assert_eq!(data, b"0123456789");
+
+        // 416: the local prefix is longer than the object, request it whole
+        let (offset, _, data) = get(12, "v1").await;
+        assert_eq!(offset, 0);
+        assert_eq!(data, b"0123456789");
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        let later = humantime::format_rfc3339_seconds(SystemTime::now() + Duration::from_secs(600))
+            .to_string();
+        // "2015-10-21T07:28:00Z" -> "Wed, 21 Oct 2015 07:28:00 GMT"
+        let (date, time) = later.trim_end_matches('Z').split_once('T').unwrap();
+        let mut date = date.split('-');
+        let (year, month, day) = (
+            date.next().unwrap(),
+            date.next().unwrap(),
+            date.next().unwrap(),
+        );
+        let month = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ][month.parse::<usize>().unwrap() - 1];
+        let header = format!("Thu, {} {} {} {} GMT", day, month, year, time);
+        let delay = parse_retry_after(&header).unwrap();
+        assert!(delay > Duration::from_secs(590) && delay <= Duration::from_secs(600));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
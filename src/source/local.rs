@@ -0,0 +1,202 @@
+//! Local directory snapshot source.
+
+use super::{ByteRange, ListedObject, ObjectInfo, ObjectStream, SnapshotSource};
+use crate::error::SnapshotError;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::io::AsyncSeekExt;
+use tokio_util::io::ReaderStream;
+
+/// Reads snapshots from a local directory laid out like the bucket,
+/// e.g. an NFS mirror or a test fixture.
+///
+/// Local files have no ETag, so only sizes are verified for them. A range
+/// guarded by an ETag (`If-Range`) can't be checked either and is answered
+/// with the whole file, like a server does when the ETag doesn't match.
+#[derive(Debug, Clone)]
+pub struct LocalSource {
+    root: PathBuf,
+}
+
+impl LocalSource {
+    /// Creates a source rooted at `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+
+    fn not_found(&self, key: &str, e: std::io::Error) -> SnapshotError {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            SnapshotError::NotFound(self.describe(key))
+        } else {
+            SnapshotError::IoError(e)
+        }
+    }
+}
+
+#[async_trait]
+impl SnapshotSource for LocalSource {
+    fn describe(&self, key: &str) -> String {
+        self.path(key).display().to_string()
+    }
+
+    async fn stat(&self, key: &str) -> Result<ObjectInfo, SnapshotError> {
+        let metadata = tokio::fs::metadata(self.path(key))
+            .await
+            .map_err(|e| self.not_found(key, e))?;
+        Ok(ObjectInfo {
+            size: Some(metadata.len()),
+            etag: None,
+        })
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ListedObject>, SnapshotError> {
+        // Walk the deepest directory covering the prefix, then filter by the full prefix
+        let dir = match prefix.rfind('/') {
+            Some(end) => &prefix[..end],
+            None => "",
+        };
+        let root = self.root.clone();
+        let start = self.path(dir);
+        let prefix = prefix.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let mut objects = Vec::new();
+            let mut pending = vec![start];
+            while let Some(dir) = pending.pop() {
+                let entries = match std::fs::read_dir(&dir) {
+                    Ok(entries) => entries,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(SnapshotError::from(e)),
+                };
+                for entry in entries {
+                    let entry = entry?;
+                    let metadata = entry.metadata()?;
+                    if metadata.is_dir() {
+                        pending.push(entry.path());
+                        continue;
+                    }
+                    let key = relative_key(&root, &entry.path());
+                    if key.starts_with(&prefix) {
+                        objects.push(ListedObject {
+                            key,
+                            size: metadata.len(),
+                            last_modified: metadata
+                                .modified()
+                                .ok()
+                                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                                .map(|age| age.as_secs() as i64),
+                        });
+                    }
+                }
+            }
+            objects.sort_by(|a, b| a.key.cmp(&b.key));
+            Ok(objects)
+        })
+        .await
+        .map_err(|e| {
+            SnapshotError::IoError(std::io::Error::other(format!("Task join error: {}", e)))
+        })?
+    }
+
+    async fn get(
+        &self,
+        key: &str,
+        range: Option<ByteRange>,
+    ) -> Result<ObjectStream, SnapshotError> {
+        let mut file = tokio::fs::File::open(self.path(key))
+            .await
+            .map_err(|e| self.not_found(key, e))?;
+        let size = file.metadata().await?.len();
+
+        let offset = match range {
+            Some(range) if range.if_etag.is_none() && range.start <= size => range.start,
+            _ => 0,
+        };
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+
+        Ok(ObjectStream {
+            info: ObjectInfo {
+                size: Some(size),
+                etag: None,
+            },
+            offset,
+            content_length: Some(size - offset),
+            stream: Box::pin(
+                ReaderStream::with_capacity(file, 1024 * 1024)
+                    .map(|piece| piece.map_err(SnapshotError::from)),
+            ),
+        })
+    }
+}
+
+/// Returns the `/`-separated key of `path` below `root`.
+fn relative_key(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_source_stat_and_ranged_get() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("net/0")).unwrap();
+        std::fs::write(dir.path().join("net/0/chunk"), b"0123456789").unwrap();
+        let source = LocalSource::new(dir.path());
+
+        let info = source.stat("net/0/chunk").await.unwrap();
+        assert_eq!(info.size, Some(10));
+        assert!(matches!(
+            source.stat("net/0/missing").await,
+            Err(SnapshotError::NotFound(_))
+        ));
+
+        let range = ByteRange {
+            start: 4,
+            if_etag: None,
+        };
+        let object = source.get("net/0/chunk", Some(range)).await.unwrap();
+        assert_eq!(object.offset, 4);
+        assert_eq!(object.content_length, Some(6));
+        let data: Vec<u8> = object
+            .stream
+            .map(|piece| piece.unwrap().to_vec())
+            .concat()
+            .await;
+        assert_eq!(data, b"456789");
+
+        // The file may have changed since the ETag was recorded, so return all of it
+        let range = ByteRange {
+            start: 4,
+            if_etag: Some("d41d8cd98f00b204e9800998ecf8427e".to_string()),
+        };
+        let object = source.get("net/0/chunk", Some(range)).await.unwrap();
+        assert_eq!(object.offset, 0);
+        assert_eq!(object.content_length, Some(10));
+
+        assert_eq!(
+            &source.fetch("net/0/chunk").await.unwrap()[..],
+            b"0123456789"
+        );
+
+        let listed = source.list("net/0/ch").await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(
+            (listed[0].key.as_str(), listed[0].size),
+            ("net/0/chunk", 10)
+        );
+        assert!(source.list("net/1/").await.unwrap().is_empty());
+    }
+}
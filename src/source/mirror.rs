@@ -0,0 +1,551 @@
+//! Failover across several snapshot mirrors.
+
+use super::{ByteRange, ByteStream, ListedObject, ObjectInfo, ObjectStream, SnapshotSource};
+use crate::error::SnapshotError;
+use crate::metadata::{fetch_snapshot_list, metadata_path};
+use crate::types::SnapshotMetadata;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::Stream;
+use std::collections::{HashMap, HashSet};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Base cooldown after a mirror fails, doubled for each consecutive failure.
+const FAILURE_COOLDOWN: Duration = Duration::from_secs(15);
+
+/// Upper bound for the failure cooldown.
+const MAX_FAILURE_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// Weight of the newest sample in the throughput moving average.
+const THROUGHPUT_SMOOTHING: f64 = 0.3;
+
+/// Transfers smaller than this are not used to estimate throughput.
+const MIN_THROUGHPUT_SAMPLE: u64 = 256 * 1024;
+
+/// Order in which mirrors are tried.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MirrorSelection {
+    /// Try mirrors in the order they were configured (default).
+    #[default]
+    Priority,
+    /// Try the mirror with the highest measured throughput first.
+    ///
+    /// Mirrors without measurements yet are tried before measured ones, so
+    /// every mirror gets sampled once.
+    Fastest,
+}
+
+/// Health and speed of one mirror.
+#[derive(Debug, Default)]
+struct MirrorStats {
+    /// Moving average of download throughput in bytes per second.
+    throughput: Option<f64>,
+    consecutive_failures: u32,
+    cooldown_until: Option<Instant>,
+}
+
+impl MirrorStats {
+    fn record_success(&mut self, bytes: u64, elapsed: Duration) {
+        self.consecutive_failures = 0;
+        self.cooldown_until = None;
+        if bytes < MIN_THROUGHPUT_SAMPLE || elapsed.is_zero() {
+            return;
+        }
+        let sample = bytes as f64 / elapsed.as_secs_f64();
+        self.throughput = Some(match self.throughput {
+            Some(average) => average + THROUGHPUT_SMOOTHING * (sample - average),
+            None => sample,
+        });
+    }
+
+    fn record_failure(&mut self) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        let cooldown = FAILURE_COOLDOWN
+            .saturating_mul(1 << self.consecutive_failures.min(8).saturating_sub(1))
+            .min(MAX_FAILURE_COOLDOWN);
+        self.cooldown_until = Some(Instant::now() + cooldown);
+    }
+
+    fn cooling_down(&self, now: Instant) -> bool {
+        self.cooldown_until.is_some_and(|until| until > now)
+    }
+}
+
+#[derive(Debug)]
+struct Mirror {
+    source: Arc<dyn SnapshotSource>,
+    stats: Arc<Mutex<MirrorStats>>,
+}
+
+/// Reads snapshots from a list of mirrors, failing over between them.
+///
+/// Every request is tried against the healthy mirrors in [`MirrorSelection`]
+/// order, then against mirrors that recently failed. Metadata is fetched from
+/// all mirrors and the first mirror (in configured order) that has it is
+/// authoritative. Whichever snapshot is restored, latest, pinned or selected,
+/// its chunks are only read from mirrors whose `latest.json` or snapshot list
+/// publish it with the same chunks.
+#[derive(Debug)]
+pub struct MirrorSource {
+    mirrors: Vec<Mirror>,
+    selection: MirrorSelection,
+    /// Mirrors that must not serve keys below a `key_base`, by `key_base`.
+    excluded: Mutex<HashMap<String, HashSet<usize>>>,
+}
+
+impl MirrorSource {
+    /// Creates a source over `mirrors`, listed in priority order.
+    pub fn new(mirrors: Vec<Arc<dyn SnapshotSource>>, selection: MirrorSelection) -> Self {
+        Self {
+            mirrors: mirrors
+                .into_iter()
+                .map(|source| Mirror {
+                    source,
+                    stats: Arc::default(),
+                })
+                .collect(),
+            selection,
+            excluded: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the indices of the mirrors to try for `key`, best first.
+    fn candidates(&self, key: &str) -> Vec<usize> {
+        let excluded = self.excluded.lock().unwrap();
+        let excluded: HashSet<usize> = excluded
+            .iter()
+            .filter(|(key_base, _)| key.starts_with(&format!("{}/", key_base)))
+            .flat_map(|(_, mirrors)| mirrors.iter().copied())
+            .collect();
+
+        let now = Instant::now();
+        let mut candidates: Vec<(usize, bool, Option<f64>)> = self
+            .mirrors
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !excluded.contains(index))
+            .map(|(index, mirror)| {
+                let stats = mirror.stats.lock().unwrap();
+                (index, stats.cooling_down(now), stats.throughput)
+            })
+            .collect();
+
+        // Stable sort, so ties keep the configured priority order
+        candidates.sort_by(|a, b| {
+            a.1.cmp(&b.1).then_with(|| match self.selection {
+                MirrorSelection::Priority => std::cmp::Ordering::Equal,
+                MirrorSelection::Fastest => match (a.2, b.2) {
+                    (Some(a), Some(b)) => b.total_cmp(&a),
+                    (None, Some(_)) => std::cmp::Ordering::Less,
+                    (Some(_), None) => std::cmp::Ordering::Greater,
+                    (None, None) => std::cmp::Ordering::Equal,
+                },
+            })
+        });
+        candidates.into_iter().map(|(index, _, _)| index).collect()
+    }
+
+    /// Runs `request` against each candidate mirror until one succeeds.
+    async fn with_failover<T, F, Fut>(&self, key: &str, request: F) -> Result<T, SnapshotError>
+    where
+        F: Fn(usize) -> Fut,
+        Fut: std::future::Future<Output = Result<T, SnapshotError>>,
+    {
+        let candidates = self.candidates(key);
+        let mut last_error = None;
+
+        for index in candidates {
+            match request(index).await {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    let mirror = &self.mirrors[index];
+                    warn!(
+                        "Mirror failed for {}: {}, trying next mirror",
+                        mirror.source.describe(key),
+                        e
+                    );
+                    // A missing object says nothing about the mirror's health
+                    if !matches!(e, SnapshotError::NotFound(_)) {
+                        mirror.stats.lock().unwrap().record_failure();
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            SnapshotError::DownloadFailed(format!(
+                "No consistent mirror available for {}",
+                self.describe(key)
+            ))
+        }))
+    }
+
+    /// Checks which mirrors publish `snapshot`, see [`publishes`].
+    ///
+    /// Mirrors publishing something else are excluded. Mirrors that can't tell,
+    /// for example because they don't list their snapshots, are excluded as well
+    /// unless no mirror confirms the snapshot.
+    async fn confirm(&self, network: &str, shard_id: u32, snapshot: &SnapshotMetadata) {
+        let results = futures_util::future::join_all(
+            self.mirrors
+                .iter()
+                .map(|mirror| publishes(mirror.source.as_ref(), network, shard_id, snapshot)),
+        )
+        .await;
+        let consistent = results
+            .iter()
+            .filter(|result| matches!(result, Publication::Same))
+            .count();
+
+        for (index, result) in results.into_iter().enumerate() {
+            let location = self.mirrors[index]
+                .source
+                .describe(&metadata_path(network, shard_id));
+            match result {
+                Publication::Same => {}
+                Publication::Different(reason) => {
+                    warn!(
+                        "Mirror {} serves a different snapshot ({}), not using it for {}",
+                        location, reason, snapshot.key_base
+                    );
+                    self.exclude(&snapshot.key_base, index);
+                }
+                Publication::Unknown(reason) if consistent > 0 => {
+                    warn!(
+                        "Could not confirm snapshot {} on mirror {}: {}, not using it for shard {}",
+                        snapshot.key_base, location, reason, shard_id
+                    );
+                    self.exclude(&snapshot.key_base, index);
+                }
+                Publication::Unknown(reason) => {
+                    warn!(
+                        "Could not confirm snapshot {} on mirror {}: {}, no mirror confirms it",
+                        snapshot.key_base, location, reason
+                    );
+                }
+            }
+        }
+
+        info!(
+            "🪞 {}/{} mirror(s) serve snapshot {} for shard {}",
+            consistent,
+            self.mirrors.len(),
+            snapshot.key_base,
+            shard_id
+        );
+    }
+
+    /// Prevents `index` from serving chunks below `key_base`.
+    fn exclude(&self, key_base: &str, index: usize) {
+        self.excluded
+            .lock()
+            .unwrap()
+            .entry(key_base.to_string())
+            .or_default()
+            .insert(index);
+    }
+}
+
+#[async_trait]
+impl SnapshotSource for MirrorSource {
+    fn describe(&self, key: &str) -> String {
+        match self.mirrors.first() {
+            Some(mirror) => mirror.source.describe(key),
+            None => key.to_string(),
+        }
+    }
+
+    async fn stat(&self, key: &str) -> Result<ObjectInfo, SnapshotError> {
+        self.with_failover(key, |index| self.mirrors[index].source.stat(key))
+            .await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ListedObject>, SnapshotError> {
+        let mut last_error = None;
+        for mirror in &self.mirrors {
+            match mirror.source.list(prefix).await {
+                Ok(objects) => return Ok(objects),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| SnapshotError::ListingNotSupported(prefix.to_string())))
+    }
+
+    async fn get(
+        &self,
+        key: &str,
+        range: Option<ByteRange>,
+    ) -> Result<ObjectStream, SnapshotError> {
+        self.with_failover(key, |index| {
+            let range = range.clone();
+            async move {
+                let mirror = &self.mirrors[index];
+                let mut object = mirror.source.get(key, range).await?;
+                object.stream = Box::pin(MeteredStream {
+                    inner: object.stream,
+                    stats: Arc::clone(&mirror.stats),
+                    started: Instant::now(),
+                    bytes: 0,
+                    done: false,
+                });
+                Ok(object)
+            }
+        })
+        .await
+    }
+
+    async fn fetch_metadata(
+        &self,
+        network: &str,
+        shard_id: u32,
+    ) -> Result<SnapshotMetadata, SnapshotError> {
+        let results = futures_util::future::join_all(
+            self.mirrors
+                .iter()
+                .map(|mirror| mirror.source.fetch_metadata(network, shard_id)),
+        )
+        .await;
+
+        let mut first_error = None;
+        for result in results {
+            match result {
+                Ok(metadata) => return Ok(metadata),
+                // Every mirror failed; report the highest-priority mirror's error
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_error.unwrap_or_else(|| {
+            SnapshotError::DownloadFailed("No snapshot mirrors configured".to_string())
+        }))
+    }
+
+    async fn confirm_snapshot(&self, network: &str, shard_id: u32, snapshot: &SnapshotMetadata) {
+        self.confirm(network, shard_id, snapshot).await;
+    }
+}
+
+/// Whether a mirror publishes a snapshot.
+enum Publication {
+    /// The mirror publishes the snapshot with the same chunks.
+    Same,
+    /// The mirror publishes other chunks under the same `key_base`, or lacks it.
+    Different(String),
+    /// The mirror's metadata couldn't be read.
+    Unknown(String),
+}
+
+/// Checks whether a mirror publishes `snapshot`, in its `latest.json` or else
+/// in its snapshot list (`index.json` or a listing).
+async fn publishes(
+    source: &dyn SnapshotSource,
+    network: &str,
+    shard_id: u32,
+    snapshot: &SnapshotMetadata,
+) -> Publication {
+    let compare = |published: &SnapshotMetadata| {
+        if same_chunks(published, snapshot) {
+            Publication::Same
+        } else {
+            Publication::Different(format!("other chunks under {}", published.key_base))
+        }
+    };
+    let latest = match source.fetch_metadata(network, shard_id).await {
+        Ok(latest) if latest.key_base == snapshot.key_base => return compare(&latest),
+        Ok(latest) => latest,
+        Err(e) => return Publication::Unknown(e.to_string()),
+    };
+    match fetch_snapshot_list(source, network, shard_id, &[]).await {
+        Ok(snapshots) => match snapshots.iter().find(|s| s.key_base == snapshot.key_base) {
+            Some(published) => compare(published),
+            None => Publication::Different(format!(
+                "{} instead of {}",
+                latest.key_base, snapshot.key_base
+            )),
+        },
+        Err(e) => Publication::Unknown(format!(
+            "latest.json points to {} and the snapshot list is unavailable: {}",
+            latest.key_base, e
+        )),
+    }
+}
+
+/// Returns true if two snapshot descriptions have the same chunks, in any order.
+fn same_chunks(a: &SnapshotMetadata, b: &SnapshotMetadata) -> bool {
+    let mut a_chunks: Vec<&String> = a.chunks.iter().collect();
+    let mut b_chunks: Vec<&String> = b.chunks.iter().collect();
+    a_chunks.sort();
+    b_chunks.sort();
+    a_chunks == b_chunks
+}
+
+/// Byte stream that records the mirror's throughput and failures.
+struct MeteredStream {
+    inner: ByteStream,
+    stats: Arc<Mutex<MirrorStats>>,
+    started: Instant,
+    bytes: u64,
+    done: bool,
+}
+
+impl Stream for MeteredStream {
+    type Item = Result<Bytes, SnapshotError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.inner.as_mut().poll_next(cx);
+        if self.done {
+            return poll;
+        }
+        match poll {
+            Poll::Ready(Some(Ok(ref piece))) => self.bytes += piece.len() as u64,
+            Poll::Ready(Some(Err(_))) => {
+                self.done = true;
+                self.stats.lock().unwrap().record_failure();
+            }
+            Poll::Ready(None) => {
+                self.done = true;
+                let (bytes, elapsed) = (self.bytes, self.started.elapsed());
+                self.stats.lock().unwrap().record_success(bytes, elapsed);
+            }
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::LocalSource;
+
+    fn write_snapshot(root: &std::path::Path, key_base: &str, chunk: &[u8]) {
+        let metadata = format!(
+            r#"{{"key_base":"{}","chunks":["chunk_0000.bin"],"timestamp":1}}"#,
+            key_base
+        );
+        std::fs::create_dir_all(root.join("net/0")).unwrap();
+        std::fs::write(root.join("net/0/latest.json"), metadata).unwrap();
+        std::fs::create_dir_all(root.join(key_base)).unwrap();
+        std::fs::write(root.join(key_base).join("chunk_0000.bin"), chunk).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mirror_source_skips_inconsistent_mirrors() {
+        let primary = tempfile::tempdir().unwrap();
+        let stale = tempfile::tempdir().unwrap();
+        let backup = tempfile::tempdir().unwrap();
+
+        // The primary lacks the chunk, the stale mirror has an older snapshot under the same path
+        write_snapshot(primary.path(), "snap/new", b"new");
+        std::fs::remove_file(primary.path().join("snap/new/chunk_0000.bin")).unwrap();
+        write_snapshot(stale.path(), "snap/old", b"old");
+        std::fs::create_dir_all(stale.path().join("snap/new")).unwrap();
+        std::fs::write(stale.path().join("snap/new/chunk_0000.bin"), b"bad").unwrap();
+        write_snapshot(backup.path(), "snap/new", b"new");
+
+        let source = MirrorSource::new(
+            vec![
+                Arc::new(LocalSource::new(primary.path())),
+                Arc::new(LocalSource::new(stale.path())),
+                Arc::new(LocalSource::new(backup.path())),
+            ],
+            MirrorSelection::Priority,
+        );
+
+        let metadata = source.fetch_metadata("net", 0).await.unwrap();
+        assert_eq!(metadata.key_base, "snap/new");
+        source.confirm_snapshot("net", 0, &metadata).await;
+        assert_eq!(source.candidates("snap/new/chunk_0000.bin"), vec![0, 2]);
+
+        let chunk = source.fetch("snap/new/chunk_0000.bin").await.unwrap();
+        assert_eq!(&chunk[..], b"new");
+    }
+
+    #[tokio::test]
+    async fn test_pinned_snapshot_is_confirmed_on_every_mirror() {
+        let complete = tempfile::tempdir().unwrap();
+        let changed = tempfile::tempdir().unwrap();
+        let pruned = tempfile::tempdir().unwrap();
+        let offline = tempfile::tempdir().unwrap();
+
+        // Every mirror moved on; only the first still has the pinned snapshot as it was
+        for mirror in [&complete, &changed, &pruned] {
+            write_snapshot(mirror.path(), "net/0/new", b"new");
+        }
+        std::fs::create_dir_all(complete.path().join("net/0/old")).unwrap();
+        std::fs::write(complete.path().join("net/0/old/chunk_0000.bin"), b"old").unwrap();
+        std::fs::create_dir_all(changed.path().join("net/0/old")).unwrap();
+        for chunk in ["chunk_0000.bin", "chunk_0001.bin"] {
+            std::fs::write(changed.path().join("net/0/old").join(chunk), b"bad").unwrap();
+        }
+
+        let source = MirrorSource::new(
+            vec![
+                Arc::new(LocalSource::new(complete.path())),
+                Arc::new(LocalSource::new(changed.path())),
+                Arc::new(LocalSource::new(pruned.path())),
+                Arc::new(LocalSource::new(offline.path())),
+            ],
+            MirrorSelection::Priority,
+        );
+        let pinned = SnapshotMetadata {
+            key_base: "net/0/old".to_string(),
+            chunks: vec!["chunk_0000.bin".to_string()],
+            timestamp: 1,
+            checksums: HashMap::new(),
+            pinned: true,
+        };
+        source.confirm_snapshot("net", 0, &pinned).await;
+        assert_eq!(source.candidates("net/0/old/chunk_0000.bin"), vec![0]);
+        assert_eq!(
+            source.candidates("net/0/new/chunk_0000.bin"),
+            vec![0, 1, 2, 3]
+        );
+
+        // Without any mirror confirming it, mirrors that can't tell stay usable
+        let source = MirrorSource::new(
+            vec![
+                Arc::new(LocalSource::new(changed.path())),
+                Arc::new(LocalSource::new(offline.path())),
+            ],
+            MirrorSelection::Priority,
+        );
+        source.confirm_snapshot("net", 0, &pinned).await;
+        assert_eq!(source.candidates("net/0/old/chunk_0000.bin"), vec![1]);
+    }
+
+    #[test]
+    fn test_fastest_selection_and_cooldown() {
+        let source = MirrorSource::new(
+            vec![
+                Arc::new(LocalSource::new("/a")),
+                Arc::new(LocalSource::new("/b")),
+                Arc::new(LocalSource::new("/c")),
+            ],
+            MirrorSelection::Fastest,
+        );
+        let second = Duration::from_secs(1);
+        source.mirrors[0]
+            .stats
+            .lock()
+            .unwrap()
+            .record_success(1 << 20, second);
+        source.mirrors[1]
+            .stats
+            .lock()
+            .unwrap()
+            .record_success(8 << 20, second);
+
+        // Unmeasured mirrors are sampled first, then the fastest
+        assert_eq!(source.candidates("key"), vec![2, 1, 0]);
+
+        source.mirrors[2].stats.lock().unwrap().record_failure();
+        assert_eq!(source.candidates("key"), vec![1, 0, 2]);
+    }
+}
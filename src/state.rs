@@ -0,0 +1,301 @@
+//! Persisted record of verified chunks for fast, offline resume.
+//!
+//! Each shard has a `shard_<id>_state.jsonl` file in the snapshot directory.
+//! Whenever a chunk is downloaded or verified, a line with its key, size,
+//! ETag, MD5, published digest and modification time is appended. On the next
+//! run a chunk whose local size and mtime still match its record is trusted
+//! without a HEAD request or re-hashing, provided the record shows it was
+//! checked against a hash: a record with only a size proves nothing.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use tracing::warn;
+
+/// What was known about a chunk when it was last verified.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct ChunkRecord {
+    /// Chunk file name.
+    chunk: String,
+    /// Object key, which changes with the snapshot.
+    key: String,
+    /// Size of the local file in bytes.
+    size: u64,
+    /// ETag of the remote object.
+    etag: Option<String>,
+    /// MD5 of the local file, if it was computed.
+    md5: Option<String>,
+    /// Published digest the local file matched, labelled with its algorithm.
+    #[serde(default)]
+    digest: Option<String>,
+    /// Modification time of the local file in nanoseconds since the Unix epoch.
+    mtime_nanos: u64,
+}
+
+/// State of one shard: the latest record per chunk and the log to append to.
+#[derive(Debug)]
+struct ShardState {
+    records: HashMap<String, ChunkRecord>,
+    log: Option<File>,
+}
+
+/// Per-shard logs of verified chunks.
+#[derive(Debug)]
+pub(crate) struct ChunkStateStore {
+    snapshot_dir: String,
+    /// Ignore existing records, every chunk is fully re-verified.
+    reverify: bool,
+    shards: Mutex<HashMap<u32, ShardState>>,
+}
+
+impl ChunkStateStore {
+    /// Creates a store for the shards in `snapshot_dir`; logs are loaded on first use.
+    pub(crate) fn new(snapshot_dir: &str, reverify: bool) -> Self {
+        Self {
+            snapshot_dir: snapshot_dir.to_string(),
+            reverify,
+            shards: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn path(&self, shard_id: u32) -> String {
+        state_path(&self.snapshot_dir, shard_id)
+    }
+
+    /// Returns the size of a chunk if it is unchanged since it was last verified.
+    ///
+    /// Only records carrying an ETag, MD5 or published digest are trusted.
+    ///
+    /// # Arguments
+    ///
+    /// * `shard_id` - Shard the chunk belongs to
+    /// * `key` - Object key of the chunk
+    /// * `filename` - Local path of the chunk
+    pub(crate) fn verified_size(&self, shard_id: u32, key: &str, filename: &str) -> Option<u64> {
+        if self.reverify {
+            return None;
+        }
+        let mut shards = self.shards.lock().unwrap();
+        let state = self.shard(&mut shards, shard_id);
+        unchanged_size(state.records.get(chunk_name(filename))?, key, filename)
+    }
+
+    /// Records that a chunk was verified against the remote object.
+    ///
+    /// Failures are logged and otherwise ignored: a missing record only means
+    /// the chunk is verified again on the next run.
+    ///
+    /// # Arguments
+    ///
+    /// * `shard_id` - Shard the chunk belongs to
+    /// * `key` - Object key of the chunk
+    /// * `filename` - Local path of the verified chunk
+    /// * `etag` - ETag of the remote object
+    /// * `md5` - MD5 of the local file, if computed
+    /// * `digest` - Published digest the local file matched, if any
+    pub(crate) fn record(
+        &self,
+        shard_id: u32,
+        key: &str,
+        filename: &str,
+        etag: Option<String>,
+        md5: Option<String>,
+        digest: Option<String>,
+    ) {
+        let (size, mtime_nanos) = match file_identity(filename) {
+            Ok(identity) => identity,
+            Err(e) => {
+                warn!("Failed to stat {} for the download state: {}", filename, e);
+                return;
+            }
+        };
+        let record = ChunkRecord {
+            chunk: chunk_name(filename).to_string(),
+            key: key.to_string(),
+            size,
+            etag,
+            md5,
+            digest,
+            mtime_nanos,
+        };
+
+        let mut shards = self.shards.lock().unwrap();
+        let state = self.shard(&mut shards, shard_id);
+        if let Some(ref mut log) = state.log {
+            if let Err(e) = append(log, &record) {
+                warn!("Failed to update {}: {}", self.path(shard_id), e);
+            }
+        }
+        state.records.insert(record.chunk.clone(), record);
+    }
+
+    /// Returns the state of a shard, loading and compacting its log on first use.
+    fn shard<'a>(
+        &self,
+        shards: &'a mut HashMap<u32, ShardState>,
+        shard_id: u32,
+    ) -> &'a mut ShardState {
+        shards.entry(shard_id).or_insert_with(|| {
+            let path = self.path(shard_id);
+            let records = if self.reverify {
+                HashMap::new()
+            } else {
+                load(&path)
+            };
+            let log = compact(&path, &records)
+                .map_err(|e| warn!("Failed to write {}: {}", path, e))
+                .ok();
+            ShardState { records, log }
+        })
+    }
+}
+
+/// Counts the chunks that are unchanged since they were last verified.
+///
+/// Reads the log of the shard without compacting it, for reporting.
+///
+/// # Arguments
+///
+/// * `snapshot_dir` - Directory holding the state logs
+/// * `shard_id` - Shard the chunks belong to
+/// * `chunks` - Object key and local path of each chunk
+pub(crate) fn count_verified(
+    snapshot_dir: &str,
+    shard_id: u32,
+    chunks: &[(String, String)],
+) -> usize {
+    let records = load(&state_path(snapshot_dir, shard_id));
+    chunks
+        .iter()
+        .filter(|(key, filename)| {
+            records
+                .get(chunk_name(filename))
+                .and_then(|record| unchanged_size(record, key, filename))
+                .is_some()
+        })
+        .count()
+}
+
+/// Returns the size of a chunk if it still matches a record of a hash check.
+fn unchanged_size(record: &ChunkRecord, key: &str, filename: &str) -> Option<u64> {
+    if record.etag.is_none() && record.md5.is_none() && record.digest.is_none() {
+        return None;
+    }
+    let (size, mtime_nanos) = file_identity(filename).ok()?;
+    (record.key == key && record.size == size && record.mtime_nanos == mtime_nanos).then_some(size)
+}
+
+/// Returns the path of a shard's state log in the snapshot directory.
+pub(crate) fn state_path(snapshot_dir: &str, shard_id: u32) -> String {
+    format!("{}/shard_{}_state.jsonl", snapshot_dir, shard_id)
+}
+
+/// Reads a log, keeping the latest record per chunk and skipping torn lines.
+fn load(path: &str) -> HashMap<String, ChunkRecord> {
+    let Ok(file) = File::open(path) else {
+        return HashMap::new();
+    };
+    io::BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str::<ChunkRecord>(&line).ok())
+        .map(|record| (record.chunk.clone(), record))
+        .collect()
+}
+
+/// Rewrites a log with one line per chunk and opens it for appending.
+fn compact(path: &str, records: &HashMap<String, ChunkRecord>) -> io::Result<File> {
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = File::create(&tmp_path)?;
+    for record in records.values() {
+        append(&mut file, record)?;
+    }
+    std::fs::rename(&tmp_path, path)?;
+    OpenOptions::new().append(true).open(path)
+}
+
+fn append(log: &mut File, record: &ChunkRecord) -> io::Result<()> {
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    log.write_all(line.as_bytes())
+}
+
+/// Returns the size and modification time of a file.
+fn file_identity(filename: &str) -> io::Result<(u64, u64)> {
+    let metadata = std::fs::metadata(filename)?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_err(io::Error::other)?;
+    Ok((metadata.len(), mtime.as_nanos() as u64))
+}
+
+fn chunk_name(filename: &str) -> &str {
+    std::path::Path::new(filename)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(filename)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_survive_restart_until_file_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot_dir = dir.path().to_str().unwrap();
+        let chunk = dir.path().join("chunk_0000.bin");
+        let chunk = chunk.to_str().unwrap();
+        std::fs::write(chunk, b"chunk data").unwrap();
+
+        let store = ChunkStateStore::new(snapshot_dir, false);
+        assert_eq!(store.verified_size(0, "snap/chunk_0000.bin", chunk), None);
+        store.record(
+            0,
+            "snap/chunk_0000.bin",
+            chunk,
+            Some("etag".into()),
+            None,
+            None,
+        );
+
+        // A new run trusts the chunk, but not for another snapshot or with --reverify
+        let store = ChunkStateStore::new(snapshot_dir, false);
+        assert_eq!(
+            store.verified_size(0, "snap/chunk_0000.bin", chunk),
+            Some(10)
+        );
+        assert_eq!(store.verified_size(0, "other/chunk_0000.bin", chunk), None);
+        assert_eq!(
+            ChunkStateStore::new(snapshot_dir, true).verified_size(0, "snap/chunk_0000.bin", chunk),
+            None
+        );
+
+        std::fs::write(chunk, b"changed chunk").unwrap();
+        assert_eq!(store.verified_size(0, "snap/chunk_0000.bin", chunk), None);
+    }
+
+    #[test]
+    fn test_records_without_hash_are_not_trusted() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot_dir = dir.path().to_str().unwrap();
+        let chunk = dir.path().join("chunk_0000.bin");
+        let chunk = chunk.to_str().unwrap();
+        std::fs::write(chunk, b"chunk data").unwrap();
+
+        let store = ChunkStateStore::new(snapshot_dir, false);
+        store.record(0, "snap/chunk_0000.bin", chunk, None, None, None);
+        assert_eq!(store.verified_size(0, "snap/chunk_0000.bin", chunk), None);
+        let key = "snap/chunk_0000.bin".to_string();
+        let chunks = [(key, chunk.to_string())];
+        assert_eq!(count_verified(snapshot_dir, 0, &chunks), 0);
+
+        let digest = Some("sha256:00".to_string());
+        store.record(0, "snap/chunk_0000.bin", chunk, None, None, digest);
+        assert_eq!(count_verified(snapshot_dir, 0, &chunks), 1);
+    }
+}
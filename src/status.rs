@@ -0,0 +1,216 @@
+//! Inspection of the local restore state.
+
+use crate::error::SnapshotError;
+use crate::extract::is_extracted;
+use crate::merge::{merged_chunks, tar_path};
+use crate::metadata::load_local_metadata;
+use crate::state::count_verified;
+use crate::stream::stream_state;
+use crate::types::DownloadConfig;
+use serde::Serialize;
+use std::path::Path;
+
+/// The next stage a shard's restore needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreStage {
+    /// Fetch the metadata or download missing chunks
+    Download,
+    /// Merge the downloaded chunks into the tar
+    Merge,
+    /// Extract the tar into the RocksDB directory
+    Extract,
+    /// Resume a streaming restore
+    Stream,
+    /// Nothing left to do
+    Done,
+}
+
+impl std::fmt::Display for RestoreStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            RestoreStage::Download => "download",
+            RestoreStage::Merge => "merge",
+            RestoreStage::Extract => "extract",
+            RestoreStage::Stream => "stream",
+            RestoreStage::Done => "done",
+        })
+    }
+}
+
+/// Local restore state of one shard.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ShardStatus {
+    /// Shard ID.
+    pub shard_id: u32,
+    /// Snapshot recorded in the local `metadata.json`, if any.
+    pub key_base: Option<String>,
+    /// Whether the snapshot is pinned in `metadata.json`.
+    pub pinned: bool,
+    /// Number of chunks in the snapshot.
+    pub chunks: usize,
+    /// Chunks fully downloaded to `shard-N/`.
+    pub chunks_present: usize,
+    /// Present chunks unchanged since they were last verified.
+    pub chunks_verified: usize,
+    /// Chunks merged into the tar, according to the merge journal.
+    pub merged_chunks: usize,
+    /// Whether the tar holds every chunk.
+    pub tar_complete: bool,
+    /// Whether the RocksDB directory holds the extracted snapshot.
+    pub extracted: bool,
+    /// The stage to run next.
+    pub next_stage: RestoreStage,
+}
+
+/// Reports how far the restore of each shard has come, from local files only.
+///
+/// Inspects the local `metadata.json`, the chunk files in `shard-N/` and their
+/// download state, the merged tar and its journal, the stream journal and the
+/// RocksDB directory. Nothing is downloaded or modified.
+///
+/// A snapshot counts as extracted when a streaming restore completed, when
+/// every file of the complete tar exists in `db_dir` with its size, or, if
+/// there is no complete tar, when `db_dir/shard-N/CURRENT` exists.
+///
+/// # Arguments
+///
+/// * `config` - Download configuration (snapshot download directory)
+/// * `db_dir` - Target directory for RocksDB data
+/// * `shard_ids` - Shards to report; empty for every shard in `metadata.json`
+///
+/// # Returns
+///
+/// One entry per shard, or an error if a tar can't be read.
+pub fn restore_status(
+    config: &DownloadConfig,
+    db_dir: &str,
+    shard_ids: &[u32],
+) -> Result<Vec<ShardStatus>, SnapshotError> {
+    let snapshot_dir = &config.snapshot_download_dir;
+    let all_metadata = load_local_metadata(&format!("{}/metadata.json", snapshot_dir));
+    let shard_ids = if shard_ids.is_empty() {
+        let mut shard_ids: Vec<u32> = all_metadata.keys().filter_map(|k| k.parse().ok()).collect();
+        shard_ids.sort_unstable();
+        shard_ids
+    } else {
+        shard_ids.to_vec()
+    };
+
+    let mut statuses = Vec::with_capacity(shard_ids.len());
+    for shard_id in shard_ids {
+        let Some(metadata) = all_metadata.get(&shard_id.to_string()) else {
+            statuses.push(ShardStatus {
+                shard_id,
+                key_base: None,
+                pinned: false,
+                chunks: 0,
+                chunks_present: 0,
+                chunks_verified: 0,
+                merged_chunks: 0,
+                tar_complete: false,
+                extracted: false,
+                next_stage: RestoreStage::Download,
+            });
+            continue;
+        };
+
+        let chunks: Vec<(String, String)> = metadata
+            .chunks
+            .iter()
+            .map(|chunk| {
+                (
+                    format!("{}/{}", metadata.key_base, chunk),
+                    format!("{}/shard-{}/{}", snapshot_dir, shard_id, chunk),
+                )
+            })
+            .collect();
+        let chunks_present = chunks
+            .iter()
+            .filter(|(_, filename)| Path::new(filename).is_file())
+            .count();
+        let chunks_verified = count_verified(snapshot_dir, shard_id, &chunks);
+
+        let tar_filename = tar_path(snapshot_dir, shard_id);
+        let local_chunks: Vec<String> = chunks.into_iter().map(|(_, filename)| filename).collect();
+        let merged_chunks = merged_chunks(&tar_filename, &metadata.key_base, &local_chunks);
+        let tar_complete = merged_chunks > 0 && merged_chunks == local_chunks.len();
+
+        let streamed = stream_state(snapshot_dir, shard_id, &metadata.key_base);
+        let extracted = if streamed == Some(true) {
+            true
+        } else if tar_complete {
+            is_extracted(&tar_filename, db_dir)?
+        } else {
+            Path::new(&format!("{}/shard-{}/CURRENT", db_dir, shard_id)).is_file()
+        };
+
+        let next_stage = if extracted {
+            RestoreStage::Done
+        } else if streamed.is_some() {
+            RestoreStage::Stream
+        } else if tar_complete {
+            RestoreStage::Extract
+        } else if chunks_present == local_chunks.len() {
+            RestoreStage::Merge
+        } else {
+            RestoreStage::Download
+        };
+
+        statuses.push(ShardStatus {
+            shard_id,
+            key_base: Some(metadata.key_base.clone()),
+            pinned: metadata.pinned,
+            chunks: local_chunks.len(),
+            chunks_present,
+            chunks_verified,
+            merged_chunks,
+            tar_complete,
+            extracted,
+            next_stage,
+        });
+    }
+    Ok(statuses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_stage_follows_local_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot_dir = dir.path().join("snap");
+        let db_dir = dir.path().join("db");
+        std::fs::create_dir_all(snapshot_dir.join("shard-0")).unwrap();
+        std::fs::write(
+            snapshot_dir.join("metadata.json"),
+            br#"{"0": {"key_base": "net/0/snap", "chunks": ["chunk_0000.bin", "chunk_0001.bin"], "timestamp": 1}}"#,
+        )
+        .unwrap();
+        let config = DownloadConfig {
+            snapshot_download_dir: snapshot_dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let status = |shard_ids: &[u32]| {
+            restore_status(&config, db_dir.to_str().unwrap(), shard_ids).unwrap()
+        };
+
+        std::fs::write(snapshot_dir.join("shard-0/chunk_0000.bin"), b"chunk").unwrap();
+        let statuses = status(&[]);
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].chunks_present, 1);
+        assert_eq!(statuses[0].next_stage, RestoreStage::Download);
+
+        std::fs::write(snapshot_dir.join("shard-0/chunk_0001.bin"), b"chunk").unwrap();
+        assert_eq!(status(&[0])[0].next_stage, RestoreStage::Merge);
+
+        std::fs::create_dir_all(db_dir.join("shard-0")).unwrap();
+        std::fs::write(db_dir.join("shard-0/CURRENT"), b"MANIFEST-000001\n").unwrap();
+        assert_eq!(status(&[0])[0].next_stage, RestoreStage::Done);
+
+        let unknown = &status(&[3])[0];
+        assert_eq!(unknown.key_base, None);
+        assert_eq!(unknown.next_stage, RestoreStage::Download);
+    }
+}
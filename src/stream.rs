@@ -0,0 +1,623 @@
+//! Single-pass streaming restore: download → gunzip → untar.
+//!
+//! Instead of writing every chunk, a merged tar and then the extracted files,
+//! chunks are decompressed and unpacked as soon as they arrive. Only a bounded
+//! window of chunk files exists on disk at any time, and each one is deleted
+//! once it has been consumed.
+//!
+//! Resumability comes from a small journal that records the tar offset at
+//! which each chunk starts and the tar offset of the first entry that has not
+//! been fully written yet. A rerun restarts from the chunk containing that
+//! entry and skips straight to its first header. The journal is written every
+//! few hundred entries, whenever a new chunk is reached and when the restore
+//! stops, so a rerun repeats at most the entries written since then.
+
+use crate::download::DownloadContext;
+use crate::error::SnapshotError;
+use crate::orchestrator::download_chunk_with_retry;
+use crate::progress::{ProgressEvent, ProgressObserver};
+use crate::types::{DownloadConfig, SnapshotMetadata};
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::info;
+
+/// Entries written between two journal checkpoints.
+const CHECKPOINT_ENTRIES: u64 = 256;
+/// Bytes written between two journal checkpoints.
+const CHECKPOINT_BYTES: u64 = 256 * 1024 * 1024;
+/// Size of the blocks a tar archive is made of.
+const TAR_BLOCK_SIZE: u64 = 512;
+
+/// Returns the path of a shard's stream journal in the snapshot directory.
+pub(crate) fn journal_path(snapshot_dir: &str, shard_id: u32) -> String {
+    format!("{}/shard_{}_stream.json", snapshot_dir, shard_id)
+}
+
+/// Returns `Some(complete)` if a streaming restore of the snapshot was started.
+pub(crate) fn stream_state(snapshot_dir: &str, shard_id: u32, key_base: &str) -> Option<bool> {
+    StreamJournal::read(&journal_path(snapshot_dir, shard_id), key_base)
+        .map(|journal| journal.complete)
+}
+
+/// Persisted progress of a streaming restore for one shard.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct StreamJournal {
+    /// Snapshot the journal belongs to.
+    key_base: String,
+    /// Decompressed tar offset at which each chunk starts, indexed by chunk position.
+    chunk_offsets: Vec<u64>,
+    /// Tar offset of the first header (including GNU long name and PAX
+    /// extension headers) of the first entry not yet fully written.
+    resume_offset: u64,
+    /// Number of tar entries fully written.
+    entries_written: u64,
+    /// Whether the whole archive has been unpacked.
+    complete: bool,
+}
+
+impl StreamJournal {
+    /// Reads the journal of a snapshot, if there is one.
+    fn read(path: &str, key_base: &str) -> Option<Self> {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<StreamJournal>(&content).ok())
+            .filter(|journal| journal.key_base == key_base)
+    }
+
+    fn load(path: &str, key_base: &str) -> Self {
+        Self::read(path, key_base).unwrap_or_else(|| StreamJournal {
+            key_base: key_base.to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn persist(&self, path: &str) -> Result<(), SnapshotError> {
+        let tmp_path = format!("{}.tmp", path);
+        std::fs::write(&tmp_path, serde_json::to_string(self)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Returns the chunk to restart from and how many decompressed bytes of it to skip.
+    fn resume_point(&self) -> (usize, u64) {
+        match self
+            .chunk_offsets
+            .iter()
+            .rposition(|&offset| offset <= self.resume_offset)
+        {
+            Some(index) => (index, self.resume_offset - self.chunk_offsets[index]),
+            None => (0, 0),
+        }
+    }
+}
+
+/// Decides when the stream journal is written.
+///
+/// Writing it after every entry would add a write and a rename per file of
+/// the database, so it is only written every [`CHECKPOINT_ENTRIES`] entries or
+/// [`CHECKPOINT_BYTES`] bytes, and after a new chunk was opened, which keeps the
+/// resume point close to the chunks still on disk.
+struct Checkpoint {
+    entries: u64,
+    bytes: u64,
+    chunks: usize,
+}
+
+impl Checkpoint {
+    fn new(journal: &StreamJournal) -> Self {
+        Self {
+            entries: 0,
+            bytes: 0,
+            chunks: journal.chunk_offsets.len(),
+        }
+    }
+
+    /// Accounts for a written entry and persists the journal when a checkpoint is due.
+    fn entry_written(
+        &mut self,
+        journal: &StreamJournal,
+        path: &str,
+        size: u64,
+    ) -> Result<(), SnapshotError> {
+        self.entries += 1;
+        self.bytes += size;
+        if self.entries >= CHECKPOINT_ENTRIES
+            || self.bytes >= CHECKPOINT_BYTES
+            || journal.chunk_offsets.len() != self.chunks
+        {
+            journal.persist(path)?;
+            *self = Self::new(journal);
+        }
+        Ok(())
+    }
+}
+
+/// A chunk download scheduled ahead of the decompressor.
+struct PendingChunk {
+    index: usize,
+    task: JoinHandle<Result<String, SnapshotError>>,
+    window_permit: OwnedSemaphorePermit,
+}
+
+/// The chunk currently being decompressed.
+struct ActiveChunk {
+    decoder: GzDecoder<io::BufReader<std::fs::File>>,
+    filename: String,
+    _window_permit: OwnedSemaphorePermit,
+}
+
+/// Presents the ordered chunk files as one continuous decompressed tar stream.
+struct ChunkChainReader {
+    receiver: mpsc::Receiver<PendingChunk>,
+    runtime: tokio::runtime::Handle,
+    current: Option<ActiveChunk>,
+    /// Decompressed bytes to discard before handing data to the tar reader.
+    skip: u64,
+    /// Absolute decompressed offset of the next byte read from the chain.
+    position: u64,
+    journal: Arc<Mutex<StreamJournal>>,
+    failure: Arc<Mutex<Option<SnapshotError>>>,
+    progress: Arc<dyn ProgressObserver>,
+    shard_id: u32,
+}
+
+impl ChunkChainReader {
+    /// Waits for the next chunk to be downloaded and opens it for decompression.
+    fn open_next(&mut self) -> io::Result<bool> {
+        let Some(pending) = self.receiver.blocking_recv() else {
+            return Ok(false);
+        };
+        let filename = match self.runtime.block_on(pending.task) {
+            Ok(Ok(filename)) => filename,
+            Ok(Err(e)) => {
+                let message = e.to_string();
+                *self.failure.lock().unwrap() = Some(e);
+                return Err(io::Error::other(message));
+            }
+            Err(e) => return Err(io::Error::other(format!("Task join error: {}", e))),
+        };
+
+        {
+            let mut journal = self.journal.lock().unwrap();
+            journal.chunk_offsets.truncate(pending.index);
+            journal.chunk_offsets.push(self.position);
+        }
+
+        let file = std::fs::File::open(&filename)?;
+        self.current = Some(ActiveChunk {
+            decoder: GzDecoder::new(io::BufReader::with_capacity(4 * 1024 * 1024, file)),
+            filename,
+            _window_permit: pending.window_permit,
+        });
+        Ok(true)
+    }
+}
+
+impl Read for ChunkChainReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.current.is_none() && !self.open_next()? {
+                return Ok(0);
+            }
+            let active = self.current.as_mut().unwrap();
+
+            // Discard data up to the resume point
+            if self.skip > 0 {
+                let mut scratch = vec![0u8; self.skip.min(1024 * 1024) as usize];
+                let n = active.decoder.read(&mut scratch)?;
+                if n > 0 {
+                    self.skip -= n as u64;
+                    self.position += n as u64;
+                    continue;
+                }
+            } else {
+                let n = active.decoder.read(buf)?;
+                if n > 0 {
+                    self.position += n as u64;
+                    return Ok(n);
+                }
+            }
+
+            // Chunk fully consumed, free its disk space and window slot
+            let finished = self.current.take().unwrap();
+            let _ = std::fs::remove_file(&finished.filename);
+            let chunk = std::path::Path::new(&finished.filename)
+                .file_name()
+                .map_or_else(String::new, |n| n.to_string_lossy().to_string());
+            self.progress.on_event(&ProgressEvent::StreamChunkUnpacked {
+                shard_id: self.shard_id,
+                chunk,
+            });
+        }
+    }
+}
+
+/// Context for streaming a shard into the database directory
+pub(crate) struct StreamRestoreContext<'a> {
+    pub config: &'a DownloadConfig,
+    pub metadata: &'a SnapshotMetadata,
+    pub snapshot_dir: &'a str,
+    pub db_dir: &'a str,
+    pub downloads: &'a DownloadContext,
+    pub shard_id: u32,
+}
+
+/// Downloads, decompresses and unpacks a shard in a single pass.
+///
+/// Chunks are downloaded ahead of the decompressor with at most one more
+/// chunk file than the download concurrency limit on disk at once. Each chunk is
+/// deleted as soon as it has been decompressed, so the required free space is
+/// roughly the size of the restored database plus that window.
+///
+/// # Returns
+///
+/// `Ok(())` on success, or an error if any chunk fails to download or unpack.
+pub(crate) async fn stream_restore(ctx: StreamRestoreContext<'_>) -> Result<(), SnapshotError> {
+    let shard_id = ctx.shard_id;
+    let journal_path = journal_path(ctx.snapshot_dir, shard_id);
+    let journal = StreamJournal::load(&journal_path, &ctx.metadata.key_base);
+
+    if journal.complete {
+        info!(
+            "✅ Shard {} already restored by streaming ({} entries)",
+            shard_id, journal.entries_written
+        );
+        return Ok(());
+    }
+
+    let (start_chunk, skip) = journal.resume_point();
+    let base_offset = journal.resume_offset;
+    if base_offset > 0 {
+        info!(
+            "⏩ Resuming streaming restore of shard {} at chunk {} ({} entries already written)",
+            shard_id,
+            start_chunk + 1,
+            journal.entries_written
+        );
+    }
+    let chunk_offsets_base = journal.chunk_offsets.get(start_chunk).copied().unwrap_or(0);
+    let journal = Arc::new(Mutex::new(journal));
+
+    std::fs::create_dir_all(format!("{}/shard-{}", ctx.snapshot_dir, shard_id))?;
+    std::fs::create_dir_all(ctx.db_dir)?;
+
+    let progress = Arc::clone(&ctx.downloads.progress);
+    progress.on_event(&ProgressEvent::StreamStarted {
+        shard_id,
+        total_chunks: ctx.metadata.chunks.len(),
+        resumed_chunks: start_chunk,
+    });
+
+    // Schedule chunk downloads in order, bounded by the window
+    let window_size = ctx.downloads.concurrency.max_level() + 1;
+    let window = Arc::new(Semaphore::new(window_size));
+    let (sender, receiver) = mpsc::channel(window_size);
+    let producer = {
+        let chunks = ctx.metadata.chunks[start_chunk..].to_vec();
+        let skip_verify = ctx.config.skip_verify;
+        let downloads = ctx.downloads.clone();
+        let base_path = ctx.metadata.key_base.clone();
+        let checksums = ctx.metadata.checksums.clone();
+        let snapshot_dir = ctx.snapshot_dir.to_string();
+
+        tokio::spawn(async move {
+            for (offset, chunk) in chunks.into_iter().enumerate() {
+                let window_permit = tokio::select! {
+                    _ = downloads.cancel.cancelled() => break,
+                    permit = Arc::clone(&window).acquire_owned() => match permit {
+                        Ok(permit) => permit,
+                        Err(_) => break,
+                    },
+                };
+                let key = format!("{}/{}", base_path, chunk);
+                let filename = format!("{}/shard-{}/{}", snapshot_dir, shard_id, chunk);
+                let checksum = checksums.get(&chunk).cloned();
+                let downloads = downloads.clone();
+
+                let task = tokio::spawn(async move {
+                    let _permit = downloads.concurrency.acquire().await;
+                    if !downloads
+                        .check_local_chunk(
+                            shard_id,
+                            &key,
+                            &filename,
+                            checksum.as_ref(),
+                            skip_verify,
+                        )
+                        .await?
+                    {
+                        download_chunk_with_retry(
+                            &downloads,
+                            shard_id,
+                            &key,
+                            &filename,
+                            checksum.as_ref(),
+                        )
+                        .await?;
+                    }
+                    Ok(filename)
+                });
+
+                let pending = PendingChunk {
+                    index: start_chunk + offset,
+                    task,
+                    window_permit,
+                };
+                if sender.send(pending).await.is_err() {
+                    // The decompressor stopped, no point in downloading more
+                    break;
+                }
+            }
+        })
+    };
+
+    // Decompress and unpack on a blocking thread
+    let failure = Arc::new(Mutex::new(None));
+    let unpack_result = tokio::task::spawn_blocking({
+        let reader = ChunkChainReader {
+            receiver,
+            runtime: tokio::runtime::Handle::current(),
+            current: None,
+            skip,
+            position: chunk_offsets_base,
+            journal: Arc::clone(&journal),
+            failure: Arc::clone(&failure),
+            progress: Arc::clone(&progress),
+            shard_id,
+        };
+        let journal = Arc::clone(&journal);
+        let journal_path = journal_path.clone();
+        let db_dir = ctx.db_dir.to_string();
+        let progress = Arc::clone(&progress);
+        let cancel = ctx.downloads.cancel.clone();
+
+        move || -> Result<(), SnapshotError> {
+            let mut archive = tar::Archive::new(reader);
+            let mut checkpoint = Checkpoint::new(&journal.lock().unwrap());
+
+            for entry in archive.entries()? {
+                if cancel.is_cancelled() {
+                    return Err(SnapshotError::Cancelled);
+                }
+                let mut entry = entry?;
+
+                entry.unpack_in(&db_dir)?;
+
+                // The next entry, including its extension headers, starts after this entry's data
+                let size = entry.header().size()?;
+                let data_end = entry.raw_file_position()
+                    + entry.header().entry_size()?.div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE;
+                {
+                    let mut journal = journal.lock().unwrap();
+                    journal.resume_offset = base_offset + data_end;
+                    journal.entries_written += 1;
+                    checkpoint.entry_written(&journal, &journal_path, size)?;
+                }
+                progress.on_event(&ProgressEvent::ExtractEntryWritten {
+                    shard_id,
+                    path: entry.path()?.to_string_lossy().to_string(),
+                    size,
+                });
+            }
+
+            // Drain trailing padding so every chunk is consumed and cleaned up
+            io::copy(&mut archive.into_inner(), &mut io::sink())?;
+            Ok(())
+        }
+    })
+    .await
+    .map_err(|e| {
+        SnapshotError::IoError(std::io::Error::other(format!("Task join error: {}", e)))
+    })?;
+
+    let _ = producer.await;
+
+    // Record how far the restore got, whether it finished, failed or was cancelled
+    let persisted = journal.lock().unwrap().persist(&journal_path);
+
+    // The archive may look complete when the chunk downloads stopped at a boundary
+    if ctx.downloads.cancel.is_cancelled() {
+        info!(
+            "⏹️  Streaming restore of shard {} stopped, the journal records where to resume",
+            shard_id
+        );
+        return Err(SnapshotError::Cancelled);
+    }
+    if let Err(e) = unpack_result {
+        // Prefer the underlying download error over the wrapped I/O error
+        return Err(failure.lock().unwrap().take().unwrap_or(e));
+    }
+    persisted?;
+
+    let mut journal = journal.lock().unwrap();
+    journal.complete = true;
+    journal.persist(&journal_path)?;
+
+    info!(
+        "✅ Streamed shard {} ({} entries written)",
+        shard_id, journal.entries_written
+    );
+    progress.on_event(&ProgressEvent::StreamFinished {
+        shard_id,
+        entries_written: journal.entries_written,
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bandwidth::BandwidthLimiter;
+    use crate::concurrency::ConcurrencyController;
+    use crate::retry::RetryPolicy;
+    use crate::source::LocalSource;
+    use crate::state::ChunkStateStore;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::collections::{BTreeMap, HashMap};
+    use std::io::Write;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio_util::sync::CancellationToken;
+
+    /// Cancels the restore once a number of entries were written.
+    #[derive(Debug)]
+    struct CancelAfter {
+        entries: AtomicUsize,
+        cancel: CancellationToken,
+    }
+
+    impl ProgressObserver for CancelAfter {
+        fn on_event(&self, event: &ProgressEvent) {
+            if matches!(event, ProgressEvent::ExtractEntryWritten { .. })
+                && self.entries.fetch_sub(1, Ordering::SeqCst) == 1
+            {
+                self.cancel.cancel();
+            }
+        }
+    }
+
+    /// Builds a tar whose paths come from GNU long name and PAX extension headers
+    /// for the fifth and eighth entry.
+    fn build_tar() -> (Vec<u8>, BTreeMap<String, Vec<u8>>) {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut files = BTreeMap::new();
+        for i in 0..12u32 {
+            let data: Vec<u8> = (0..1000 + 313 * i)
+                .map(|n| (n % 251) as u8 ^ i as u8)
+                .collect();
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            let path = match i {
+                4 => {
+                    let path = format!("shard-0/{}.log", "gnu-long-name-".repeat(10));
+                    builder
+                        .append_data(&mut header, &path, data.as_slice())
+                        .unwrap();
+                    path
+                }
+                7 => {
+                    let path = format!("shard-0/{}.log", "pax-path-".repeat(15));
+                    builder
+                        .append_pax_extensions([("path", path.as_bytes())])
+                        .unwrap();
+                    header.set_path("shard-0/truncated.log").unwrap();
+                    header.set_cksum();
+                    builder.append(&header, data.as_slice()).unwrap();
+                    path
+                }
+                _ => {
+                    let path = format!("shard-0/{:06}.dat", i);
+                    builder
+                        .append_data(&mut header, &path, data.as_slice())
+                        .unwrap();
+                    path
+                }
+            };
+            files.insert(path, data);
+        }
+        (builder.into_inner().unwrap(), files)
+    }
+
+    fn read_tree(dir: &Path) -> BTreeMap<String, Vec<u8>> {
+        let mut files = BTreeMap::new();
+        for entry in std::fs::read_dir(dir.join("shard-0")).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy();
+            files.insert(format!("shard-0/{}", name), std::fs::read(&path).unwrap());
+        }
+        files
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_interrupted_stream_restore_resumes_identically() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tar, files) = build_tar();
+
+        // Split the tar at arbitrary offsets into separately compressed chunks
+        let mut chunks = Vec::new();
+        let snapshot = dir.path().join("source/net/0/snap");
+        std::fs::create_dir_all(&snapshot).unwrap();
+        for (i, piece) in tar.chunks(tar.len() / 3 + 1).enumerate() {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(piece).unwrap();
+            let chunk = format!("chunk_{:04}.bin", i);
+            std::fs::write(snapshot.join(&chunk), encoder.finish().unwrap()).unwrap();
+            chunks.push(chunk);
+        }
+        let metadata = SnapshotMetadata {
+            key_base: "net/0/snap".to_string(),
+            chunks,
+            timestamp: 1,
+            checksums: HashMap::new(),
+            pinned: false,
+        };
+        let snapshot_dir = dir.path().join("snap");
+        let snapshot_dir = snapshot_dir.to_str().unwrap();
+        let db_dir = dir.path().join("db");
+        let config = DownloadConfig::default();
+
+        let restore = |entries: usize| {
+            let cancel = CancellationToken::new();
+            let downloads = DownloadContext {
+                source: Arc::new(LocalSource::new(dir.path().join("source"))),
+                limiter: BandwidthLimiter::unlimited(),
+                concurrency: Arc::new(ConcurrencyController::new(2, None)),
+                retry: RetryPolicy::default(),
+                progress: Arc::new(CancelAfter {
+                    entries: AtomicUsize::new(entries),
+                    cancel: cancel.clone(),
+                }),
+                cancel,
+                state: Arc::new(ChunkStateStore::new(snapshot_dir, false)),
+                multipart_part_size: None,
+            };
+            let (config, metadata, db_dir) = (&config, &metadata, db_dir.to_str().unwrap());
+            async move {
+                stream_restore(StreamRestoreContext {
+                    config,
+                    metadata,
+                    snapshot_dir,
+                    db_dir,
+                    downloads: &downloads,
+                    shard_id: 0,
+                })
+                .await
+            }
+        };
+
+        // Stop right before the entry with a GNU long name, then right before the PAX entry
+        let journal_path = journal_path(snapshot_dir, 0);
+        for entries in [4, 3] {
+            assert!(matches!(
+                restore(entries).await,
+                Err(SnapshotError::Cancelled)
+            ));
+        }
+        let journal = StreamJournal::read(&journal_path, "net/0/snap").unwrap();
+        assert_eq!(journal.entries_written, 7);
+        assert!(!journal.complete);
+
+        restore(usize::MAX).await.unwrap();
+        let tree = read_tree(&db_dir);
+        assert_eq!(
+            tree.keys().collect::<Vec<_>>(),
+            files.keys().collect::<Vec<_>>()
+        );
+        assert!(tree == files, "restored files differ from the archive");
+        assert!(
+            StreamJournal::read(&journal_path, "net/0/snap")
+                .unwrap()
+                .complete
+        );
+        assert!(std::fs::read_dir(format!("{}/shard-0", snapshot_dir))
+            .unwrap()
+            .next()
+            .is_none());
+    }
+}
@@ -1,16 +1,217 @@
 //! Data structures for snapshot operations.
 
+use crate::bandwidth::BandwidthLimiter;
+use crate::concurrency::AdaptiveConcurrency;
+use crate::error::SnapshotError;
+use crate::progress::{NoProgress, ProgressObserver};
+use crate::retry::RetryPolicy;
+use crate::signature::TrustedKey;
+use crate::source::{source_from_url, MirrorSelection, MirrorSource, S3Config, SnapshotSource};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 
 /// Metadata for a snapshot, describing its location and chunks.
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub(crate) struct SnapshotMetadata {
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotMetadata {
     /// Base path for the snapshot in S3/R2 storage.
     pub key_base: String,
     /// List of chunk filenames to download.
     pub chunks: Vec<String>,
     /// Unix timestamp when the snapshot was created.
     pub timestamp: i64,
+    /// Sizes and digests of the chunks, keyed by chunk filename (optional).
+    ///
+    /// Older snapshots don't publish checksums; their chunks are verified
+    /// against the ETag reported by the storage provider.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub checksums: HashMap<String, ChunkChecksum>,
+    /// Set in the local `metadata.json` when the snapshot was chosen with a
+    /// [`SnapshotSelector`]; later runs keep restoring it instead of following
+    /// `latest.json` until another selector is given.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+}
+
+impl SnapshotMetadata {
+    /// Returns the published checksum of a chunk, if any.
+    pub fn checksum(&self, chunk: &str) -> Option<&ChunkChecksum> {
+        self.checksums.get(chunk)
+    }
+
+    /// Returns true if both describe the same published snapshot (same `key_base` and `timestamp`).
+    pub fn is_same_snapshot(&self, other: &SnapshotMetadata) -> bool {
+        self.key_base == other.key_base && self.timestamp == other.timestamp
+    }
+}
+
+/// Chooses which of the available snapshots of a shard to restore.
+///
+/// Parsed from strings as used by the `--snapshot` option:
+///
+/// * `latest` - [`SnapshotSelector::Latest`]
+/// * `before:<time>` - [`SnapshotSelector::Before`]
+/// * `at:<time>` or a bare `<time>` - [`SnapshotSelector::Timestamp`]
+/// * anything else - [`SnapshotSelector::KeyBase`]
+///
+/// where `<time>` is a Unix timestamp, an RFC 3339 date (`2025-01-31T00:00:00Z`)
+/// or a duration ago (`2d`, `36h`).
+///
+/// # Example
+///
+/// ```
+/// use snapsync::SnapshotSelector;
+///
+/// let selector: SnapshotSelector = "before:1700000000".parse().unwrap();
+/// assert_eq!(selector, SnapshotSelector::Before(1700000000));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SnapshotSelector {
+    /// The snapshot `latest.json` points to.
+    #[default]
+    Latest,
+    /// The snapshot with this `key_base`, or with this last path component of it.
+    KeyBase(String),
+    /// The snapshot created at exactly this Unix timestamp.
+    Timestamp(i64),
+    /// The newest snapshot created before this Unix timestamp.
+    Before(i64),
+}
+
+impl SnapshotSelector {
+    /// Picks a snapshot from `snapshots`, or `None` if none matches.
+    ///
+    /// [`SnapshotSelector::Latest`] picks the newest snapshot; callers that can
+    /// read `latest.json` use it instead.
+    pub fn select<'a>(&self, snapshots: &'a [SnapshotMetadata]) -> Option<&'a SnapshotMetadata> {
+        match self {
+            SnapshotSelector::Latest => snapshots.iter().max_by_key(|m| m.timestamp),
+            SnapshotSelector::KeyBase(key_base) => snapshots.iter().find(|m| {
+                m.key_base == *key_base || m.key_base.rsplit('/').next() == Some(key_base.as_str())
+            }),
+            SnapshotSelector::Timestamp(timestamp) => {
+                snapshots.iter().find(|m| m.timestamp == *timestamp)
+            }
+            SnapshotSelector::Before(before) => snapshots
+                .iter()
+                .filter(|m| m.timestamp < *before)
+                .max_by_key(|m| m.timestamp),
+        }
+    }
+}
+
+impl std::fmt::Display for SnapshotSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotSelector::Latest => write!(f, "the latest snapshot"),
+            SnapshotSelector::KeyBase(key_base) => write!(f, "key_base '{}'", key_base),
+            SnapshotSelector::Timestamp(timestamp) => write!(f, "timestamp {}", timestamp),
+            SnapshotSelector::Before(before) => write!(f, "the newest snapshot before {}", before),
+        }
+    }
+}
+
+impl std::str::FromStr for SnapshotSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("empty snapshot selector".to_string());
+        }
+        if s == "latest" {
+            return Ok(SnapshotSelector::Latest);
+        }
+        if let Some(time) = s.strip_prefix("before:") {
+            return parse_time(time).map(SnapshotSelector::Before);
+        }
+        if let Some(time) = s.strip_prefix("at:") {
+            return parse_time(time).map(SnapshotSelector::Timestamp);
+        }
+        match parse_time(s) {
+            Ok(timestamp) => Ok(SnapshotSelector::Timestamp(timestamp)),
+            Err(_) => Ok(SnapshotSelector::KeyBase(s.to_string())),
+        }
+    }
+}
+
+/// Parses a Unix timestamp, an RFC 3339 date or a duration ago into a Unix timestamp.
+fn parse_time(value: &str) -> Result<i64, String> {
+    let value = value.trim();
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return Ok(timestamp);
+    }
+    let since_epoch = |time: std::time::SystemTime| {
+        time.duration_since(std::time::UNIX_EPOCH)
+            .map(|age| age.as_secs() as i64)
+            .unwrap_or(0)
+    };
+    if let Ok(time) = humantime::parse_rfc3339_weak(value) {
+        return Ok(since_epoch(time));
+    }
+    match humantime::parse_duration(value) {
+        Ok(ago) => Ok(since_epoch(std::time::SystemTime::now()) - ago.as_secs() as i64),
+        Err(_) => Err(format!(
+            "invalid time '{}' (expected a Unix timestamp, an RFC 3339 date or a duration like 2d)",
+            value
+        )),
+    }
+}
+
+/// What to do when `latest.json` points to a different snapshot than the one
+/// a previous, unfinished run was restoring.
+///
+/// Chunks of different snapshots share file names, so they must never be mixed
+/// in one shard directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SnapshotChangePolicy {
+    /// Keep restoring the previous snapshot and pin it in `metadata.json`.
+    Resume,
+    /// Discard the local files of the previous snapshot and restore the new one.
+    #[default]
+    Restart,
+    /// Stop with [`SnapshotError::SnapshotChanged`](crate::SnapshotError::SnapshotChanged).
+    Fail,
+}
+
+/// Size and digests of a chunk, published in the snapshot metadata.
+///
+/// When a digest is present it is verified instead of the ETag, which is only
+/// an MD5 for simple uploads and is not a security boundary.
+///
+/// # Example
+///
+/// ```json
+/// {"size": 1048576, "sha256": "9f86d0...", "blake3": "4878ca..."}
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChunkChecksum {
+    /// Size of the chunk in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Hex-encoded SHA-256 digest of the chunk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Hex-encoded BLAKE3 digest of the chunk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+}
+
+impl ChunkChecksum {
+    /// Returns true if at least one digest is published.
+    pub fn has_digest(&self) -> bool {
+        self.sha256.is_some() || self.blake3.is_some()
+    }
+
+    /// Returns a published digest labelled with its algorithm, e.g. `sha256:<hex>`.
+    pub(crate) fn digest(&self) -> Option<String> {
+        match (&self.sha256, &self.blake3) {
+            (Some(sha256), _) => Some(format!("sha256:{}", sha256)),
+            (None, Some(blake3)) => Some(format!("blake3:{}", blake3)),
+            (None, None) => None,
+        }
+    }
 }
 
 /// Configuration for downloading snapshots.
@@ -26,12 +227,22 @@ pub(crate) struct SnapshotMetadata {
 ///     network: "FARCASTER_NETWORK_MAINNET".to_string(),
 ///     max_concurrent_downloads: 8,
 ///     skip_verify: false,
+///     ..Default::default()
 /// };
 /// ```
 #[derive(Debug, Clone)]
 pub struct DownloadConfig {
-    /// Base URL for snapshot downloads (e.g., `<https://pub-xxx.r2.dev>`)
+    /// Base URL for snapshot downloads (e.g., `<https://pub-xxx.r2.dev>`).
+    ///
+    /// Also accepts `s3://bucket/prefix`, `file:///path` or a plain local path.
     pub snapshot_download_url: String,
+    /// Additional mirror base URLs, tried after `snapshot_download_url` (default: none).
+    ///
+    /// Accepts the same schemes as `snapshot_download_url`. Chunks are only read
+    /// from mirrors whose metadata matches the primary snapshot.
+    pub snapshot_mirror_urls: Vec<String>,
+    /// Order in which mirrors are tried (default: [`MirrorSelection::Priority`]).
+    pub mirror_selection: MirrorSelection,
     /// Temporary directory for downloads (e.g., `".rocks.snapshot"`)
     pub snapshot_download_dir: String,
     /// Network name (e.g., `"FARCASTER_NETWORK_MAINNET"`, `"FARCASTER_NETWORK_TESTNET"`)
@@ -42,12 +253,110 @@ pub struct DownloadConfig {
     /// even low-core CPUs can handle 8-16 concurrent downloads efficiently.
     /// The limiting factor is network bandwidth, not CPU.
     pub max_concurrent_downloads: usize,
+    /// Adapt the number of concurrent downloads within these bounds (default: disabled).
+    ///
+    /// When set, `max_concurrent_downloads` is only the starting level; it grows
+    /// while throughput improves and shrinks when the source throttles or fails.
+    pub adaptive_concurrency: Option<AdaptiveConcurrency>,
     /// Skip all verification, trust existing files completely (default: false).
     ///
     /// When enabled, existing files are assumed to be valid without any checks
     /// (no size check, no MD5 check). This is extremely fast but should only be
     /// used when you completely trust the local files (e.g., re-running after interruption).
     pub skip_verify: bool,
+    /// Ignore the recorded download state and fully re-verify existing chunks (default: false).
+    ///
+    /// Normally a chunk that is unchanged (same size and modification time) since
+    /// it was last verified is trusted without a HEAD request or MD5 computation.
+    pub reverify: bool,
+    /// Part size the snapshot's multipart uploads were made with (default: inferred).
+    ///
+    /// Multipart ETags are the MD5 of the part MD5s, so verifying them needs the
+    /// part size. When unset, common client part sizes that fit the ETag's part
+    /// count are tried; a chunk no candidate matches is only size-checked.
+    pub multipart_part_size: Option<u64>,
+    /// Keys trusted to sign snapshot metadata (default: none, unsigned metadata is accepted).
+    ///
+    /// When non-empty, `latest.json` must come with a detached ed25519 signature
+    /// in `latest.json.sig` made by one of these keys, or the download is refused.
+    pub trusted_keys: Vec<TrustedKey>,
+    /// Which snapshot to restore (default: the pinned snapshot, if any, otherwise the latest).
+    ///
+    /// A selector other than [`SnapshotSelector::Latest`] is resolved against the
+    /// snapshots listed by [`list_snapshots`](crate::list_snapshots), and the choice
+    /// is pinned in the local `metadata.json` so that resuming later restores the
+    /// same snapshot. `Some(SnapshotSelector::Latest)` removes the pin.
+    pub snapshot: Option<SnapshotSelector>,
+    /// What to do when `latest.json` moved on to another snapshot since the
+    /// previous run (default: [`SnapshotChangePolicy::Restart`]).
+    ///
+    /// Only applies while following `latest.json`; when a new snapshot is
+    /// selected explicitly the files of the previous one are always discarded.
+    pub on_snapshot_change: SnapshotChangePolicy,
+    /// Delete a shard's database directory when its snapshot changes (default: false).
+    ///
+    /// Extraction keeps existing files of the right size, so a new snapshot must not
+    /// be restored on top of a previous one. Without this option the restore stops with
+    /// [`SnapshotError::StaleDatabase`](crate::SnapshotError::StaleDatabase) instead,
+    /// unless the directory only holds an unfinished streaming restore. Download-only
+    /// runs never touch the database directory.
+    pub clear_db_on_snapshot_change: bool,
+    /// Limit on the combined throughput of all chunk downloads (default: unlimited).
+    ///
+    /// Keep a clone of the limiter to change the rate while downloads are running.
+    pub bandwidth_limiter: BandwidthLimiter,
+    /// How failed chunk downloads are retried (default: [`RetryPolicy::default`]).
+    pub retry_policy: RetryPolicy,
+    /// Approximate ceiling in bytes for decompressed data buffered while merging (default: 256 MiB).
+    ///
+    /// Chunks are decompressed in parallel and streamed to the tar file in fixed-size
+    /// blocks, so memory usage is bounded by this value rather than by chunk size.
+    pub merge_memory_limit: usize,
+    /// Settings for `s3://` snapshot URLs.
+    pub s3: S3Config,
+    /// Custom snapshot source, used instead of the one derived from `snapshot_download_url`.
+    ///
+    /// Useful for embedding snapsync with a backend of your own or for offline tests.
+    pub source: Option<Arc<dyn SnapshotSource>>,
+    /// Receiver for progress events (default: none).
+    ///
+    /// The library draws no progress bars; the `snapsync` CLI renders these events
+    /// with indicatif.
+    pub progress: Option<Arc<dyn ProgressObserver>>,
+    /// Stops the restore when cancelled (default: a token that is never cancelled).
+    ///
+    /// No new work is started after cancellation. In-flight chunk downloads stop
+    /// and keep their partial files for resuming, merges and extractions stop at
+    /// the next chunk or entry boundary, and [`download_snapshots`](crate::download_snapshots)
+    /// returns [`SnapshotError::Cancelled`]. A later run resumes where this one stopped.
+    pub cancel: CancellationToken,
+}
+
+impl DownloadConfig {
+    /// Returns the source snapshots are read from.
+    pub(crate) fn snapshot_source(&self) -> Result<Arc<dyn SnapshotSource>, SnapshotError> {
+        match self.source {
+            Some(ref source) => Ok(Arc::clone(source)),
+            None if self.snapshot_mirror_urls.is_empty() => {
+                source_from_url(&self.snapshot_download_url, &self.s3)
+            }
+            None => {
+                let mirrors = std::iter::once(&self.snapshot_download_url)
+                    .chain(&self.snapshot_mirror_urls)
+                    .map(|url| source_from_url(url, &self.s3))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Arc::new(MirrorSource::new(mirrors, self.mirror_selection)))
+            }
+        }
+    }
+
+    /// Returns the observer progress events are sent to.
+    pub(crate) fn progress_observer(&self) -> Arc<dyn ProgressObserver> {
+        match self.progress {
+            Some(ref progress) => Arc::clone(progress),
+            None => Arc::new(NoProgress),
+        }
+    }
 }
 
 impl Default for DownloadConfig {
@@ -55,10 +364,26 @@ impl Default for DownloadConfig {
         Self {
             snapshot_download_url: "https://pub-d352dd8819104a778e20d08888c5a661.r2.dev"
                 .to_string(),
+            snapshot_mirror_urls: Vec::new(),
+            mirror_selection: MirrorSelection::Priority,
             snapshot_download_dir: ".rocks.snapshot".to_string(),
             network: "FARCASTER_NETWORK_MAINNET".to_string(),
             max_concurrent_downloads: 4,
+            adaptive_concurrency: None,
             skip_verify: false,
+            reverify: false,
+            multipart_part_size: None,
+            trusted_keys: Vec::new(),
+            snapshot: None,
+            on_snapshot_change: SnapshotChangePolicy::default(),
+            clear_db_on_snapshot_change: false,
+            bandwidth_limiter: BandwidthLimiter::unlimited(),
+            retry_policy: RetryPolicy::default(),
+            merge_memory_limit: 256 * 1024 * 1024,
+            s3: S3Config::default(),
+            source: None,
+            progress: None,
+            cancel: CancellationToken::new(),
         }
     }
 }
@@ -74,4 +399,6 @@ pub enum ExecutionStage {
     MergeOnly,
     /// Only extract tar to directory
     ExtractOnly,
+    /// Download, decompress and unpack in a single pass without an intermediate tar
+    Stream,
 }
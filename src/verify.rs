@@ -1,22 +1,21 @@
-//! File verification utilities (MD5 checksums and size checks).
+//! File verification utilities (published digests, MD5 checksums and size checks).
 
+use crate::download::{partial_etag_path, partial_path};
 use crate::error::SnapshotError;
+use crate::source::SnapshotSource;
+use crate::types::ChunkChecksum;
 use tracing::{info, warn};
 
-/// Computes the MD5 hash of a local file.
+/// Feeds the contents of a local file to a hasher.
 ///
 /// This function reads the file in chunks to avoid loading large files
 /// entirely into memory. It runs in a blocking task to avoid blocking
 /// the async runtime.
-///
-/// # Arguments
-///
-/// * `filename` - Path to the file
-///
-/// # Returns
-///
-/// The MD5 hash as a hexadecimal string, or an error.
-pub(crate) async fn compute_file_md5(filename: &str) -> Result<String, SnapshotError> {
+async fn hash_file<H: Send + 'static>(
+    filename: &str,
+    mut hasher: H,
+    update: fn(&mut H, &[u8]),
+) -> Result<H, SnapshotError> {
     let filename = filename.to_string();
 
     tokio::task::spawn_blocking(move || {
@@ -24,9 +23,6 @@ pub(crate) async fn compute_file_md5(filename: &str) -> Result<String, SnapshotE
 
         let file = std::fs::File::open(&filename).map_err(SnapshotError::IoError)?;
         let mut reader = std::io::BufReader::with_capacity(1024 * 1024, file);
-        use md5::{Digest, Md5};
-
-        let mut hasher = Md5::new();
         let mut buffer = vec![0u8; 1024 * 1024];
 
         loop {
@@ -34,41 +30,434 @@ pub(crate) async fn compute_file_md5(filename: &str) -> Result<String, SnapshotE
             if n == 0 {
                 break;
             }
-            hasher.update(&buffer[..n]);
+            update(&mut hasher, &buffer[..n]);
         }
 
-        let digest = hasher.finalize();
-        Ok(format!("{:x}", digest))
+        Ok(hasher)
     })
     .await
     .map_err(|e| SnapshotError::IoError(std::io::Error::other(format!("Task join error: {}", e))))?
 }
 
+/// Computes the MD5 hash state of a local file.
+///
+/// The returned hasher can be updated further, which is used to continue
+/// hashing when resuming a partial download.
+///
+/// # Arguments
+///
+/// * `filename` - Path to the file
+///
+/// # Returns
+///
+/// The MD5 hasher after consuming the whole file, or an error.
+pub(crate) async fn md5_hasher_for_file(filename: &str) -> Result<md5::Md5, SnapshotError> {
+    use md5::Digest;
+
+    hash_file(filename, md5::Md5::new(), |hasher, data| {
+        hasher.update(data)
+    })
+    .await
+}
+
+/// Incremental computation of the digests published for a chunk.
+pub(crate) struct ChecksumHasher {
+    sha256: Option<sha2::Sha256>,
+    blake3: Option<Box<blake3::Hasher>>,
+}
+
+impl ChecksumHasher {
+    /// Returns a hasher for the digests in `expected`, or `None` if none are published.
+    pub(crate) fn new(expected: &ChunkChecksum) -> Option<Self> {
+        use sha2::Digest;
+
+        expected.has_digest().then(|| Self {
+            sha256: expected.sha256.as_ref().map(|_| sha2::Sha256::new()),
+            blake3: expected.blake3.as_ref().map(|_| Box::default()),
+        })
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        use sha2::Digest;
+
+        if let Some(ref mut sha256) = self.sha256 {
+            sha256.update(data);
+        }
+        if let Some(ref mut blake3) = self.blake3 {
+            blake3.update(data);
+        }
+    }
+
+    /// Compares the digests with the published ones.
+    ///
+    /// # Returns
+    ///
+    /// The first mismatching digest as `(expected, actual)`, both prefixed with
+    /// the algorithm name, or `None` if all digests match.
+    pub(crate) fn mismatch(self, expected: &ChunkChecksum) -> Option<(String, String)> {
+        use sha2::Digest;
+
+        let sha256 = self.sha256.map(|hasher| hex::encode(hasher.finalize()));
+        let blake3 = self
+            .blake3
+            .map(|hasher| hasher.finalize().to_hex().to_string());
+        [
+            ("sha256", expected.sha256.as_deref(), sha256),
+            ("blake3", expected.blake3.as_deref(), blake3),
+        ]
+        .into_iter()
+        .find_map(|(algorithm, expected, actual)| match (expected, actual) {
+            (Some(expected), Some(actual)) if !expected.eq_ignore_ascii_case(&actual) => Some((
+                format!("{}:{}", algorithm, expected),
+                format!("{}:{}", algorithm, actual),
+            )),
+            _ => None,
+        })
+    }
+}
+
+/// Computes the published digests of a local file.
+///
+/// Like [`md5_hasher_for_file`], the returned hasher can be updated further
+/// when resuming a partial download.
+///
+/// # Arguments
+///
+/// * `filename` - Path to the file
+/// * `expected` - Published checksum naming the digests to compute
+///
+/// # Returns
+///
+/// The hasher after consuming the whole file, `None` if no digest is published, or an error.
+pub(crate) async fn checksum_hasher_for_file(
+    filename: &str,
+    expected: &ChunkChecksum,
+) -> Result<Option<ChecksumHasher>, SnapshotError> {
+    match ChecksumHasher::new(expected) {
+        Some(hasher) => Ok(Some(
+            hash_file(filename, hasher, ChecksumHasher::update).await?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Computes the MD5 hash of a local file.
+///
+/// # Arguments
+///
+/// * `filename` - Path to the file
+///
+/// # Returns
+///
+/// The MD5 hash as a hexadecimal string, or an error.
+pub(crate) async fn compute_file_md5(filename: &str) -> Result<String, SnapshotError> {
+    use md5::Digest;
+
+    let digest = md5_hasher_for_file(filename).await?.finalize();
+    Ok(format!("{:x}", digest))
+}
+
+/// Part sizes commonly used by S3 clients, tried when the part size of a
+/// multipart upload isn't configured.
+const COMMON_PART_SIZES: &[u64] = &[
+    5 << 20,
+    8 << 20,
+    10 << 20,
+    15 << 20,
+    16 << 20,
+    25 << 20,
+    32 << 20,
+    50 << 20,
+    64 << 20,
+    100 << 20,
+    128 << 20,
+    256 << 20,
+    512 << 20,
+    1 << 30,
+    5_000_000,
+    8_000_000,
+    10_000_000,
+    16_000_000,
+    100_000_000,
+];
+
+/// Returns the number of parts encoded in a multipart ETag (`<md5>-<parts>`).
+pub(crate) fn multipart_part_count(etag: &str) -> Option<u64> {
+    let (md5, parts) = etag.split_once('-')?;
+    if md5.len() != 32 || !md5.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    parts.parse().ok().filter(|&parts| parts > 0)
+}
+
+/// Returns the part sizes that could have produced `parts` parts for an object of `size` bytes.
+///
+/// The configured part size is the only candidate when set. Otherwise the common
+/// client defaults are tried, together with the smallest whole-MiB part size that
+/// fits the object in `parts` parts.
+fn candidate_part_sizes(size: u64, parts: u64, configured: Option<u64>) -> Vec<u64> {
+    if configured.is_some() {
+        return configured
+            .filter(|&part_size| part_size > 0)
+            .into_iter()
+            .collect();
+    }
+    let smallest_mib = size.div_ceil(parts).div_ceil(1 << 20) << 20;
+    let mut candidates: Vec<u64> = COMMON_PART_SIZES
+        .iter()
+        .copied()
+        .chain(std::iter::once(smallest_mib))
+        .filter(|&part_size| part_size > 0 && size.div_ceil(part_size) == parts)
+        .collect();
+    candidates.sort_unstable();
+    candidates.dedup();
+    candidates
+}
+
+/// Multipart ETag computation for one candidate part size.
+struct MultipartHasher {
+    part_size: u64,
+    part: md5::Md5,
+    part_len: u64,
+    part_digests: Vec<u8>,
+    parts: u64,
+}
+
+impl MultipartHasher {
+    fn new(part_size: u64) -> Self {
+        use md5::Digest;
+
+        Self {
+            part_size,
+            part: md5::Md5::new(),
+            part_len: 0,
+            part_digests: Vec::new(),
+            parts: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        use md5::Digest;
+
+        while !data.is_empty() {
+            let take = data.len().min((self.part_size - self.part_len) as usize);
+            self.part.update(&data[..take]);
+            self.part_len += take as u64;
+            data = &data[take..];
+            if self.part_len == self.part_size {
+                self.finish_part();
+            }
+        }
+    }
+
+    fn finish_part(&mut self) {
+        use md5::Digest;
+
+        let part = std::mem::replace(&mut self.part, md5::Md5::new());
+        self.part_digests.extend_from_slice(&part.finalize());
+        self.part_len = 0;
+        self.parts += 1;
+    }
+
+    fn finalize(mut self) -> String {
+        use md5::Digest;
+
+        if self.part_len > 0 || self.parts == 0 {
+            self.finish_part();
+        }
+        format!("{:x}-{}", md5::Md5::digest(&self.part_digests), self.parts)
+    }
+}
+
+/// Result of checking a file against a multipart ETag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum MultipartCheck {
+    /// The ETag computed with this part size matches.
+    Verified {
+        /// Part size the object was uploaded with.
+        part_size: u64,
+    },
+    /// The ETag computed with the configured part size differs.
+    Mismatch {
+        /// ETag computed from the local file.
+        actual: String,
+    },
+    /// No candidate part size reproduces the ETag, so the file can't be checked.
+    UnknownPartSize,
+}
+
+/// Checks a local file against the ETag of a multipart upload.
+///
+/// S3 computes such ETags as the MD5 of the concatenated part MD5s, followed by
+/// `-<parts>`. The part size isn't recorded, so the file is hashed once for every
+/// plausible part size (see [`candidate_part_sizes`]) in a single pass.
+///
+/// # Arguments
+///
+/// * `filename` - Path to the local file
+/// * `etag` - Multipart ETag of the remote object
+/// * `configured_part_size` - Part size the snapshot was uploaded with, if known
+///
+/// # Returns
+///
+/// Whether the file matches. A mismatch is only reported for a configured part
+/// size; with inferred part sizes a mismatch can't be told apart from an
+/// unusual part size and is reported as [`MultipartCheck::UnknownPartSize`].
+pub(crate) async fn verify_multipart_etag(
+    filename: &str,
+    etag: &str,
+    configured_part_size: Option<u64>,
+) -> Result<MultipartCheck, SnapshotError> {
+    let Some(parts) = multipart_part_count(etag) else {
+        return Ok(MultipartCheck::UnknownPartSize);
+    };
+    let size = tokio::fs::metadata(filename).await?.len();
+    let candidates = candidate_part_sizes(size, parts, configured_part_size);
+    if candidates.is_empty() {
+        return Ok(MultipartCheck::UnknownPartSize);
+    }
+
+    let hashers: Vec<_> = candidates.into_iter().map(MultipartHasher::new).collect();
+    let computed: Vec<_> = hash_file(filename, hashers, |hashers, data| {
+        for hasher in hashers {
+            hasher.update(data);
+        }
+    })
+    .await?
+    .into_iter()
+    .map(|hasher| (hasher.part_size, hasher.finalize()))
+    .collect();
+
+    if let Some(&(part_size, _)) = computed.iter().find(|(_, actual)| actual == etag) {
+        return Ok(MultipartCheck::Verified { part_size });
+    }
+    Ok(match (configured_part_size, computed.into_iter().next()) {
+        (Some(_), Some((_, actual))) => MultipartCheck::Mismatch { actual },
+        _ => MultipartCheck::UnknownPartSize,
+    })
+}
+
+/// Outcome of checking a local file against the remote object.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct LocalFileStatus {
+    /// The local file is complete and doesn't need to be downloaded.
+    pub verified: bool,
+    /// Size of the object in bytes, if it was looked up (the local size for trusted files).
+    pub size: Option<u64>,
+    /// ETag of the remote object, if the file was checked against it.
+    pub etag: Option<String>,
+    /// MD5 of the local file, if it was computed.
+    pub md5: Option<String>,
+    /// Published digest the local file matched, labelled with its algorithm.
+    pub digest: Option<String>,
+}
+
+impl LocalFileStatus {
+    fn verified(size: u64, etag: Option<&str>, md5: Option<String>) -> Self {
+        Self {
+            verified: true,
+            size: Some(size),
+            etag: etag.map(str::to_string),
+            md5,
+            digest: None,
+        }
+    }
+
+    /// Returns true if the file was checked against a hash, not just its size.
+    pub(crate) fn integrity_confirmed(&self) -> bool {
+        self.verified && (self.etag.is_some() || self.md5.is_some() || self.digest.is_some())
+    }
+
+    fn needs_download(size: Option<u64>) -> Self {
+        Self {
+            size,
+            ..Default::default()
+        }
+    }
+}
+
+/// Verifies a local file against the size and digests published in the metadata.
+async fn verify_published_checksum(
+    filename: &str,
+    local_size: u64,
+    checksum: &ChunkChecksum,
+) -> LocalFileStatus {
+    let file_display_name = std::path::Path::new(filename)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(filename);
+
+    if let Some(expected_size) = checksum.size.filter(|&size| size != local_size) {
+        info!(
+            "Size mismatch for {}: local={} bytes, published={} bytes",
+            filename, local_size, expected_size
+        );
+        return LocalFileStatus::needs_download(Some(expected_size));
+    }
+
+    match checksum_hasher_for_file(filename, checksum).await {
+        Ok(Some(hasher)) => match hasher.mismatch(checksum) {
+            None => {
+                info!(
+                    "✅ File {} verified (published checksum match)",
+                    file_display_name
+                );
+                LocalFileStatus {
+                    digest: checksum.digest(),
+                    ..LocalFileStatus::verified(local_size, None, None)
+                }
+            }
+            Some((expected, actual)) => {
+                info!(
+                    "❌ Checksum mismatch for {}: local={}, published={}",
+                    file_display_name, actual, expected
+                );
+                LocalFileStatus::needs_download(Some(local_size))
+            }
+        },
+        Ok(None) => LocalFileStatus::needs_download(checksum.size),
+        Err(e) => {
+            warn!(
+                "⚠️  Failed to compute checksum for {}: {}",
+                file_display_name, e
+            );
+            LocalFileStatus::needs_download(Some(local_size))
+        }
+    }
+}
+
 /// Verifies if a local file matches the remote file.
 ///
 /// This function performs the following checks:
 /// 1. Checks if the local file exists
-/// 2. If `skip_verify` is true, immediately returns Ok(true)
-/// 3. Sends a HEAD request to get remote file size and ETag
-/// 4. Compares file sizes
-/// 5. If ETag is available, computes local MD5 and compares
+/// 2. If `skip_verify` is true, the file is trusted as is
+/// 3. If the metadata publishes digests for the chunk, checks the size and digests
+///    and skips the remaining steps
+/// 4. Stats the remote object to get its size and ETag
+/// 5. Compares file sizes (a smaller local file is kept as a partial download to resume)
+/// 6. If ETag is available, computes local MD5 (or the multipart ETag) and compares
 ///
 /// # Arguments
 ///
 /// * `filename` - Path to the local file
-/// * `remote_url` - URL of the remote file
+/// * `source` - Snapshot source holding the remote file
+/// * `key` - Key of the remote file within the source
 /// * `skip_verify` - If true, skip all verification
+/// * `multipart_part_size` - Part size of multipart uploads, inferred when `None`
+/// * `checksum` - Size and digests published in the metadata, if any
 ///
 /// # Returns
 ///
-/// `Ok(true)` if the file is valid and doesn't need re-downloading,
-/// `Ok(false)` if the file needs to be downloaded,
-/// `Err` on verification errors.
+/// A status telling whether the file is valid and doesn't need re-downloading,
+/// along with the object size when it is known, or `Err` on verification errors.
 pub(crate) async fn verify_local_file(
     filename: &str,
-    remote_url: &str,
+    source: &dyn SnapshotSource,
+    key: &str,
     skip_verify: bool,
-) -> Result<bool, SnapshotError> {
+    multipart_part_size: Option<u64>,
+    checksum: Option<&ChunkChecksum>,
+) -> Result<LocalFileStatus, SnapshotError> {
     let file_display_name = std::path::Path::new(filename)
         .file_name()
         .and_then(|n| n.to_str())
@@ -77,7 +466,7 @@ pub(crate) async fn verify_local_file(
     // Check if local file exists
     let local_metadata = match tokio::fs::metadata(filename).await {
         Ok(m) => m,
-        Err(_) => return Ok(false), // File doesn't exist, need to download
+        Err(_) => return Ok(LocalFileStatus::default()), // File doesn't exist, need to download
     };
 
     // If skip_verify is enabled, trust the file completely without any checks
@@ -87,31 +476,30 @@ pub(crate) async fn verify_local_file(
             file_display_name,
             local_metadata.len()
         );
-        return Ok(true);
+        return Ok(LocalFileStatus {
+            verified: true,
+            size: Some(local_metadata.len()),
+            ..Default::default()
+        });
     }
 
-    // Send HEAD request to get remote file info
-    let client = reqwest::Client::new();
-    let response = match client.head(remote_url).send().await {
-        Ok(r) => match r.error_for_status() {
-            Ok(resp) => resp,
-            Err(e) => {
-                warn!("HEAD request failed for {}: {}", remote_url, e);
-                return Ok(false);
-            }
-        },
+    // Published digests take precedence over the ETag and need no request
+    if let Some(checksum) = checksum.filter(|checksum| checksum.has_digest()) {
+        return Ok(verify_published_checksum(filename, local_metadata.len(), checksum).await);
+    }
+
+    // Stat the remote object to get its size and ETag
+    let info = match source.stat(key).await {
+        Ok(info) => info,
         Err(e) => {
-            warn!("Failed to connect to {}: {}", remote_url, e);
-            return Ok(false);
+            warn!("Failed to stat {}: {}", source.describe(key), e);
+            return Ok(LocalFileStatus::default());
         }
     };
+    let remote_size = info.size;
 
-    // Get remote file size
-    let remote_size = response
-        .headers()
-        .get("content-length")
-        .and_then(|v| v.to_str().ok())
-        .and_then(|s| s.parse::<u64>().ok());
+    // Get ETag (which is MD5 for simple uploads in S3/R2)
+    let etag = info.etag.as_deref();
 
     // Compare file sizes first (quick check)
     if let Some(remote_size) = remote_size {
@@ -122,29 +510,59 @@ pub(crate) async fn verify_local_file(
                 local_metadata.len(),
                 remote_size
             );
-            return Ok(false);
+            // A truncated file is a prefix of the remote object, keep it for a ranged resume.
+            // The end-to-end MD5 check catches the case where it was not.
+            if let Some(etag_val) = etag.filter(|_| local_metadata.len() < remote_size) {
+                if tokio::fs::rename(filename, partial_path(filename))
+                    .await
+                    .is_ok()
+                {
+                    tokio::fs::write(partial_etag_path(filename), etag_val).await?;
+                }
+            }
+            return Ok(LocalFileStatus::needs_download(Some(remote_size)));
         }
     } else {
         // Can't verify size, assume need to re-download
-        return Ok(false);
+        return Ok(LocalFileStatus::default());
     }
-
-    // Get ETag (which is MD5 for simple uploads in S3/R2)
-    let etag = response
-        .headers()
-        .get("etag")
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.trim_matches('"'));
+    let size = local_metadata.len();
 
     // Verify MD5 if ETag is available
     if let Some(etag_val) = etag {
-        // Skip multipart uploads (they have "-" in ETag)
+        // Multipart uploads (with "-" in the ETag) have an ETag derived from the part MD5s
         if etag_val.contains('-') {
-            info!(
-                "✅ File {} verified (size match, multipart upload)",
-                file_display_name
-            );
-            return Ok(true);
+            return match verify_multipart_etag(filename, etag_val, multipart_part_size).await {
+                Ok(MultipartCheck::Verified { part_size }) => {
+                    info!(
+                        "✅ File {} verified (multipart ETag match, {} byte parts)",
+                        file_display_name, part_size
+                    );
+                    Ok(LocalFileStatus::verified(size, etag, None))
+                }
+                Ok(MultipartCheck::Mismatch { actual }) => {
+                    info!(
+                        "❌ Multipart ETag mismatch for {}: local={}, remote={}",
+                        file_display_name, actual, etag_val
+                    );
+                    Ok(LocalFileStatus::needs_download(Some(size)))
+                }
+                Ok(MultipartCheck::UnknownPartSize) => {
+                    warn!(
+                        "⚠️  File {} only size-checked (unknown multipart part size, set multipart_part_size to verify it)",
+                        file_display_name
+                    );
+                    // The ETag was not checked, so it must not vouch for the file
+                    Ok(LocalFileStatus::verified(size, None, None))
+                }
+                Err(e) => {
+                    warn!(
+                        "⚠️  Failed to compute multipart ETag for {}: {}",
+                        file_display_name, e
+                    );
+                    Ok(LocalFileStatus::needs_download(Some(size)))
+                }
+            };
         }
 
         // Compute local file MD5
@@ -152,18 +570,18 @@ pub(crate) async fn verify_local_file(
             Ok(local_md5) => {
                 if local_md5 == etag_val {
                     info!("✅ File {} verified (MD5 match)", file_display_name);
-                    return Ok(true);
+                    return Ok(LocalFileStatus::verified(size, etag, Some(local_md5)));
                 } else {
                     info!(
                         "❌ MD5 mismatch for {}: local={}, remote={}",
                         file_display_name, local_md5, etag_val
                     );
-                    return Ok(false);
+                    return Ok(LocalFileStatus::needs_download(Some(size)));
                 }
             }
             Err(e) => {
                 warn!("⚠️  Failed to compute MD5 for {}: {}", file_display_name, e);
-                return Ok(false);
+                return Ok(LocalFileStatus::needs_download(Some(size)));
             }
         }
     }
@@ -171,8 +589,46 @@ pub(crate) async fn verify_local_file(
     // No ETag available, but size matches - assume valid
     info!(
         "✅ File {} verified (size match, {} bytes, no ETag)",
-        file_display_name,
-        local_metadata.len()
+        file_display_name, size
     );
-    Ok(true)
+    Ok(LocalFileStatus::verified(size, None, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_multipart_etag_part_size_inference() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("chunk_0000.bin");
+        let filename = filename.to_str().unwrap();
+        let data: Vec<u8> = (0..(12u32 << 20)).map(|i| (i % 251) as u8).collect();
+        std::fs::write(filename, &data).unwrap();
+
+        // Two 8 MiB parts, the default of most S3 clients
+        let mut hasher = MultipartHasher::new(8 << 20);
+        hasher.update(&data);
+        let etag = hasher.finalize();
+        assert!(etag.ends_with("-2"));
+
+        assert_eq!(
+            verify_multipart_etag(filename, &etag, None).await.unwrap(),
+            MultipartCheck::Verified { part_size: 8 << 20 }
+        );
+        assert!(matches!(
+            verify_multipart_etag(filename, &etag, Some(10 << 20))
+                .await
+                .unwrap(),
+            MultipartCheck::Mismatch { .. }
+        ));
+
+        let corrupt = format!("{}-2", "0".repeat(32));
+        assert_eq!(
+            verify_multipart_etag(filename, &corrupt, None)
+                .await
+                .unwrap(),
+            MultipartCheck::UnknownPartSize
+        );
+    }
 }